@@ -1,24 +1,131 @@
 // programs/nft-marketplace/src/lib.rs
 use anchor_lang::prelude::*;
-use anchor_lang::solana_program::borsh::try_from_slice_unchecked;
-use anchor_spl::token::{Mint, Token, TokenAccount, MintTo};
+use anchor_spl::token::{CloseAccount, Mint, Token, TokenAccount, MintTo};
 use anchor_spl::associated_token::AssociatedToken;
+use anchor_lang::solana_program::sysvar::instructions as sysvar_instructions;
 use mpl_token_metadata::{
     instructions::{
         CreateMasterEditionV3,
         CreateMasterEditionV3InstructionArgs,
         CreateMetadataAccountV3,
         CreateMetadataAccountV3InstructionArgs,
+        CreateV1,
+        CreateV1InstructionArgs,
+        DelegateStakingV1,
+        DelegateStakingV1InstructionArgs,
+        LockV1,
+        LockV1InstructionArgs,
+        MintV1,
+        MintV1InstructionArgs,
+        RevokeStakingV1,
+        SetCollectionSize,
+        SetCollectionSizeInstructionArgs,
+        UnlockV1,
+        UnlockV1InstructionArgs,
         VerifyCollection,
+        VerifyCollectionV1,
+        VerifySizedCollectionItem,
     },
-    types::{Collection, Creator, DataV2},
+    types::{Collection, CollectionDetails, Creator, DataV2, SetCollectionSizeArgs, TokenStandard},
     accounts::Metadata as TokenMetadata,
 };
 
 declare_id!("ptcbSp1UEqYLmod2jgFxGPZnFMqBECcrRyU1fTmnJ5b");
 
+// Metaplex Token Auth Rules program (enforces pNFT royalty rule sets)
+pub const TOKEN_AUTH_RULES_PROGRAM_ID: Pubkey = pubkey!("auth9SigNpDKz4sJJ1DfCTuZrZNSAgh9sFD3rboVmgg");
+
+// Collection and type names are stored in full and seeded by their SHA-256 hash,
+// so they are not bound by the 32-byte PDA seed limit
+pub const MAX_COLLECTION_NAME_LEN: usize = 64;
+pub const MAX_TYPE_NAME_LEN: usize = 64;
+
+// Bounds on how long a full room may run before anyone can time it out
+pub const MIN_ROOM_TIMEOUT_SECS: i64 = 60;
+pub const MAX_ROOM_TIMEOUT_SECS: i64 = 7 * 24 * 60 * 60;
+
+// How long after a dispute window closes the admin has to arbitrate a disputed room;
+// after it anyone can pay out the referee's result
+pub const ARBITRATION_WINDOW_SECS: i64 = 7 * 24 * 60 * 60;
+
+// Elo rating for player profiles
+pub const INITIAL_RATING: u32 = 1200;
+pub const ELO_K_FACTOR: i64 = 32;
+// Expected score in per-mille against an opponent rated 0, 25, 50, ... 800 points higher
+const ELO_EXPECTED_PER_MILLE: [i64; 33] = [
+    500, 464, 429, 394, 360, 327, 297, 267, 240, 215, 192, 170, 151, 133, 118, 104, 91,
+    80, 70, 61, 53, 46, 40, 35, 31, 27, 23, 20, 17, 15, 13, 11, 10,
+];
+
+// Seat limits for multi-player rooms
+pub const MIN_ROOM_SEATS: u8 = 2;
+pub const MAX_ROOM_SEATS: u8 = 16;
+
+// Longest best-of-N series a room can play
+pub const MAX_SERIES_GAMES: u8 = 9;
+
+// Battle rooms: stat limits and the length of a simulated fight
+pub const MAX_BATTLE_STAT: u16 = 1_000;
+pub const MAX_RARITY: u8 = 4;
+pub const BATTLE_BASE_HP: u32 = 100;
+pub const MAX_BATTLE_ROUNDS: u32 = 20;
+
+// Seasons: points per settled room and the largest prize leaderboard
+pub const SEASON_WIN_POINTS: u64 = 3;
+pub const SEASON_DRAW_POINTS: u64 = 1;
+pub const MAX_SEASON_PLACES: usize = 16;
+
+// Tournament brackets: entrants, a power of two
+pub const MIN_TOURNAMENT_SIZE: u8 = 2;
+pub const MAX_TOURNAMENT_SIZE: u8 = 32;
+
+// Session keys: what a delegated key may do, and how long it may live
+pub const SESSION_SCOPE_COMMIT: u8 = 1 << 0;
+pub const SESSION_SCOPE_REVEAL: u8 = 1 << 1;
+pub const SESSION_SCOPE_FORFEIT: u8 = 1 << 2;
+pub const SESSION_SCOPE_ALL: u8 = SESSION_SCOPE_COMMIT | SESSION_SCOPE_REVEAL | SESSION_SCOPE_FORFEIT;
+pub const MAX_SESSION_SECS: i64 = 24 * 60 * 60;
+
+// Builds the RoomSettleAccounts for `settle_room` from an instruction's accounts; every
+// instruction that settles a room carries the same settle accounts as ResolveRoom
+macro_rules! room_settle_accounts {
+    ($accounts:expr) => {
+        RoomSettleAccounts {
+            creator: $accounts.creator.to_account_info(),
+            challenger: $accounts.challenger.to_account_info(),
+            stake: RoomStakeAccounts {
+                vault: $accounts.room_vault.as_deref(),
+                creator_token: $accounts.creator_stake_token.as_deref(),
+                challenger_token: $accounts.challenger_stake_token.as_deref(),
+                token_program: $accounts.token_program.as_ref(),
+            },
+            treasury: RoomTreasuryAccounts {
+                treasury: $accounts.treasury.as_ref().map(|treasury| treasury.as_ref()),
+                treasury_token: $accounts.treasury_stake_token.as_deref(),
+                season: $accounts.season.as_deref(),
+            },
+            nfts: RoomNftAccounts {
+                creator_lock: &$accounts.creator_nft_lock,
+                challenger_lock: &$accounts.challenger_nft_lock,
+                creator_escrow: $accounts.creator_nft_escrow.as_deref(),
+                challenger_escrow: $accounts.challenger_nft_escrow.as_deref(),
+                creator_nft_destination: $accounts.creator_nft_destination.as_deref(),
+                challenger_nft_destination: $accounts.challenger_nft_destination.as_deref(),
+            },
+        }
+    };
+}
+
+// Anchor 0.31 emits its IDL instruction handlers next to the program module, and they call
+// the deprecated `AccountInfo::realloc`. This wrapper only exists to allow that in the
+// generated code; the program module turns the warning back on for its own handlers.
+#[allow(deprecated)]
+mod program_root {
+use super::*;
+
 #[program]
 pub mod nft_marketplace {
+    #![warn(deprecated)]
     use super::*;
 
     pub fn initialize_marketplace(ctx: Context<InitializeMarketplace>, fee_bps: u16) -> Result<()> {
@@ -27,11 +134,188 @@ pub mod nft_marketplace {
         marketplace.fee_bps = fee_bps;
         marketplace.total_collections = 0;
         marketplace.bump = ctx.bumps.marketplace;
+        marketplace.creation_policy = CreationPolicy::AdminOnly as u8;
+        marketplace.listing_fee_lamports = 0;
+        marketplace.treasury = ctx.accounts.admin.key();
+        marketplace.referee = ctx.accounts.admin.key();
         
         msg!("Marketplace initialized with admin: {}", marketplace.admin);
         Ok(())
     }
 
+    // Grow a marketplace account created with an older layout to the current size.
+    // New fields read as zero: admin-only creation, no listing fee, unset treasury and referee,
+    // no room rake.
+    pub fn migrate_marketplace(ctx: Context<MigrateMarketplace>) -> Result<()> {
+        let marketplace_info = ctx.accounts.marketplace.to_account_info();
+        {
+            let data = marketplace_info.try_borrow_data()?;
+            require!(
+                data.len() >= 8 + 32 && &data[..8] == Marketplace::DISCRIMINATOR,
+                ErrorCode::Unauthorized
+            );
+            let admin = Pubkey::try_from(&data[8..40]).map_err(|_| ErrorCode::Unauthorized)?;
+            require!(admin == ctx.accounts.admin.key(), ErrorCode::Unauthorized);
+        }
+
+        let new_len = Marketplace::space();
+        if marketplace_info.data_len() < new_len {
+            let rent_exempt = Rent::get()?.minimum_balance(new_len);
+            let top_up = rent_exempt.saturating_sub(marketplace_info.lamports());
+            if top_up > 0 {
+                let ix = anchor_lang::solana_program::system_instruction::transfer(
+                    &ctx.accounts.admin.key(),
+                    &marketplace_info.key(),
+                    top_up,
+                );
+                anchor_lang::solana_program::program::invoke(
+                    &ix,
+                    &[
+                        ctx.accounts.admin.to_account_info(),
+                        marketplace_info.clone(),
+                    ],
+                )?;
+            }
+            marketplace_info.resize(new_len)?;
+        }
+
+        msg!("Marketplace migrated to {} bytes", new_len);
+        Ok(())
+    }
+
+    // Admin: choose who may create collections under this marketplace
+    pub fn set_creation_policy(
+        ctx: Context<UpdateMarketplaceConfig>,
+        creation_policy: u8,
+        listing_fee_lamports: u64,
+        treasury: Pubkey,
+    ) -> Result<()> {
+        require!(creation_policy <= CreationPolicy::Permissionless as u8, ErrorCode::InvalidCreationPolicy);
+
+        let marketplace = &mut ctx.accounts.marketplace;
+        marketplace.creation_policy = creation_policy;
+        marketplace.listing_fee_lamports = listing_fee_lamports;
+        marketplace.treasury = treasury;
+
+        msg!(
+            "Creation policy set to {} (listing fee {} lamports)",
+            creation_policy,
+            listing_fee_lamports
+        );
+        Ok(())
+    }
+
+    // Admin: set the default referee that resolves matchmaking rooms
+    pub fn set_referee(ctx: Context<UpdateMarketplaceConfig>, referee: Pubkey) -> Result<()> {
+        let marketplace = &mut ctx.accounts.marketplace;
+        marketplace.referee = referee;
+
+        msg!("Room referee set to {}", referee);
+        Ok(())
+    }
+
+    // Admin: configure the rake taken from room pots into the treasury.
+    // `room_rake_cap` is the most taken from a single lamport room; 0 = uncapped.
+    // Rooms staked in an SPL token are charged `room_rake_bps` without a cap.
+    pub fn set_room_rake(
+        ctx: Context<UpdateMarketplaceConfig>,
+        room_rake_bps: u16,
+        room_rake_cap: u64,
+    ) -> Result<()> {
+        require!(room_rake_bps <= 10_000, ErrorCode::InvalidRakeBps);
+        let marketplace = &mut ctx.accounts.marketplace;
+        require!(
+            room_rake_bps == 0 || marketplace.treasury != Pubkey::default(),
+            ErrorCode::MissingTreasury
+        );
+        marketplace.room_rake_bps = room_rake_bps;
+        marketplace.room_rake_cap = room_rake_cap;
+
+        msg!("Room rake set to {} bps (cap {})", room_rake_bps, room_rake_cap);
+        Ok(())
+    }
+
+    // Admin: set the house fee taken from spectator bet pools into the treasury
+    pub fn set_bet_fee(ctx: Context<UpdateMarketplaceConfig>, bet_fee_bps: u16) -> Result<()> {
+        require!(bet_fee_bps <= 10_000, ErrorCode::InvalidRakeBps);
+        let marketplace = &mut ctx.accounts.marketplace;
+        require!(
+            bet_fee_bps == 0 || marketplace.treasury != Pubkey::default(),
+            ErrorCode::MissingTreasury
+        );
+        marketplace.bet_fee_bps = bet_fee_bps;
+
+        msg!("Bet fee set to {} bps", bet_fee_bps);
+        Ok(())
+    }
+
+    // Admin: set how long players can dispute a referee's result, and the bond they post.
+    // A zero window pays referee results out immediately.
+    pub fn set_dispute_terms(
+        ctx: Context<UpdateMarketplaceConfig>,
+        dispute_window_secs: i64,
+        dispute_bond_lamports: u64,
+    ) -> Result<()> {
+        require!(
+            (0..=MAX_ROOM_TIMEOUT_SECS).contains(&dispute_window_secs),
+            ErrorCode::InvalidRoomTimeout
+        );
+        let marketplace = &mut ctx.accounts.marketplace;
+        marketplace.dispute_window_secs = dispute_window_secs;
+        marketplace.dispute_bond_lamports = dispute_bond_lamports;
+
+        msg!(
+            "Dispute window set to {}s with a {} lamport bond",
+            dispute_window_secs,
+            dispute_bond_lamports
+        );
+        Ok(())
+    }
+
+    // Admin: exempt rooms created by a wallet from the room rake
+    pub fn add_rake_exemption(ctx: Context<AddRakeExemption>, wallet: Pubkey) -> Result<()> {
+        let rake_exemption = &mut ctx.accounts.rake_exemption;
+        rake_exemption.marketplace = ctx.accounts.marketplace.key();
+        rake_exemption.wallet = wallet;
+        rake_exemption.bump = ctx.bumps.rake_exemption;
+
+        msg!("Rake exemption added: {}", wallet);
+        Ok(())
+    }
+
+    // Admin: remove a wallet's rake exemption
+    pub fn remove_rake_exemption(ctx: Context<RemoveRakeExemption>) -> Result<()> {
+        msg!("Rake exemption removed: {}", ctx.accounts.rake_exemption.wallet);
+        Ok(())
+    }
+
+    // Admin: allow a wallet to create collections under the Allowlisted policy
+    pub fn add_allowed_creator(ctx: Context<AddAllowedCreator>, creator: Pubkey) -> Result<()> {
+        let allowed_creator = &mut ctx.accounts.allowed_creator;
+        allowed_creator.marketplace = ctx.accounts.marketplace.key();
+        allowed_creator.creator = creator;
+        allowed_creator.bump = ctx.bumps.allowed_creator;
+
+        msg!("Creator allowlisted: {}", creator);
+        Ok(())
+    }
+
+    // Admin: remove a wallet from the creator allowlist
+    pub fn remove_allowed_creator(ctx: Context<RemoveAllowedCreator>) -> Result<()> {
+        msg!("Creator removed from allowlist: {}", ctx.accounts.allowed_creator.creator);
+        Ok(())
+    }
+
+    // Admin: mark a collection as verified (curated) by the marketplace
+    pub fn set_collection_verified(ctx: Context<SetCollectionVerified>, verified: bool) -> Result<()> {
+        let collection = &mut ctx.accounts.collection;
+        collection.verified = verified;
+
+        msg!("Collection {} verified: {}", collection.name, verified);
+        Ok(())
+    }
+
+    #[allow(clippy::too_many_arguments)]
     pub fn create_nft_type(
         ctx: Context<CreateNFTType>,
         type_name: String,
@@ -39,12 +323,18 @@ pub mod nft_marketplace {
         price: u64,
         max_supply: u64,
         stake_multiplier: u64,
+        programmable: bool,
+        rule_set: Option<Pubkey>,
     ) -> Result<()> {
         let collection = &ctx.accounts.collection;
         let nft_type = &mut ctx.accounts.nft_type;
 
         require!(collection.is_active, ErrorCode::CollectionInactive);
+        validate_name(&type_name, MAX_TYPE_NAME_LEN)?;
+        require!(uri.len() <= mpl_token_metadata::MAX_URI_LENGTH, ErrorCode::UriTooLong);
         require!(stake_multiplier > 0, ErrorCode::InvalidStakeMultiplier);
+        // A rule set only means something for programmable NFTs
+        require!(programmable || rule_set.is_none(), ErrorCode::NotProgrammable);
 
         nft_type.collection = collection.key();
         nft_type.name = type_name;
@@ -53,19 +343,93 @@ pub mod nft_marketplace {
         nft_type.max_supply = max_supply;
         nft_type.current_supply = 0;
         nft_type.stake_multiplier = stake_multiplier;
+        nft_type.programmable = programmable;
+        nft_type.rule_set = rule_set;
         nft_type.bump = ctx.bumps.nft_type;
+        nft_type.hashed_seed = true;
+        nft_type.battle_stats = BattleStats::default();
+
+        let type_index = &mut ctx.accounts.type_index;
+        type_index.collection = collection.key();
+        type_index.nft_type = nft_type.key();
+        type_index.index = collection.total_types;
+        type_index.bump = ctx.bumps.type_index;
+        ctx.accounts.type_indexed.set(type_index.index, ctx.bumps.type_indexed);
+
+        let collection = &mut ctx.accounts.collection;
+        collection.total_types += 1;
 
         msg!("NFT type created under collection: {}", collection.name);
         Ok(())
     }
 
+    // Collection admin: set the stats a type fights with in battle rooms
+    pub fn set_battle_stats(ctx: Context<SetBattleStats>, stats: BattleStats) -> Result<()> {
+        require!(
+            stats.attack <= MAX_BATTLE_STAT
+                && stats.defense <= MAX_BATTLE_STAT
+                && stats.speed <= MAX_BATTLE_STAT
+                && stats.rarity <= MAX_RARITY,
+            ErrorCode::InvalidBattleStats
+        );
+        let nft_type = &mut ctx.accounts.nft_type;
+        nft_type.battle_stats = stats;
+
+        msg!(
+            "Battle stats for {}: atk {} def {} spd {} rarity {}",
+            nft_type.name,
+            stats.attack,
+            stats.defense,
+            stats.speed,
+            stats.rarity
+        );
+        Ok(())
+    }
+
     pub fn create_nft_collection(
         ctx: Context<CreateNFTCollection>,
         collection_name: String,
         symbol: String,
         uri: String,
         royalty: u16,
+        creators: Vec<CollectionCreator>,
     ) -> Result<()> {
+        validate_name(&collection_name, MAX_COLLECTION_NAME_LEN)?;
+        require!(symbol.len() <= mpl_token_metadata::MAX_SYMBOL_LENGTH, ErrorCode::SymbolTooLong);
+        require!(uri.len() <= mpl_token_metadata::MAX_URI_LENGTH, ErrorCode::UriTooLong);
+        validate_creators(&creators)?;
+
+        // Enforce the marketplace's collection creation policy
+        let creator_key = ctx.accounts.admin.key();
+        let marketplace_admin = ctx.accounts.marketplace.admin;
+        let policy = ctx.accounts.marketplace.creation_policy;
+        if policy == CreationPolicy::AdminOnly as u8 {
+            require!(creator_key == marketplace_admin, ErrorCode::Unauthorized);
+        } else if policy == CreationPolicy::Allowlisted as u8 {
+            require!(
+                creator_key == marketplace_admin || ctx.accounts.allowed_creator.is_some(),
+                ErrorCode::CreatorNotAllowlisted
+            );
+        } else {
+            let listing_fee = ctx.accounts.marketplace.listing_fee_lamports;
+            if creator_key != marketplace_admin && listing_fee > 0 {
+                let treasury = ctx.accounts.treasury.as_ref().ok_or(ErrorCode::MissingTreasury)?;
+                let fee_ix = anchor_lang::solana_program::system_instruction::transfer(
+                    &creator_key,
+                    &treasury.key(),
+                    listing_fee,
+                );
+                anchor_lang::solana_program::program::invoke(
+                    &fee_ix,
+                    &[
+                        ctx.accounts.admin.to_account_info(),
+                        treasury.to_account_info(),
+                    ],
+                )?;
+                msg!("Listing fee paid: {} lamports", listing_fee);
+            }
+        }
+
         let collection = &mut ctx.accounts.collection;
         let marketplace = &mut ctx.accounts.marketplace;
         
@@ -77,6 +441,18 @@ pub mod nft_marketplace {
         collection.mint = ctx.accounts.collection_mint.key();
         collection.is_active = true;
         collection.bump = ctx.bumps.collection;
+        collection.is_sized = true;
+        collection.creators = creators;
+        collection.total_types = 0;
+        collection.hashed_seed = true;
+        collection.verified = false;
+
+        let collection_index = &mut ctx.accounts.collection_index;
+        collection_index.marketplace = marketplace.key();
+        collection_index.collection = collection.key();
+        collection_index.index = marketplace.total_collections;
+        collection_index.bump = ctx.bumps.collection_index;
+        ctx.accounts.collection_indexed.set(collection_index.index, ctx.bumps.collection_indexed);
 
         // Mint 1 token to admin - required for master edition
         let cpi_accounts = MintTo {
@@ -92,15 +468,11 @@ pub mod nft_marketplace {
 
         // Create collection metadata
         let metadata_data = DataV2 {
-            name: collection_name.clone(),
+            name: truncate_name(&collection_name, mpl_token_metadata::MAX_NAME_LENGTH).to_string(),
             symbol: symbol.clone(),
             uri: uri.clone(),
             seller_fee_basis_points: royalty,
-            creators: Some(vec![Creator {
-                address: ctx.accounts.admin.key(),
-                verified: true,
-                share: 100,
-            }]),
+            creators: Some(collection.metadata_creators()),
             collection: None,
             uses: None,
         };
@@ -116,7 +488,8 @@ pub mod nft_marketplace {
         }.instruction(CreateMetadataAccountV3InstructionArgs {
             data: metadata_data,
             is_mutable: true,
-            collection_details: None,
+            // Sized collection: Token Metadata keeps the item count on-chain
+            collection_details: Some(CollectionDetails::V1 { size: 0 }),
         });
 
         let metadata_accounts = vec![
@@ -168,29 +541,160 @@ pub mod nft_marketplace {
         Ok(())
     }
 
-    pub fn mint_nft_from_collection(
-        ctx: Context<MintNFTFromCollection>,
+    // Grow a collection created before the fields after `bump` were added to the current
+    // layout. Collections too short to have ever held those fields get them zeroed, so they
+    // read as unsized, without creator splits, unindexed, seeded by the raw name and unverified.
+    pub fn migrate_collection(ctx: Context<MigrateCollection>) -> Result<()> {
+        let collection = load_migrated_collection(
+            &ctx.accounts.collection.to_account_info(),
+            &ctx.accounts.admin.to_account_info(),
+            &ctx.accounts.system_program.to_account_info(),
+        )?;
+        require!(collection.admin == ctx.accounts.admin.key(), ErrorCode::Unauthorized);
+
+        msg!("Collection {} migrated", collection.name);
+        Ok(())
+    }
+
+    // Grow an NFT type created before the fields after `bump` were added to the current
+    // layout. Types too short to have ever held those fields get them zeroed, so they read
+    // as standard NFTs without a rule set or battle stats, seeded by the raw name.
+    pub fn migrate_nft_type(ctx: Context<MigrateNftType>) -> Result<()> {
+        let nft_type = load_migrated_nft_type(
+            &ctx.accounts.nft_type.to_account_info(),
+            &ctx.accounts.collection.key(),
+            &ctx.accounts.admin.to_account_info(),
+            &ctx.accounts.system_program.to_account_info(),
+        )?;
+
+        msg!("NFT type {} migrated", nft_type.name);
+        Ok(())
+    }
+
+    // Migrate a collection created before sized collections to a sized one.
+    // `size` must be the number of items already verified into the collection.
+    // Collections in an older account layout are migrated first.
+    pub fn migrate_collection_to_sized(ctx: Context<MigrateCollectionToSized>, size: u64) -> Result<()> {
+        let collection_info = ctx.accounts.collection.to_account_info();
+        let mut collection = load_migrated_collection(
+            &collection_info,
+            &ctx.accounts.admin.to_account_info(),
+            &ctx.accounts.system_program.to_account_info(),
+        )?;
+        require!(collection.admin == ctx.accounts.admin.key(), ErrorCode::Unauthorized);
+        require_keys_eq!(ctx.accounts.collection_mint.key(), collection.mint, ErrorCode::InvalidNFTMint);
+        require!(!collection.is_sized, ErrorCode::CollectionAlreadySized);
+
+        let set_size_ix = SetCollectionSize {
+            collection_metadata: ctx.accounts.collection_metadata.key(),
+            collection_authority: ctx.accounts.admin.key(),
+            collection_mint: ctx.accounts.collection_mint.key(),
+            collection_authority_record: None,
+        }.instruction(SetCollectionSizeInstructionArgs {
+            set_collection_size_args: SetCollectionSizeArgs { size },
+        });
+
+        let set_size_accounts = vec![
+            ctx.accounts.collection_metadata.to_account_info(),
+            ctx.accounts.admin.to_account_info(),
+            ctx.accounts.collection_mint.to_account_info(),
+            ctx.accounts.token_metadata_program.to_account_info(),
+        ];
+
+        anchor_lang::solana_program::program::invoke(&set_size_ix, &set_size_accounts)?;
+
+        collection.is_sized = true;
+        collection.try_serialize(&mut &mut collection_info.try_borrow_mut_data()?[..])?;
+
+        msg!("Collection {} migrated to sized collection with size {}", collection.name, size);
+        Ok(())
+    }
+
+    // Registry: index a collection created before collection indexes existed.
+    // `index` must be a slot below total_collections that no other collection uses, and
+    // each collection is indexed once. Collections in an older account layout are migrated first.
+    pub fn index_existing_collection(ctx: Context<IndexExistingCollection>, index: u64) -> Result<()> {
+        let collection = load_migrated_collection(
+            &ctx.accounts.collection.to_account_info(),
+            &ctx.accounts.admin.to_account_info(),
+            &ctx.accounts.system_program.to_account_info(),
+        )?;
+        let marketplace = &ctx.accounts.marketplace;
+        require!(index < marketplace.total_collections, ErrorCode::InvalidRegistryIndex);
+
+        let collection_index = &mut ctx.accounts.collection_index;
+        collection_index.marketplace = marketplace.key();
+        collection_index.collection = ctx.accounts.collection.key();
+        collection_index.index = index;
+        collection_index.bump = ctx.bumps.collection_index;
+        ctx.accounts.collection_indexed.set(index, ctx.bumps.collection_indexed);
+
+        msg!("Collection {} indexed at {}", collection.name, index);
+        Ok(())
+    }
+
+    // Registry: index an NFT type created before type indexes existed. Each type is
+    // indexed once; a repeat fails on its existing `type_indexed` entry.
+    pub fn index_existing_nft_type(ctx: Context<IndexExistingNftType>) -> Result<()> {
+        let collection = &mut ctx.accounts.collection;
+
+        let type_index = &mut ctx.accounts.type_index;
+        type_index.collection = collection.key();
+        type_index.nft_type = ctx.accounts.nft_type.key();
+        type_index.index = collection.total_types;
+        type_index.bump = ctx.bumps.type_index;
+        ctx.accounts.type_indexed.set(type_index.index, ctx.bumps.type_indexed);
+
+        collection.total_types += 1;
+
+        msg!("NFT type {} indexed at {}", ctx.accounts.nft_type.name, type_index.index);
+        Ok(())
+    }
+
+    // Record the type of an item minted before item types were recorded at mint time.
+    // The item must be a verified item of the collection whose metadata name is one this
+    // type has minted, e.g. "Hero #12" for the 12th Hero.
+    pub fn backfill_item_type(ctx: Context<BackfillItemType>) -> Result<()> {
+        let nft_type = &ctx.accounts.nft_type;
+        let metadata_info = ctx.accounts.nft_metadata.to_account_info();
+        verify_collection_item(&metadata_info, &ctx.accounts.nft_mint.key(), &ctx.accounts.collection.mint)?;
+
+        let metadata: TokenMetadata = TokenMetadata::from_bytes(&metadata_info.data.borrow())?;
+        require_keys_eq!(metadata.update_authority, ctx.accounts.collection.admin, ErrorCode::InvalidMetadata);
+        let number = item_number(&metadata.name, &nft_type.name).ok_or(ErrorCode::NftTypeMismatch)?;
+        require!(number >= 1 && number <= nft_type.current_supply, ErrorCode::NftTypeMismatch);
+
+        let item_type = &mut ctx.accounts.item_type;
+        item_type.nft_type = nft_type.key();
+        item_type.bump = ctx.bumps.item_type;
+
+        msg!("Item {} recorded as {} #{}", ctx.accounts.nft_mint.key(), nft_type.name, number);
+        Ok(())
+    }
+
+    pub fn mint_nft_from_collection<'info>(
+        ctx: Context<'_, '_, 'info, 'info, MintNFTFromCollection<'info>>,
         type_name: String,
     ) -> Result<()> {
         let collection = &mut ctx.accounts.collection;
         let nft_type = &mut ctx.accounts.nft_type;
         
         require!(collection.is_active, ErrorCode::CollectionInactive);
+        require!(!nft_type.programmable, ErrorCode::ProgrammableNftType);
         require!(nft_type.current_supply < nft_type.max_supply, ErrorCode::CollectionSoldOut);
 
-        // Transfer payment to collection admin
-        let transfer_ix = anchor_lang::solana_program::system_instruction::transfer(
-            &ctx.accounts.buyer.key(),
-            &collection.admin,
+        // Split payment across creator wallets (remaining accounts, in collection.creators order).
+        // Collections without creator splits pay the collection admin.
+        let creator_accounts = if ctx.remaining_accounts.is_empty() {
+            vec![ctx.accounts.collection_admin.to_account_info()]
+        } else {
+            ctx.remaining_accounts.to_vec()
+        };
+        pay_creators(
+            &ctx.accounts.buyer.to_account_info(),
+            &creator_accounts,
+            &collection.creator_splits(),
             nft_type.price,
-        );
-
-        anchor_lang::solana_program::program::invoke(
-            &transfer_ix,
-            &[
-                ctx.accounts.buyer.to_account_info(),
-                ctx.accounts.collection_admin.to_account_info(),
-            ],
         )?;
 
         // Mint NFT to buyer
@@ -200,10 +704,10 @@ pub mod nft_marketplace {
             authority: ctx.accounts.collection_admin.to_account_info(),
         };
 
-        let collection_name = collection.name.as_bytes();
+        let collection_name_seed = collection.name_seed();
         let seeds = &[
             b"collection",
-            collection_name,
+            collection_name_seed.as_slice(),
             &[collection.bump],
         ];
         let signer = &[&seeds[..]];
@@ -214,17 +718,13 @@ pub mod nft_marketplace {
         anchor_spl::token::mint_to(cpi_ctx, 1)?;
 
         // Create NFT metadata (fixed per type)
-        let nft_name = format!("{} #{}", type_name, nft_type.current_supply + 1);
+        let nft_name = item_name(&type_name, nft_type.current_supply + 1);
         let metadata_data = DataV2 {
             name: nft_name,
             symbol: collection.symbol.clone(),
             uri: nft_type.uri.clone(),
             seller_fee_basis_points: collection.royalty,
-            creators: Some(vec![Creator {
-                address: collection.admin,
-                verified: true,
-                share: 100,
-            }]),
+            creators: Some(collection.metadata_creators()),
             collection: Some(Collection {
                 verified: false,
                 key: collection.mint,
@@ -258,17 +758,30 @@ pub mod nft_marketplace {
 
         anchor_lang::solana_program::program::invoke(&create_nft_metadata_ix, &nft_metadata_accounts)?;
 
-        // Verify collection (unsized) after metadata creation
-        let verify_collection_ix = VerifyCollection {
-            metadata: ctx.accounts.nft_metadata.key(),
-            collection_authority: ctx.accounts.collection_admin.key(),
-            payer: ctx.accounts.buyer.key(),
-            collection_mint: ctx.accounts.collection_mint_account.key(),
-            collection: ctx.accounts.collection_metadata.key(),
-            collection_master_edition_account: ctx.accounts.collection_master_edition.key(),
-            collection_authority_record: None,
-        }
-        .instruction();
+        // Verify collection after metadata creation (sized collections also bump the on-chain size)
+        let verify_collection_ix = if collection.is_sized {
+            VerifySizedCollectionItem {
+                metadata: ctx.accounts.nft_metadata.key(),
+                collection_authority: ctx.accounts.collection_admin.key(),
+                payer: ctx.accounts.buyer.key(),
+                collection_mint: ctx.accounts.collection_mint_account.key(),
+                collection: ctx.accounts.collection_metadata.key(),
+                collection_master_edition_account: ctx.accounts.collection_master_edition.key(),
+                collection_authority_record: None,
+            }
+            .instruction()
+        } else {
+            VerifyCollection {
+                metadata: ctx.accounts.nft_metadata.key(),
+                collection_authority: ctx.accounts.collection_admin.key(),
+                payer: ctx.accounts.buyer.key(),
+                collection_mint: ctx.accounts.collection_mint_account.key(),
+                collection: ctx.accounts.collection_metadata.key(),
+                collection_master_edition_account: ctx.accounts.collection_master_edition.key(),
+                collection_authority_record: None,
+            }
+            .instruction()
+        };
 
         let verify_accounts = vec![
             ctx.accounts.nft_metadata.to_account_info(),
@@ -282,6 +795,8 @@ pub mod nft_marketplace {
         anchor_lang::solana_program::program::invoke(&verify_collection_ix, &verify_accounts)?;
 
         nft_type.current_supply += 1;
+        ctx.accounts.item_type.nft_type = nft_type.key();
+        ctx.accounts.item_type.bump = ctx.bumps.item_type;
         
         msg!(
             "NFT minted: {} - {} (type #{}/{})",
@@ -293,628 +808,5035 @@ pub mod nft_marketplace {
         Ok(())
     }
 
-	// Matchmaking: Create a room with an initial stake
+    // Mint a programmable NFT (pNFT) so royalties are enforced by the type's rule set
+    pub fn mint_programmable_nft_from_collection<'info>(
+        ctx: Context<'_, '_, 'info, 'info, MintProgrammableNFTFromCollection<'info>>,
+        type_name: String,
+    ) -> Result<()> {
+        let collection = &ctx.accounts.collection;
+        let nft_type = &mut ctx.accounts.nft_type;
+
+        require!(collection.is_active, ErrorCode::CollectionInactive);
+        require!(nft_type.programmable, ErrorCode::NotProgrammable);
+        require!(nft_type.current_supply < nft_type.max_supply, ErrorCode::CollectionSoldOut);
+        require!(
+            ctx.accounts.authorization_rules.as_ref().map(|rules| rules.key()) == nft_type.rule_set,
+            ErrorCode::InvalidRuleSet
+        );
+
+        // Split payment across creator wallets (remaining accounts, in collection.creators order).
+        // Collections without creator splits pay the collection admin.
+        let creator_accounts = if ctx.remaining_accounts.is_empty() {
+            vec![ctx.accounts.collection_admin.to_account_info()]
+        } else {
+            ctx.remaining_accounts.to_vec()
+        };
+        pay_creators(
+            &ctx.accounts.buyer.to_account_info(),
+            &creator_accounts,
+            &collection.creator_splits(),
+            nft_type.price,
+        )?;
+
+        // Create metadata, master edition and mint in one go (mint becomes frozen by the edition)
+        let nft_name = item_name(&type_name, nft_type.current_supply + 1);
+        let create_ix = CreateV1 {
+            metadata: ctx.accounts.nft_metadata.key(),
+            master_edition: Some(ctx.accounts.nft_master_edition.key()),
+            mint: (ctx.accounts.nft_mint.key(), true),
+            authority: ctx.accounts.collection_admin.key(),
+            payer: ctx.accounts.buyer.key(),
+            update_authority: (ctx.accounts.collection_admin.key(), true),
+            system_program: ctx.accounts.system_program.key(),
+            sysvar_instructions: ctx.accounts.sysvar_instructions.key(),
+            spl_token_program: Some(ctx.accounts.token_program.key()),
+        }.instruction(CreateV1InstructionArgs {
+            name: nft_name,
+            symbol: collection.symbol.clone(),
+            uri: nft_type.uri.clone(),
+            seller_fee_basis_points: collection.royalty,
+            creators: Some(collection.metadata_creators()),
+            primary_sale_happened: false,
+            is_mutable: false,
+            token_standard: TokenStandard::ProgrammableNonFungible,
+            collection: Some(Collection {
+                verified: false,
+                key: collection.mint,
+            }),
+            uses: None,
+            collection_details: None,
+            rule_set: nft_type.rule_set,
+            decimals: Some(0),
+            print_supply: None,
+        });
+
+        let create_accounts = vec![
+            ctx.accounts.nft_metadata.to_account_info(),
+            ctx.accounts.nft_master_edition.to_account_info(),
+            ctx.accounts.nft_mint.to_account_info(),
+            ctx.accounts.collection_admin.to_account_info(),
+            ctx.accounts.buyer.to_account_info(),
+            ctx.accounts.system_program.to_account_info(),
+            ctx.accounts.sysvar_instructions.to_account_info(),
+            ctx.accounts.token_program.to_account_info(),
+        ];
+
+        anchor_lang::solana_program::program::invoke(&create_ix, &create_accounts)?;
+
+        // Mint the single token to the buyer's ATA (creates the ATA and token record)
+        let mint_ix = MintV1 {
+            token: ctx.accounts.buyer_token_account.key(),
+            token_owner: Some(ctx.accounts.buyer.key()),
+            metadata: ctx.accounts.nft_metadata.key(),
+            master_edition: Some(ctx.accounts.nft_master_edition.key()),
+            token_record: Some(ctx.accounts.token_record.key()),
+            mint: ctx.accounts.nft_mint.key(),
+            authority: ctx.accounts.collection_admin.key(),
+            delegate_record: None,
+            payer: ctx.accounts.buyer.key(),
+            system_program: ctx.accounts.system_program.key(),
+            sysvar_instructions: ctx.accounts.sysvar_instructions.key(),
+            spl_token_program: ctx.accounts.token_program.key(),
+            spl_ata_program: ctx.accounts.associated_token_program.key(),
+            authorization_rules_program: ctx.accounts.authorization_rules_program.as_ref().map(|p| p.key()),
+            authorization_rules: ctx.accounts.authorization_rules.as_ref().map(|r| r.key()),
+        }.instruction(MintV1InstructionArgs {
+            amount: 1,
+            authorization_data: None,
+        });
+
+        let mut mint_accounts = vec![
+            ctx.accounts.buyer_token_account.to_account_info(),
+            ctx.accounts.buyer.to_account_info(),
+            ctx.accounts.nft_metadata.to_account_info(),
+            ctx.accounts.nft_master_edition.to_account_info(),
+            ctx.accounts.token_record.to_account_info(),
+            ctx.accounts.nft_mint.to_account_info(),
+            ctx.accounts.collection_admin.to_account_info(),
+            ctx.accounts.system_program.to_account_info(),
+            ctx.accounts.sysvar_instructions.to_account_info(),
+            ctx.accounts.token_program.to_account_info(),
+            ctx.accounts.associated_token_program.to_account_info(),
+            ctx.accounts.token_metadata_program.to_account_info(),
+        ];
+        if let Some(rules_program) = &ctx.accounts.authorization_rules_program {
+            mint_accounts.push(rules_program.to_account_info());
+        }
+        if let Some(rules) = &ctx.accounts.authorization_rules {
+            mint_accounts.push(rules.to_account_info());
+        }
+
+        anchor_lang::solana_program::program::invoke(&mint_ix, &mint_accounts)?;
+
+        // Verify collection membership (handles both sized and unsized collections)
+        let verify_ix = VerifyCollectionV1 {
+            authority: ctx.accounts.collection_admin.key(),
+            delegate_record: None,
+            metadata: ctx.accounts.nft_metadata.key(),
+            collection_mint: ctx.accounts.collection_mint_account.key(),
+            collection_metadata: Some(ctx.accounts.collection_metadata.key()),
+            collection_master_edition: Some(ctx.accounts.collection_master_edition.key()),
+            system_program: ctx.accounts.system_program.key(),
+            sysvar_instructions: ctx.accounts.sysvar_instructions.key(),
+        }
+        .instruction();
+
+        let verify_accounts = vec![
+            ctx.accounts.collection_admin.to_account_info(),
+            ctx.accounts.nft_metadata.to_account_info(),
+            ctx.accounts.collection_mint_account.to_account_info(),
+            ctx.accounts.collection_metadata.to_account_info(),
+            ctx.accounts.collection_master_edition.to_account_info(),
+            ctx.accounts.system_program.to_account_info(),
+            ctx.accounts.sysvar_instructions.to_account_info(),
+            ctx.accounts.token_metadata_program.to_account_info(),
+        ];
+
+        anchor_lang::solana_program::program::invoke(&verify_ix, &verify_accounts)?;
+
+        nft_type.current_supply += 1;
+        ctx.accounts.item_type.nft_type = nft_type.key();
+        ctx.accounts.item_type.bump = ctx.bumps.item_type;
+
+        msg!(
+            "pNFT minted: {} - {} (type #{}/{})",
+            collection.name,
+            type_name,
+            nft_type.current_supply,
+            nft_type.max_supply
+        );
+        Ok(())
+    }
+
+	// Matchmaking: Create the profile for `wallet`; rooms create profiles for their players,
+	// this covers wallets in rooms that were opened before profiles existed
+	pub fn create_player_profile(ctx: Context<CreatePlayerProfile>, wallet: Pubkey) -> Result<()> {
+		ctx.accounts.profile.init_if_new(wallet, ctx.bumps.profile);
+		Ok(())
+	}
+
+	// Matchmaking: Create a room with an initial stake.
+	// Referee rooms are resolved by `referee` (or the marketplace referee); commit-reveal
+	// rooms take the creator's `commitment` and are decided on-chain. Once joined, the
+	// match must finish within `timeout_secs` or anyone can time it out.
+	// Passing `creator_nft_escrow` wagers the gating NFT itself; the winner takes both NFTs
+	// and the lamport or token stake may then be zero.
+	// Private rooms set `invited_challenger` and/or `invite_hash` (see `invite_hash`); only
+	// that wallet, or a challenger holding the secret, can join.
+	// `best_of` plays an odd-length series; the stakes are paid out once a player clinches it.
+	// Battle rooms pass the gating NFT's `nft_type`; its stats fight the challenger's.
+	#[allow(clippy::too_many_arguments)]
 	pub fn create_room(
 		ctx: Context<CreateRoom>,
 		room_id: u64,
 		stake_lamports: u64,
+		mode: u8,
+		referee: Option<Pubkey>,
+		commitment: Option<[u8; 32]>,
+		timeout_secs: i64,
+		invited_challenger: Option<Pubkey>,
+		invite_hash: Option<[u8; 32]>,
+		best_of: u8,
 	) -> Result<()> {
-		require!(stake_lamports > 0, ErrorCode::InsufficientFunds);
+		require!(
+			best_of % 2 == 1 && best_of <= MAX_SERIES_GAMES,
+			ErrorCode::InvalidSeriesLength
+		);
+		let wager_nfts = ctx.accounts.creator_nft_escrow.is_some();
+		require!(stake_lamports > 0 || wager_nfts, ErrorCode::InsufficientFunds);
+		require!(
+			(MIN_ROOM_TIMEOUT_SECS..=MAX_ROOM_TIMEOUT_SECS).contains(&timeout_secs),
+			ErrorCode::InvalidRoomTimeout
+		);
+		require!(mode <= RoomMode::Battle as u8, ErrorCode::InvalidRoomMode);
+		let (referee, commitment) = if mode == RoomMode::Referee as u8 {
+			let referee = referee.unwrap_or(ctx.accounts.marketplace.referee);
+			require!(referee != Pubkey::default(), ErrorCode::MissingReferee);
+			(referee, [0u8; 32])
+		} else {
+			(Pubkey::default(), commitment.ok_or(ErrorCode::MissingCommitment)?)
+		};
 
 		// Require creator to own at least 1 token of the provided NFT mint
 		require!(ctx.accounts.creator_nft_token.amount >= 1, ErrorCode::Unauthorized);
 
-		// Verify that the provided NFT belongs to the expected collection
-		let metadata_account_info = ctx.accounts.nft_metadata.to_account_info();
-		let metadata: TokenMetadata = try_from_slice_unchecked(&metadata_account_info.data.borrow())?;
-		let collection = metadata.collection.ok_or(ErrorCode::Unauthorized)?;
-		require!(collection.key == ctx.accounts.collection_mint.key(), ErrorCode::Unauthorized);
+		// Verify that the provided NFT is a verified item of the gating collection
+		verify_collection_item(
+			&ctx.accounts.nft_metadata,
+			&ctx.accounts.nft_mint.key(),
+			&ctx.accounts.collection.mint,
+		)?;
+		let creator_stats = if mode == RoomMode::Battle as u8 {
+			item_battle_stats(ctx.accounts.item_type.as_deref(), ctx.accounts.nft_type.as_deref())?
+		} else {
+			BattleStats::default()
+		};
 
 		let room = &mut ctx.accounts.room;
-		room.creator = ctx.accounts.creator.key();
-		room.challenger = None;
-		room.room_id = room_id;
+		room.init(
+			ctx.accounts.creator.key(),
+			room_id,
+			ctx.accounts.collection.key(),
+			ctx.accounts.nft_mint.key(),
+			ctx.bumps.room,
+		);
 		room.stake_lamports = stake_lamports;
-		room.status = RoomStatus::Waiting as u8;
-		room.bump = ctx.bumps.room;
-
-		// Transfer stake from creator to the room (escrow)
-		let transfer_ix = anchor_lang::solana_program::system_instruction::transfer(
-			&ctx.accounts.creator.key(),
-			&room.key(),
-			stake_lamports,
+		room.referee = referee;
+		room.mode = mode;
+		room.creator_commitment = commitment;
+		room.timeout_secs = timeout_secs;
+		room.stake_mint = ctx.accounts.stake_mint.as_ref().map(|mint| mint.key());
+		// Rake terms are fixed when the room is created
+		room.snapshot_terms(&ctx.accounts.marketplace, ctx.accounts.rake_exemption.is_some());
+		room.wager_nfts = wager_nfts;
+		room.invited_challenger = invited_challenger;
+		room.invite_hash = invite_hash;
+		room.best_of = best_of;
+		room.creator_stats = creator_stats;
+
+		ctx.accounts.creator_nft_lock.set(
+			room.creator_nft,
+			room.creator,
+			room.key(),
+			ctx.bumps.creator_nft_lock,
 		);
-		anchor_lang::solana_program::program::invoke(
-			&transfer_ix,
-			&[
-				ctx.accounts.creator.to_account_info(),
-				room.to_account_info(),
-			],
-		)?;
 
-		Ok(())
-	}
+		ctx.accounts.creator_profile.init_if_new(room.creator, ctx.bumps.creator_profile);
+
+		if let Some(creator_nft_escrow) = &ctx.accounts.creator_nft_escrow {
+			// Escrow the gating NFT as part of the stake
+			let token_program = ctx.accounts.token_program.as_ref().ok_or(ErrorCode::MissingStakeAccounts)?;
+			let transfer_cpi_accounts = anchor_spl::token::Transfer {
+				from: ctx.accounts.creator_nft_token.to_account_info(),
+				to: creator_nft_escrow.to_account_info(),
+				authority: ctx.accounts.creator.to_account_info(),
+			};
+			let transfer_cpi_ctx = CpiContext::new(token_program.to_account_info(), transfer_cpi_accounts);
+			anchor_spl::token::transfer(transfer_cpi_ctx, 1)?;
+		}
 
-	// Matchmaking: Join a room by matching the stake
-	pub fn join_room(ctx: Context<JoinRoom>) -> Result<()> {
-		let room = &mut ctx.accounts.room;
+		if room.stake_mint.is_some() {
+			// Token stake: move it into the room-owned vault
+			let (Some(room_vault), Some(creator_stake_token), Some(token_program)) = (
+				&ctx.accounts.room_vault,
+				&ctx.accounts.creator_stake_token,
+				&ctx.accounts.token_program,
+			) else {
+				return err!(ErrorCode::MissingStakeAccounts);
+			};
+			require_keys_eq!(creator_stake_token.mint, room_vault.mint, ErrorCode::InvalidStakeAccount);
+			let transfer_cpi_accounts = anchor_spl::token::Transfer {
+				from: creator_stake_token.to_account_info(),
+				to: room_vault.to_account_info(),
+				authority: ctx.accounts.creator.to_account_info(),
+			};
+			let transfer_cpi_ctx = CpiContext::new(token_program.to_account_info(), transfer_cpi_accounts);
+			anchor_spl::token::transfer(transfer_cpi_ctx, stake_lamports)?;
+		} else {
+			// Transfer stake from creator to the room (escrow)
+			let transfer_ix = anchor_lang::solana_program::system_instruction::transfer(
+				&ctx.accounts.creator.key(),
+				&room.key(),
+				stake_lamports,
+			);
+			anchor_lang::solana_program::program::invoke(
+				&transfer_ix,
+				&[
+					ctx.accounts.creator.to_account_info(),
+					room.to_account_info(),
+				],
+			)?;
+		}
+
+		Ok(())
+	}
+
+	// Matchmaking: Join a room by matching the stake; commit-reveal rooms take the
+	// challenger's `commitment` and start the reveal window
+	// Private rooms need the invited wallet and/or the `invite_secret` matching the room's hash
+	pub fn join_room(
+		ctx: Context<JoinRoom>,
+		commitment: Option<[u8; 32]>,
+		invite_secret: Option<[u8; 32]>,
+	) -> Result<()> {
+		let room = &mut ctx.accounts.room;
 		require!(room.status == RoomStatus::Waiting as u8, ErrorCode::RoomNotWaiting);
 		require!(room.challenger.is_none(), ErrorCode::RoomHasChallenger);
 		require!(ctx.accounts.challenger.key() != room.creator, ErrorCode::Unauthorized);
+		if let Some(invited) = room.invited_challenger {
+			require_keys_eq!(ctx.accounts.challenger.key(), invited, ErrorCode::NotInvited);
+		}
+		if let Some(expected) = room.invite_hash {
+			let secret = invite_secret.ok_or(ErrorCode::InvalidInviteSecret)?;
+			require!(invite_hash(&secret) == expected, ErrorCode::InvalidInviteSecret);
+		}
 
 		// Require challenger to own at least 1 token of the provided NFT mint
 		require!(ctx.accounts.challenger_nft_token.amount >= 1, ErrorCode::Unauthorized);
 
-		// Verify that the provided NFT belongs to the expected collection
-		let metadata_account_info = ctx.accounts.nft_metadata.to_account_info();
-		let metadata: TokenMetadata = try_from_slice_unchecked(&metadata_account_info.data.borrow())?;
-		let collection = metadata.collection.ok_or(ErrorCode::Unauthorized)?;
-		require!(collection.key == ctx.accounts.collection_mint.key(), ErrorCode::Unauthorized);
-
-		// Transfer matching stake from challenger to the room escrow
-		let transfer_ix = anchor_lang::solana_program::system_instruction::transfer(
-			&ctx.accounts.challenger.key(),
-			&room.key(),
-			room.stake_lamports,
-		);
-		anchor_lang::solana_program::program::invoke(
-			&transfer_ix,
-			&[
-				ctx.accounts.challenger.to_account_info(),
-				room.to_account_info(),
-			],
+		// Verify that the provided NFT is a verified item of the room's collection
+		verify_collection_item(
+			&ctx.accounts.nft_metadata,
+			&ctx.accounts.nft_mint.key(),
+			&ctx.accounts.collection.mint,
 		)?;
+		if room.mode == RoomMode::Battle as u8 {
+			room.challenger_stats =
+				item_battle_stats(ctx.accounts.item_type.as_deref(), ctx.accounts.nft_type.as_deref())?;
+		}
 
-		room.challenger = Some(ctx.accounts.challenger.key());
-		room.status = RoomStatus::Ongoing as u8;
-		Ok(())
-	}
-
-	// Matchmaking: Resolve room, pay winner (creator for now) and close
-	pub fn resolve_room(ctx: Context<ResolveRoom>) -> Result<()> {
-		let room = &ctx.accounts.room;
-		require!(room.status == RoomStatus::Ongoing as u8, ErrorCode::RoomNotOngoing);
-		require!(ctx.accounts.creator.key() == room.creator, ErrorCode::Unauthorized);
-
-		// Payout all lamports held by room to the creator.
-		let room_lamports = **ctx.accounts.room.to_account_info().lamports.borrow();
-		let rent_exempt = Rent::get()?.minimum_balance(Room::space(None));
-		let transferable = room_lamports.saturating_sub(rent_exempt);
-		if transferable > 0 {
-			let seeds = &[
-				b"room",
-				room.creator.as_ref(),
-				&room.room_id.to_le_bytes(),
-				&[room.bump],
-			];
-			let signer = &[&seeds[..]];
-			let ix = anchor_lang::solana_program::system_instruction::transfer(
-				&ctx.accounts.room.key(),
-				&ctx.accounts.creator.key(),
-				transferable,
+		if room.stake_mint.is_some() {
+			// Transfer matching token stake from challenger to the room vault
+			let (Some(room_vault), Some(challenger_stake_token), Some(token_program)) = (
+				&ctx.accounts.room_vault,
+				&ctx.accounts.challenger_stake_token,
+				&ctx.accounts.token_program,
+			) else {
+				return err!(ErrorCode::MissingStakeAccounts);
+			};
+			require_keys_eq!(challenger_stake_token.mint, room_vault.mint, ErrorCode::InvalidStakeAccount);
+			let transfer_cpi_accounts = anchor_spl::token::Transfer {
+				from: challenger_stake_token.to_account_info(),
+				to: room_vault.to_account_info(),
+				authority: ctx.accounts.challenger.to_account_info(),
+			};
+			let transfer_cpi_ctx = CpiContext::new(token_program.to_account_info(), transfer_cpi_accounts);
+			anchor_spl::token::transfer(transfer_cpi_ctx, room.stake_lamports)?;
+		} else {
+			// Transfer matching stake from challenger to the room escrow
+			let transfer_ix = anchor_lang::solana_program::system_instruction::transfer(
+				&ctx.accounts.challenger.key(),
+				&room.key(),
+				room.stake_lamports,
 			);
-			anchor_lang::solana_program::program::invoke_signed(
-				&ix,
+			anchor_lang::solana_program::program::invoke(
+				&transfer_ix,
 				&[
-					ctx.accounts.room.to_account_info(),
-					ctx.accounts.creator.to_account_info(),
-					ctx.accounts.system_program.to_account_info(),
+					ctx.accounts.challenger.to_account_info(),
+					room.to_account_info(),
 				],
-				signer,
 			)?;
 		}
 
-		// Status will be set to Closed and Anchor will close the account via close attribute
+		if room.wager_nfts {
+			// Escrow the challenger's NFT to match the creator's
+			let (Some(challenger_nft_escrow), Some(token_program)) =
+				(&ctx.accounts.challenger_nft_escrow, &ctx.accounts.token_program)
+			else {
+				return err!(ErrorCode::MissingNftEscrow);
+			};
+			let transfer_cpi_accounts = anchor_spl::token::Transfer {
+				from: ctx.accounts.challenger_nft_token.to_account_info(),
+				to: challenger_nft_escrow.to_account_info(),
+				authority: ctx.accounts.challenger.to_account_info(),
+			};
+			let transfer_cpi_ctx = CpiContext::new(token_program.to_account_info(), transfer_cpi_accounts);
+			anchor_spl::token::transfer(transfer_cpi_ctx, 1)?;
+		}
+
+		room.challenger = Some(ctx.accounts.challenger.key());
+		room.challenger_nft = ctx.accounts.nft_mint.key();
+		ctx.accounts.challenger_nft_lock.set(
+			room.challenger_nft,
+			ctx.accounts.challenger.key(),
+			room.key(),
+			ctx.bumps.challenger_nft_lock,
+		);
+		room.deadline = Clock::get()?.unix_timestamp + room.timeout_secs;
+		ctx.accounts
+			.challenger_profile
+			.init_if_new(ctx.accounts.challenger.key(), ctx.bumps.challenger_profile);
+		if room.mode == RoomMode::Referee as u8 {
+			room.status = RoomStatus::Ongoing as u8;
+		} else {
+			room.challenger_commitment = commitment.ok_or(ErrorCode::MissingCommitment)?;
+			room.status = RoomStatus::Committed as u8;
+		}
 		Ok(())
 	}
 
-    // Presale: initialize with 1-day timer and 845 SOL target
-    pub fn initialize_presale(ctx: Context<InitializePresale>) -> Result<()> {
-        let presale = &mut ctx.accounts.presale;
-        presale.admin = ctx.accounts.admin.key();
-        presale.bump = ctx.bumps.presale;
-        presale.is_active = true;
-        let clock = Clock::get()?;
-        presale.start_ts = clock.unix_timestamp;
-        presale.end_ts = clock.unix_timestamp + 86_400; // 1 day
-        presale.total_raised = 0;
-        presale.target_lamports = 845u64.saturating_mul(1_000_000_000);
-        Ok(())
-    }
+	// Sessions: Let `session_key` act for the wallet in room gameplay until `expires_at`.
+	// `scope` is a bitmask of SESSION_SCOPE_* flags. Session keys can only commit, reveal
+	// or forfeit; they never sign transfers of funds or NFTs. Replaces any earlier session.
+	pub fn create_session(
+		ctx: Context<CreateSession>,
+		session_key: Pubkey,
+		expires_at: i64,
+		scope: u8,
+	) -> Result<()> {
+		let now = Clock::get()?.unix_timestamp;
+		require!(
+			expires_at > now && expires_at <= now + MAX_SESSION_SECS,
+			ErrorCode::InvalidSessionExpiry
+		);
+		require!(scope != 0 && scope & !SESSION_SCOPE_ALL == 0, ErrorCode::InvalidSessionScope);
+
+		let session = &mut ctx.accounts.session;
+		session.wallet = ctx.accounts.wallet.key();
+		session.session_key = session_key;
+		session.expires_at = expires_at;
+		session.scope = scope;
+		session.bump = ctx.bumps.session;
+		Ok(())
+	}
 
-    // Presale: restart/reset with 1-day timer; keeps same admin and target
-    pub fn restart_presale(ctx: Context<RestartPresale>) -> Result<()> {
-        let presale = &mut ctx.accounts.presale;
-        require!(ctx.accounts.admin.key() == presale.admin, ErrorCode::Unauthorized);
+	// Sessions: Revoke the wallet's session key and reclaim its rent
+	pub fn revoke_session(_ctx: Context<RevokeSession>) -> Result<()> {
+		Ok(())
+	}
 
-        let clock = Clock::get()?;
-        presale.is_active = true;
-        presale.start_ts = clock.unix_timestamp;
-        presale.end_ts = clock.unix_timestamp + 86_400; // 1 day
-        presale.total_raised = 0;
-        Ok(())
-    }
+	// Matchmaking: Commit a choice for the next game of a commit-reveal series
+	pub fn commit_choice(ctx: Context<CommitChoice>, commitment: [u8; 32]) -> Result<()> {
+		let room = &mut ctx.accounts.room;
+		require!(room.status == RoomStatus::Committed as u8, ErrorCode::RoomNotRevealing);
+		require!(Clock::get()?.unix_timestamp <= room.deadline, ErrorCode::RoomDeadlinePassed);
 
-    // Presale: contribute SOL and record contributor
-    pub fn contribute_presale(ctx: Context<ContributePresale>, lamports: u64) -> Result<()> {
-        require!(lamports > 0, ErrorCode::InsufficientFunds);
+		let player = acting_wallet(
+			&ctx.accounts.player.key(),
+			ctx.accounts.session.as_ref(),
+			SESSION_SCOPE_COMMIT,
+		)?;
+		let slot = if player == room.creator {
+			&mut room.creator_commitment
+		} else if Some(player) == room.challenger {
+			&mut room.challenger_commitment
+		} else {
+			return err!(ErrorCode::Unauthorized);
+		};
+		require!(*slot == [0u8; 32], ErrorCode::AlreadyCommitted);
+		*slot = commitment;
+		Ok(())
+	}
 
-        let presale = &mut ctx.accounts.presale;
-        require!(presale.is_active, ErrorCode::PresaleNotActive);
-        let clock = Clock::get()?;
-        require!(clock.unix_timestamp <= presale.end_ts, ErrorCode::PresaleEnded);
+	// Matchmaking: Reveal a committed choice. The commitment is
+	// sha256(choice || salt || player). Once both players reveal, the game is decided;
+	// the winner is paid when the game settles the series.
+	pub fn reveal_choice(ctx: Context<RevealChoice>, choice: u8, salt: [u8; 32]) -> Result<()> {
+		let room = &mut ctx.accounts.room;
+		require!(
+			room.status == RoomStatus::Committed as u8 || room.status == RoomStatus::Revealing as u8,
+			ErrorCode::RoomNotRevealing
+		);
+		require!(Clock::get()?.unix_timestamp <= room.deadline, ErrorCode::RoomDeadlinePassed);
+		require!(
+			room.creator_commitment != [0u8; 32] && room.challenger_commitment != [0u8; 32],
+			ErrorCode::ChoicesNotCommitted
+		);
+		require!(choice < RoomMode::choices(room.mode), ErrorCode::InvalidChoice);
 
-        // Transfer SOL from contributor to the presale PDA (escrow)
-        let ix = anchor_lang::solana_program::system_instruction::transfer(
-            &ctx.accounts.contributor.key(),
-            &presale.key(),
-            lamports,
-        );
-        anchor_lang::solana_program::program::invoke(
-            &ix,
-            &[
-                ctx.accounts.contributor.to_account_info(),
-                presale.to_account_info(),
-            ],
-        )?;
+		let player = acting_wallet(
+			&ctx.accounts.player.key(),
+			ctx.accounts.session.as_ref(),
+			SESSION_SCOPE_REVEAL,
+		)?;
+		let commitment = room_commitment(choice, &salt, &player);
+		if player == room.creator {
+			require!(room.creator_choice.is_none(), ErrorCode::AlreadyRevealed);
+			require!(commitment == room.creator_commitment, ErrorCode::CommitmentMismatch);
+			room.creator_choice = Some(choice);
+		} else if Some(player) == room.challenger {
+			require!(room.challenger_choice.is_none(), ErrorCode::AlreadyRevealed);
+			require!(commitment == room.challenger_commitment, ErrorCode::CommitmentMismatch);
+			room.challenger_choice = Some(choice);
+		} else {
+			return err!(ErrorCode::Unauthorized);
+		}
+		// Battle randomness: neither player knows the combined salts until both reveal
+		for (entropy, byte) in room.battle_entropy.iter_mut().zip(salt) {
+			*entropy ^= byte;
+		}
 
-        // Track contribution amount per contributor (accumulates)
-        let contrib = &mut ctx.accounts.contribution;
-        contrib.presale = presale.key();
-        contrib.contributor = ctx.accounts.contributor.key();
-        contrib.amount = contrib.amount.saturating_add(lamports);
-        contrib.bump = ctx.bumps.contribution;
+		let (Some(creator_choice), Some(challenger_choice)) = (room.creator_choice, room.challenger_choice) else {
+			room.status = RoomStatus::Revealing as u8;
+			return Ok(());
+		};
+
+		let outcome = if room.mode == RoomMode::Battle as u8 {
+			let seed = anchor_lang::solana_program::hash::hashv(&[&room.battle_entropy, room.key().as_ref()]);
+			resolve_battle(&room.creator_stats, &room.challenger_stats, &seed.to_bytes())
+		} else {
+			RoomMode::outcome(room.mode, creator_choice, challenger_choice)
+		};
+		let winner = match outcome {
+			GameOutcome::CreatorWins => Some(room.creator),
+			GameOutcome::ChallengerWins => room.challenger,
+			GameOutcome::Draw => None,
+		};
+		if !record_series_game(room, winner)? {
+			return Ok(());
+		}
+		let payouts = settle_room(room, room_settle_accounts!(ctx.accounts), winner)?;
+		record_room_result(
+			&mut ctx.accounts.creator_profile,
+			&mut ctx.accounts.challenger_profile,
+			room,
+			payouts,
+			ctx.accounts.season.as_deref_mut(),
+		)?;
+		Ok(())
+	}
 
-        presale.total_raised = presale.total_raised.saturating_add(lamports);
-        Ok(())
-    }
+	// Matchmaking: Anyone refunds a room allocated with the original six-field layout, which
+	// the other room instructions cannot read. The challenger of a joined room gets their
+	// stake back, the creator gets the rest, and the account is closed.
+	pub fn refund_legacy_room(ctx: Context<RefundLegacyRoom>) -> Result<()> {
+		let room_info = ctx.accounts.room.to_account_info();
+		let legacy = {
+			let data = room_info.try_borrow_data()?;
+			require!(
+				data.len() == LegacyRoom::SPACE && &data[..8] == Room::DISCRIMINATOR,
+				ErrorCode::NotLegacyRoom
+			);
+			LegacyRoom::deserialize(&mut &data[8..])?
+		};
+		let room_key = Pubkey::create_program_address(
+			&[b"room", legacy.creator.as_ref(), &legacy.room_id.to_le_bytes(), &[legacy.bump]],
+			&crate::ID,
+		)
+		.map_err(|_| ErrorCode::NotLegacyRoom)?;
+		require_keys_eq!(room_key, room_info.key(), ErrorCode::NotLegacyRoom);
+		require_keys_eq!(ctx.accounts.creator.key(), legacy.creator, ErrorCode::Unauthorized);
+
+		if legacy.status == RoomStatus::Ongoing as u8 {
+			if let Some(challenger) = legacy.challenger {
+				let account = ctx.accounts.challenger.as_ref().ok_or(ErrorCode::Unauthorized)?;
+				require_keys_eq!(account.key(), challenger, ErrorCode::Unauthorized);
+				move_lamports(&room_info, &account.to_account_info(), legacy.stake_lamports)?;
+			}
+		}
+		move_lamports(&room_info, &ctx.accounts.creator.to_account_info(), room_info.lamports())?;
+		room_info.assign(&System::id());
+		room_info.resize(0)?;
+		Ok(())
+	}
 
-    // Presale: end and withdraw funds to admin after timer or if target reached
-    pub fn end_presale(ctx: Context<EndPresale>) -> Result<()> {
-        let presale = &mut ctx.accounts.presale;
-        require!(presale.is_active, ErrorCode::PresaleNotActive);
-        require!(ctx.accounts.admin.key() == presale.admin, ErrorCode::Unauthorized);
+	// Matchmaking: Creator cancels a room nobody has joined; the stake and rent are
+	// refunded when the room closes
+	pub fn cancel_room(ctx: Context<CancelRoom>) -> Result<()> {
+		let room = &mut ctx.accounts.room;
+		require!(room.status == RoomStatus::Waiting as u8, ErrorCode::RoomNotWaiting);
 
-        let clock = Clock::get()?;
-        let reached_time = clock.unix_timestamp >= presale.end_ts;
-        let reached_target = presale.total_raised >= presale.target_lamports;
-        require!(reached_time || reached_target, ErrorCode::PresaleNotEnded);
+		if room.stake_mint.is_some() {
+			let stake = RoomStakeAccounts {
+				vault: ctx.accounts.room_vault.as_deref(),
+				creator_token: ctx.accounts.creator_stake_token.as_deref(),
+				challenger_token: None,
+				token_program: ctx.accounts.token_program.as_ref(),
+			};
+			let refund = stake.vault.map_or(0, |vault| vault.amount);
+			pay_room_vault(room, &stake, None, &ctx.accounts.creator.to_account_info(), refund, 0, 0)?;
+		}
+		if room.wager_nfts {
+			let (Some(escrow), Some(destination), Some(token_program)) = (
+				ctx.accounts.creator_nft_escrow.as_deref(),
+				ctx.accounts.creator_nft_destination.as_deref(),
+				ctx.accounts.token_program.as_ref(),
+			) else {
+				return err!(ErrorCode::MissingNftEscrow);
+			};
+			let creator = ctx.accounts.creator.to_account_info();
+			release_room_nft(room, escrow, destination, room.creator, &creator, token_program)?;
+		}
 
-        // Transfer lamports by directly adjusting balances (source has data)
-        let presale_info = presale.to_account_info();
-        let admin_info = ctx.accounts.admin.to_account_info();
-        let presale_lamports = **presale_info.lamports.borrow();
-        let rent_exempt = Rent::get()?.minimum_balance(Presale::space());
-        let transferable = presale_lamports.saturating_sub(rent_exempt);
-        if transferable > 0 {
-            **presale_info.try_borrow_mut_lamports()? -= transferable;
-            **admin_info.try_borrow_mut_lamports()? += transferable;
-        }
+		room.status = RoomStatus::Closed as u8;
+		Ok(())
+	}
 
-        presale.is_active = false;
-        Ok(())
-    }
+	// Matchmaking: Anyone can settle a full room once its deadline has passed.
+	// A referee room that was never resolved refunds both stakes. In commit-reveal rooms
+	// a player who did not reveal forfeits to one who did, and in later games of a series
+	// one who did not commit forfeits to one who did; otherwise both stakes are refunded.
+	// Refunds are not recorded as matches.
+	pub fn timeout_room(ctx: Context<TimeoutRoom>) -> Result<()> {
+		let room = &mut ctx.accounts.room;
+		require!(
+			room.status == RoomStatus::Ongoing as u8
+				|| room.status == RoomStatus::Committed as u8
+				|| room.status == RoomStatus::Revealing as u8,
+			ErrorCode::RoomNotOngoing
+		);
+		require!(Clock::get()?.unix_timestamp > room.deadline, ErrorCode::RoomDeadlineNotReached);
+
+		let winner = match (room.creator_choice, room.challenger_choice) {
+			(Some(_), None) => Some(room.creator),
+			(None, Some(_)) => room.challenger,
+			_ => match (room.creator_commitment != [0u8; 32], room.challenger_commitment != [0u8; 32]) {
+				(true, false) => Some(room.creator),
+				(false, true) => room.challenger,
+				_ => None,
+			},
+		};
+		let payouts = settle_room(room, room_settle_accounts!(ctx.accounts), winner)?;
+		if winner.is_some() {
+			record_room_result(
+				&mut ctx.accounts.creator_profile,
+				&mut ctx.accounts.challenger_profile,
+				room,
+				payouts,
+				ctx.accounts.season.as_deref_mut(),
+			)?;
+		}
+		Ok(())
+	}
 
-    // Staking: Initialize the staking pool with reward token and rate
-    pub fn initialize_stake_pool(
-        ctx: Context<InitializeStakePool>,
-        reward_rate_per_second: u64, // Reward tokens per second (base rate before multiplier)
-    ) -> Result<()> {
-        let stake_pool = &mut ctx.accounts.stake_pool;
-        stake_pool.admin = ctx.accounts.admin.key();
-        stake_pool.reward_token_mint = ctx.accounts.reward_token_mint.key();
-        stake_pool.reward_rate_per_second = reward_rate_per_second;
-        stake_pool.total_staked = 0;
-        stake_pool.bump = ctx.bumps.stake_pool;
+	// Matchmaking: A player concedes a room in play and the opponent takes the pot; in a
+	// series this concedes the whole series
+	pub fn forfeit_room(ctx: Context<ForfeitRoom>) -> Result<()> {
+		let room = &mut ctx.accounts.room;
+		require!(room.is_in_play(), ErrorCode::RoomNotOngoing);
+		let player = acting_wallet(
+			&ctx.accounts.player.key(),
+			ctx.accounts.session.as_ref(),
+			SESSION_SCOPE_FORFEIT,
+		)?;
+		let winner = if player == room.creator {
+			room.challenger
+		} else if Some(player) == room.challenger {
+			Some(room.creator)
+		} else {
+			return err!(ErrorCode::Unauthorized);
+		};
+
+		let payouts = settle_room(room, room_settle_accounts!(ctx.accounts), winner)?;
+		record_room_result(
+			&mut ctx.accounts.creator_profile,
+			&mut ctx.accounts.challenger_profile,
+			room,
+			payouts,
+			ctx.accounts.season.as_deref_mut(),
+		)?;
+		Ok(())
+	}
 
-        msg!("Stake pool initialized with reward rate: {} tokens/second", reward_rate_per_second);
-        Ok(())
-    }
+	// Matchmaking: Referee resolves the room. `winner` must be the creator or the
+	// challenger and takes the pot; `None` is a draw and splits it. In a series this
+	// records one game, and a drawn game is replayed. If the room has a dispute window,
+	// the deciding result is only proposed: either player may dispute it until the window
+	// closes, after which anyone can finalize it.
+	pub fn resolve_room(ctx: Context<ResolveRoom>, winner: Option<Pubkey>) -> Result<()> {
+		let room = &mut ctx.accounts.room;
+		require!(room.status == RoomStatus::Ongoing as u8, ErrorCode::RoomNotOngoing);
+		require!(room.mode == RoomMode::Referee as u8, ErrorCode::InvalidRoomMode);
+		if let Some(winner) = winner {
+			require!(
+				winner == room.creator || Some(winner) == room.challenger,
+				ErrorCode::InvalidWinner
+			);
+		}
+		if !record_series_game(room, winner)? {
+			return Ok(());
+		}
+		if room.dispute_window_secs > 0 {
+			room.proposed_winner = winner;
+			room.dispute_deadline = Clock::get()?.unix_timestamp + room.dispute_window_secs;
+			room.status = RoomStatus::Proposed as u8;
+			emit!(ResultProposed {
+				room: room.key(),
+				winner,
+				dispute_deadline: room.dispute_deadline,
+			});
+			return Ok(());
+		}
 
-    // Staking: Stake an NFT into the vault
-    pub fn stake_nft(ctx: Context<StakeNFT>) -> Result<()> {
-        let stake_account = &mut ctx.accounts.stake_account;
-        let nft_type = &ctx.accounts.nft_type;
-        let stake_pool = &mut ctx.accounts.stake_pool;
+		let payouts = settle_room(room, room_settle_accounts!(ctx.accounts), winner)?;
+		record_room_result(
+			&mut ctx.accounts.creator_profile,
+			&mut ctx.accounts.challenger_profile,
+			room,
+			payouts,
+			ctx.accounts.season.as_deref_mut(),
+		)?;
+		Ok(())
+	}
 
-        // Verify NFT metadata belongs to the collection
-        let metadata_account_info = ctx.accounts.nft_metadata.to_account_info();
-        let metadata: TokenMetadata = try_from_slice_unchecked(&metadata_account_info.data.borrow())?;
-        let collection = metadata.collection.ok_or(ErrorCode::InvalidNFTMint)?;
-        require!(collection.key == ctx.accounts.collection.mint, ErrorCode::InvalidNFTMint);
+	// Matchmaking: A player disputes a proposed result by posting the room's dispute bond.
+	// The result then waits for `arbitrate_room`.
+	pub fn dispute_result(ctx: Context<DisputeResult>) -> Result<()> {
+		let room = &mut ctx.accounts.room;
+		require!(room.status == RoomStatus::Proposed as u8, ErrorCode::NoProposedResult);
+		require!(Clock::get()?.unix_timestamp <= room.dispute_deadline, ErrorCode::DisputeWindowClosed);
+		let disputer = ctx.accounts.disputer.key();
+		require!(
+			disputer == room.creator || Some(disputer) == room.challenger,
+			ErrorCode::Unauthorized
+		);
 
-        let clock = Clock::get()?;
+		let dispute = &mut ctx.accounts.dispute;
+		dispute.room = room.key();
+		dispute.disputer = disputer;
+		dispute.bond = room.dispute_bond;
+		dispute.bump = ctx.bumps.dispute;
 
-        // Initialize stake account
-        stake_account.owner = ctx.accounts.staker.key();
-        stake_account.nft_mint = ctx.accounts.nft_mint.key();
-        stake_account.nft_type = nft_type.key();
-        stake_account.stake_pool = stake_pool.key();
-        stake_account.stake_timestamp = clock.unix_timestamp;
-        stake_account.last_claim_timestamp = clock.unix_timestamp;
-        stake_account.stake_multiplier = nft_type.stake_multiplier;
-        stake_account.bump = ctx.bumps.stake_account;
+		let transfer_ix = anchor_lang::solana_program::system_instruction::transfer(
+			&disputer,
+			&dispute.key(),
+			dispute.bond,
+		);
+		anchor_lang::solana_program::program::invoke(
+			&transfer_ix,
+			&[
+				ctx.accounts.disputer.to_account_info(),
+				dispute.to_account_info(),
+			],
+		)?;
 
-        // Transfer NFT from staker to vault
-        let transfer_cpi_accounts = anchor_spl::token::Transfer {
-            from: ctx.accounts.staker_nft_token_account.to_account_info(),
-            to: ctx.accounts.vault_nft_token_account.to_account_info(),
-            authority: ctx.accounts.staker.to_account_info(),
-        };
-        let transfer_cpi_ctx = CpiContext::new(
-            ctx.accounts.token_program.to_account_info(),
-            transfer_cpi_accounts,
-        );
-        anchor_spl::token::transfer(transfer_cpi_ctx, 1)?;
+		room.status = RoomStatus::Disputed as u8;
+		Ok(())
+	}
 
-        stake_pool.total_staked += 1;
+	// Matchmaking: Anyone pays out an undisputed proposed result once its window has passed.
+	// A disputed result the admin has not arbitrated within ARBITRATION_WINDOW_SECS of the
+	// window closing is paid out the same way, and the disputer gets the bond back.
+	pub fn finalize_result(ctx: Context<FinalizeResult>) -> Result<()> {
+		let room = &mut ctx.accounts.room;
+		let now = Clock::get()?.unix_timestamp;
+		if room.status == RoomStatus::Disputed as u8 {
+			require!(
+				now > room.dispute_deadline + ARBITRATION_WINDOW_SECS,
+				ErrorCode::ArbitrationWindowOpen
+			);
+			let (Some(dispute), Some(disputer)) = (&ctx.accounts.dispute, &ctx.accounts.disputer) else {
+				return err!(ErrorCode::MissingDispute);
+			};
+			require_keys_eq!(dispute.disputer, disputer.key(), ErrorCode::Unauthorized);
+			dispute.close(disputer.to_account_info())?;
+		} else {
+			require!(room.status == RoomStatus::Proposed as u8, ErrorCode::NoProposedResult);
+			require!(now > room.dispute_deadline, ErrorCode::DisputeWindowOpen);
+		}
+		let winner = room.proposed_winner;
+
+		let payouts = settle_room(room, room_settle_accounts!(ctx.accounts), winner)?;
+		record_room_result(
+			&mut ctx.accounts.creator_profile,
+			&mut ctx.accounts.challenger_profile,
+			room,
+			payouts,
+			ctx.accounts.season.as_deref_mut(),
+		)?;
+		Ok(())
+	}
 
-        msg!(
-            "NFT staked: {} with multiplier {}",
-            ctx.accounts.nft_mint.key(),
-            nft_type.stake_multiplier
-        );
-        Ok(())
-    }
+	// Matchmaking: Marketplace admin settles a disputed room with its own `winner` (`None`
+	// for a draw). The bond goes to the treasury when `slash_bond`, otherwise back to the
+	// disputer, who always gets the dispute account's rent. In a series `winner` replaces
+	// the referee's result for the disputed game; if that leaves the series undecided,
+	// play resumes instead of settling.
+	pub fn arbitrate_room(ctx: Context<ArbitrateRoom>, winner: Option<Pubkey>, slash_bond: bool) -> Result<()> {
+		let room = &mut ctx.accounts.room;
+		require!(room.status == RoomStatus::Disputed as u8, ErrorCode::RoomNotDisputed);
+		if let Some(winner) = winner {
+			require!(
+				winner == room.creator || Some(winner) == room.challenger,
+				ErrorCode::InvalidWinner
+			);
+		}
 
-    // Staking: Unstake NFT and claim all pending rewards
-    pub fn unstake_nft(ctx: Context<UnstakeNFT>) -> Result<()> {
-        let stake_account = &ctx.accounts.stake_account;
+		if slash_bond {
+			let treasury = ctx.accounts.treasury.as_ref().ok_or(ErrorCode::MissingTreasury)?;
+			move_lamports(
+				&ctx.accounts.dispute.to_account_info(),
+				treasury,
+				ctx.accounts.dispute.bond,
+			)?;
+		}
+		emit!(RoomArbitrated {
+			room: room.key(),
+			proposed_winner: room.proposed_winner,
+			winner,
+			bond_slashed: slash_bond,
+		});
+
+		if room.best_of > 1 {
+			// The disputed game was counted with the referee's result; count this one instead
+			match room.proposed_winner {
+				Some(proposed) if proposed == room.creator => room.creator_game_wins -= 1,
+				Some(_) => room.challenger_game_wins -= 1,
+				None => {}
+			}
+			room.games_played -= 1;
+			room.proposed_winner = None;
+			if !record_series_game(room, winner)? {
+				room.status = RoomStatus::Ongoing as u8;
+				return Ok(());
+			}
+		}
 
-        require!(stake_account.owner == ctx.accounts.staker.key(), ErrorCode::Unauthorized);
+		let payouts = settle_room(room, room_settle_accounts!(ctx.accounts), winner)?;
+		record_room_result(
+			&mut ctx.accounts.creator_profile,
+			&mut ctx.accounts.challenger_profile,
+			room,
+			payouts,
+			ctx.accounts.season.as_deref_mut(),
+		)?;
+		Ok(())
+	}
 
-        let clock = Clock::get()?;
+	// Matchmaking: Creator reopens a settled room for a rematch with the same challenger and
+	// stake. The creator stakes again and the challenger rejoins with `join_room`. The
+	// rematch plays under the marketplace's current rake, dispute terms and season.
+	pub fn rematch(ctx: Context<Rematch>, commitment: Option<[u8; 32]>) -> Result<()> {
+		let room = &mut ctx.accounts.room;
+		require!(room.status == RoomStatus::Closed as u8, ErrorCode::RoomNotClosed);
+		require!(
+			room.stake_mint.is_none() && !room.wager_nfts && room.tournament.is_none(),
+			ErrorCode::RematchUnsupported
+		);
+		require!(!room.bet_pool_open, ErrorCode::BetPoolUnsettled);
+		let challenger = room.challenger.ok_or(ErrorCode::RematchUnsupported)?;
+		require!(ctx.accounts.creator_nft_token.amount >= 1, ErrorCode::Unauthorized);
 
-        // Calculate and transfer pending rewards
-        let time_staked = clock.unix_timestamp.saturating_sub(stake_account.last_claim_timestamp);
-        let reward_rate_per_second = ctx.accounts.stake_pool.reward_rate_per_second;
-        let base_rewards = (time_staked as u64)
-            .saturating_mul(reward_rate_per_second);
+		room.creator_commitment = if room.mode == RoomMode::Referee as u8 {
+			[0u8; 32]
+		} else {
+			commitment.ok_or(ErrorCode::MissingCommitment)?
+		};
+		room.challenger = None;
+		room.challenger_nft = Pubkey::default();
+		room.invited_challenger = Some(challenger);
+		room.invite_hash = None;
+		room.status = RoomStatus::Waiting as u8;
+		room.winner = None;
+		room.challenger_commitment = [0u8; 32];
+		room.creator_choice = None;
+		room.challenger_choice = None;
+		room.battle_entropy = [0u8; 32];
+		room.proposed_winner = None;
+		room.dispute_deadline = 0;
+		room.deadline = 0;
+		room.creator_game_wins = 0;
+		room.challenger_game_wins = 0;
+		room.games_played = 0;
+		room.snapshot_terms(&ctx.accounts.marketplace, ctx.accounts.rake_exemption.is_some());
+		ctx.accounts.creator_nft_lock.set(
+			room.creator_nft,
+			room.creator,
+			room.key(),
+			ctx.bumps.creator_nft_lock,
+		);
+
+		let transfer_ix = anchor_lang::solana_program::system_instruction::transfer(
+			&room.creator,
+			&room.key(),
+			room.stake_lamports,
+		);
+		anchor_lang::solana_program::program::invoke(
+			&transfer_ix,
+			&[
+				ctx.accounts.creator.to_account_info(),
+				room.to_account_info(),
+			],
+		)?;
+		Ok(())
+	}
+
+	// Matchmaking: Creator closes a settled room and reclaims its rent
+	pub fn close_room(_ctx: Context<CloseRoom>) -> Result<()> {
+		Ok(())
+	}
+
+	// Seasons: Admin opens a season and makes it the one new rooms play in. Rooms settled
+	// between `start_ts` and `end_ts` earn points; the top `payout_bps.len()` players share
+	// the prize pool, which also receives `rake_share_bps` of lamport room rake. The current
+	// season, if any, must be passed as `previous_season` and have ended, since profiles
+	// only keep points for one season at a time.
+	pub fn create_season(
+		ctx: Context<CreateSeason>,
+		season_id: u64,
+		start_ts: i64,
+		end_ts: i64,
+		payout_bps: Vec<u16>,
+		rake_share_bps: u16,
+	) -> Result<()> {
+		let now = Clock::get()?.unix_timestamp;
+		require!(end_ts > start_ts && end_ts > now, ErrorCode::InvalidSeasonWindow);
+		let previous_season = ctx.accounts.previous_season.as_ref();
+		require!(
+			previous_season.map(|season| season.key()) == ctx.accounts.marketplace.current_season,
+			ErrorCode::SeasonMismatch
+		);
+		if let Some(previous_season) = previous_season {
+			require!(now >= previous_season.end_ts, ErrorCode::SeasonNotEnded);
+		}
+		require!(
+			!payout_bps.is_empty()
+				&& payout_bps.len() <= MAX_SEASON_PLACES
+				&& payout_bps.iter().all(|bps| *bps > 0)
+				&& payout_bps.iter().map(|bps| *bps as u32).sum::<u32>() == 10_000,
+			ErrorCode::InvalidPayoutTable
+		);
+		require!(rake_share_bps <= 10_000, ErrorCode::InvalidRakeBps);
+
+		let season = &mut ctx.accounts.season;
+		season.season_id = season_id;
+		season.start_ts = start_ts;
+		season.end_ts = end_ts;
+		season.rake_share_bps = rake_share_bps;
+		season.payout_bps = payout_bps;
+		season.leaderboard = Vec::new();
+		season.finalized = false;
+		season.prize_pool = 0;
+		season.claimed = 0;
+		season.bump = ctx.bumps.season;
+
+		ctx.accounts.marketplace.current_season = Some(season.key());
+		Ok(())
+	}
+
+	// Seasons: Add lamports to a season's prize pool before it is finalized
+	pub fn fund_season(ctx: Context<FundSeason>, amount: u64) -> Result<()> {
+		let season = &ctx.accounts.season;
+		require!(!season.finalized, ErrorCode::SeasonFinalized);
+
+		let transfer_ix = anchor_lang::solana_program::system_instruction::transfer(
+			&ctx.accounts.funder.key(),
+			&season.key(),
+			amount,
+		);
+		anchor_lang::solana_program::program::invoke(
+			&transfer_ix,
+			&[
+				ctx.accounts.funder.to_account_info(),
+				season.to_account_info(),
+			],
+		)?;
+		Ok(())
+	}
+
+	// Seasons: Anyone locks the leaderboard and prize pool once the season has ended
+	pub fn finalize_season(ctx: Context<FinalizeSeason>) -> Result<()> {
+		let season = &mut ctx.accounts.season;
+		require!(!season.finalized, ErrorCode::SeasonFinalized);
+		require!(Clock::get()?.unix_timestamp >= season.end_ts, ErrorCode::SeasonNotEnded);
+
+		let season_info = season.to_account_info();
+		let rent_exempt = Rent::get()?.minimum_balance(season_info.data_len());
+		season.prize_pool = season_info.lamports().saturating_sub(rent_exempt);
+		season.finalized = true;
+
+		emit!(SeasonFinalized {
+			season: season.key(),
+			prize_pool: season.prize_pool,
+			winners: season.leaderboard.iter().map(|entry| entry.wallet).collect(),
+		});
+		Ok(())
+	}
+
+	// Seasons: A leaderboard player claims the prize for `rank` (0 = first). If fewer players
+	// placed than there are paid places, the filled places split the whole pool.
+	pub fn claim_season_prize(ctx: Context<ClaimSeasonPrize>, rank: u8) -> Result<()> {
+		let season = &mut ctx.accounts.season;
+		require!(season.finalized, ErrorCode::SeasonNotEnded);
+		let rank = rank as usize;
+		require!(
+			season.leaderboard.get(rank).map(|entry| entry.wallet) == Some(ctx.accounts.winner.key()),
+			ErrorCode::Unauthorized
+		);
+		require!(season.claimed & (1 << rank) == 0, ErrorCode::PrizeAlreadyClaimed);
+
+		let placed_bps: u64 = season.payout_bps[..season.leaderboard.len()]
+			.iter()
+			.map(|bps| *bps as u64)
+			.sum();
+		let prize = (season.prize_pool as u128 * season.payout_bps[rank] as u128 / placed_bps as u128) as u64;
+		season.claimed |= 1 << rank;
+		move_lamports(
+			&season.to_account_info(),
+			&ctx.accounts.winner.to_account_info(),
+			prize,
+		)?;
+		Ok(())
+	}
+
+	// Seasons: Admin moves the prize pool of a finalized season nobody placed in to the treasury
+	pub fn sweep_season(ctx: Context<SweepSeason>) -> Result<()> {
+		let season = &mut ctx.accounts.season;
+		require!(season.finalized, ErrorCode::SeasonNotEnded);
+		require!(season.leaderboard.is_empty(), ErrorCode::SeasonHasWinners);
+
+		let prize = season.prize_pool;
+		season.prize_pool = 0;
+		move_lamports(
+			&season.to_account_info(),
+			&ctx.accounts.treasury.to_account_info(),
+			prize,
+		)?;
+		Ok(())
+	}
+
+	// Betting: Open a spectator bet pool on a room in play. Bets are taken until `cutoff`,
+	// which must fall before the room's current deadline. Only rooms decided on-chain or by
+	// the marketplace referee take bets; a player-chosen referee could settle them.
+	pub fn create_bet_pool(ctx: Context<CreateBetPool>, cutoff: i64) -> Result<()> {
+		let room = &mut ctx.accounts.room;
+		let marketplace = &ctx.accounts.marketplace;
+		require!(
+			room.mode != RoomMode::Referee as u8 || room.referee == marketplace.referee,
+			ErrorCode::BetPoolUnsupported
+		);
+		require!(room.accepts_bets(), ErrorCode::BetPoolClosed);
+		let now = Clock::get()?.unix_timestamp;
+		require!(cutoff > now && cutoff <= room.deadline, ErrorCode::InvalidBetCutoff);
+
+		let pool = &mut ctx.accounts.bet_pool;
+		pool.room = room.key();
+		pool.cutoff = cutoff;
+		pool.fee_bps = marketplace.bet_fee_bps;
+		pool.treasury = marketplace.treasury;
+		pool.creator_total = 0;
+		pool.challenger_total = 0;
+		pool.status = BetPoolStatus::Open as u8;
+		pool.winning_side = None;
+		pool.payout_total = 0;
+		pool.bump = ctx.bumps.bet_pool;
+		pool.payer = ctx.accounts.payer.key();
+		pool.open_bets = 0;
+
+		room.bet_pool_open = true;
+		Ok(())
+	}
+
+	// Betting: Spectator backs the creator or challenger. A wallet can add to its bet but
+	// not switch sides; the room's players cannot bet. Betting closes once any result is
+	// public: the first reveal or the first game of a series.
+	pub fn place_bet(ctx: Context<PlaceBet>, side: u8, amount: u64) -> Result<()> {
+		let room = &ctx.accounts.room;
+		let pool = &mut ctx.accounts.bet_pool;
+		require!(pool.status == BetPoolStatus::Open as u8, ErrorCode::BetPoolClosed);
+		require!(room.accepts_bets(), ErrorCode::BetPoolClosed);
+		require!(Clock::get()?.unix_timestamp < pool.cutoff, ErrorCode::BetPoolClosed);
+		require!(amount > 0, ErrorCode::InsufficientFunds);
+		require!(side <= BetSide::Challenger as u8, ErrorCode::InvalidBetSide);
+
+		let bettor = ctx.accounts.bettor.key();
+		require!(
+			bettor != room.creator && Some(bettor) != room.challenger,
+			ErrorCode::Unauthorized
+		);
+
+		let bet = &mut ctx.accounts.bet;
+		if bet.amount == 0 {
+			bet.pool = pool.key();
+			bet.bettor = bettor;
+			bet.side = side;
+			bet.bump = ctx.bumps.bet;
+			pool.open_bets += 1;
+		} else {
+			require!(bet.side == side, ErrorCode::InvalidBetSide);
+		}
+		bet.amount += amount;
+		if side == BetSide::Creator as u8 {
+			pool.creator_total += amount;
+		} else {
+			pool.challenger_total += amount;
+		}
+
+		let transfer_ix = anchor_lang::solana_program::system_instruction::transfer(
+			&bettor,
+			&pool.key(),
+			amount,
+		);
+		anchor_lang::solana_program::program::invoke(
+			&transfer_ix,
+			&[
+				ctx.accounts.bettor.to_account_info(),
+				pool.to_account_info(),
+			],
+		)?;
+		Ok(())
+	}
+
+	// Betting: Anyone settles a pool once its room has settled. The house fee goes to the
+	// treasury and the rest is shared by the winning side. If the room was drawn or
+	// refunded, or nobody backed the winner, every bet is refunded without a fee.
+	pub fn settle_bet_pool(ctx: Context<SettleBetPool>) -> Result<()> {
+		let room = &mut ctx.accounts.room;
+		let pool = &mut ctx.accounts.bet_pool;
+		require!(pool.status == BetPoolStatus::Open as u8, ErrorCode::BetPoolClosed);
+		require!(room.status == RoomStatus::Closed as u8, ErrorCode::RoomNotClosed);
+
+		let winning_side = room.winner.map(|winner| {
+			if winner == room.creator { BetSide::Creator as u8 } else { BetSide::Challenger as u8 }
+		});
+		let winning_total = match winning_side {
+			Some(side) if side == BetSide::Creator as u8 => pool.creator_total,
+			Some(_) => pool.challenger_total,
+			None => 0,
+		};
+
+		if winning_total == 0 {
+			pool.status = BetPoolStatus::Refunding as u8;
+		} else {
+			let total = pool.creator_total + pool.challenger_total;
+			let fee = (total as u128 * pool.fee_bps as u128 / 10_000) as u64;
+			if fee > 0 {
+				let treasury = ctx.accounts.treasury.as_ref().ok_or(ErrorCode::MissingTreasury)?;
+				move_lamports(&pool.to_account_info(), treasury, fee)?;
+			}
+			pool.payout_total = total - fee;
+			pool.winning_side = winning_side;
+			pool.status = BetPoolStatus::Settled as u8;
+		}
+		room.bet_pool_open = false;
+
+		emit!(BetPoolSettled {
+			room: room.key(),
+			pool: pool.key(),
+			winning_side: pool.winning_side,
+			payout_total: pool.payout_total,
+		});
+		Ok(())
+	}
+
+	// Betting: Pay a bet its pro-rata share of a settled pool (or its refund) and close it.
+	// Losing bets can be closed to reclaim their rent. Anyone may claim for a bettor; the
+	// payout always goes to the bettor.
+	pub fn claim_bet(ctx: Context<ClaimBet>) -> Result<()> {
+		let pool = &mut ctx.accounts.bet_pool;
+		let bet = &ctx.accounts.bet;
+		require!(pool.status != BetPoolStatus::Open as u8, ErrorCode::BetPoolUnsettled);
+		let payout = if pool.status == BetPoolStatus::Refunding as u8 {
+			bet.amount
+		} else if pool.winning_side == Some(bet.side) {
+			let winning_total = if bet.side == BetSide::Creator as u8 {
+				pool.creator_total
+			} else {
+				pool.challenger_total
+			};
+			(bet.amount as u128 * pool.payout_total as u128 / winning_total as u128) as u64
+		} else {
+			0
+		};
+
+		move_lamports(
+			&pool.to_account_info(),
+			&ctx.accounts.bettor.to_account_info(),
+			payout,
+		)?;
+		pool.open_bets -= 1;
+		Ok(())
+	}
+
+	// Betting: Anyone closes a settled pool once every bet is claimed, so the room can take
+	// a new pool after a rematch. Rent and any rounding dust go back to the pool's payer.
+	pub fn close_bet_pool(ctx: Context<CloseBetPool>) -> Result<()> {
+		let pool = &ctx.accounts.bet_pool;
+		require!(pool.status != BetPoolStatus::Open as u8, ErrorCode::BetPoolUnsettled);
+		require!(pool.open_bets == 0, ErrorCode::BetsOutstanding);
+		Ok(())
+	}
+
+	// Matchmaking: Admin opens a queue for a verified collection at one stake tier
+	pub fn create_match_queue(
+		ctx: Context<CreateMatchQueue>,
+		stake_lamports: u64,
+		timeout_secs: i64,
+	) -> Result<()> {
+		require!(stake_lamports > 0, ErrorCode::InsufficientFunds);
+		require!(
+			(MIN_ROOM_TIMEOUT_SECS..=MAX_ROOM_TIMEOUT_SECS).contains(&timeout_secs),
+			ErrorCode::InvalidRoomTimeout
+		);
+
+		let queue = &mut ctx.accounts.match_queue;
+		queue.collection = ctx.accounts.collection.key();
+		queue.stake_lamports = stake_lamports;
+		queue.timeout_secs = timeout_secs;
+		queue.waiting = None;
+		queue.matches = 0;
+		queue.bump = ctx.bumps.match_queue;
+
+		msg!("Match queue opened at {} lamports", stake_lamports);
+		Ok(())
+	}
+
+	// Matchmaking: Join a stake tier queue. If nobody is waiting, the stake is held by the
+	// queue; otherwise the caller creates a referee room against the waiting player, whose
+	// stake moves from the queue into the room. Pass `room` only when pairing.
+	pub fn enqueue(ctx: Context<Enqueue>) -> Result<()> {
+		require!(ctx.accounts.player_nft_token.amount >= 1, ErrorCode::Unauthorized);
+		verify_collection_item(
+			&ctx.accounts.nft_metadata,
+			&ctx.accounts.nft_mint.key(),
+			&ctx.accounts.collection.mint,
+		)?;
+
+		let player = ctx.accounts.player.key();
+		let nft_mint = ctx.accounts.nft_mint.key();
+		ctx.accounts.player_profile.init_if_new(player, ctx.bumps.player_profile);
+		let queue = &mut ctx.accounts.match_queue;
+		let Some(waiting) = queue.waiting else {
+			require!(ctx.accounts.room.is_none(), ErrorCode::QueueEmpty);
+
+			// The NFT stays locked to the queue until the player is paired or leaves
+			ctx.accounts.player_nft_lock.set(nft_mint, player, queue.key(), ctx.bumps.player_nft_lock);
+
+			// Hold the stake in the queue until an opponent arrives
+			let transfer_ix = anchor_lang::solana_program::system_instruction::transfer(
+				&player,
+				&queue.key(),
+				queue.stake_lamports,
+			);
+			anchor_lang::solana_program::program::invoke(
+				&transfer_ix,
+				&[
+					ctx.accounts.player.to_account_info(),
+					queue.to_account_info(),
+				],
+			)?;
+			queue.waiting = Some(player);
+			queue.waiting_nft = nft_mint;
+			return Ok(());
+		};
+		require_keys_neq!(waiting, player, ErrorCode::AlreadyQueued);
+
+		let marketplace = &ctx.accounts.marketplace;
+		require!(marketplace.referee != Pubkey::default(), ErrorCode::MissingReferee);
+		let room = ctx.accounts.room.as_mut().ok_or(ErrorCode::MissingRoomAccount)?;
+		room.init(
+			player,
+			queue.next_room_id(),
+			queue.collection,
+			nft_mint,
+			ctx.bumps.room.ok_or(ErrorCode::MissingRoomAccount)?,
+		);
+		room.challenger = Some(waiting);
+		room.challenger_nft = queue.waiting_nft;
+		room.stake_lamports = queue.stake_lamports;
+		room.status = RoomStatus::Ongoing as u8;
+		room.referee = marketplace.referee;
+		room.timeout_secs = queue.timeout_secs;
+		room.deadline = Clock::get()?.unix_timestamp + queue.timeout_secs;
+		room.snapshot_terms(marketplace, ctx.accounts.rake_exemption.is_some());
+
+		// Both gating NFTs are now locked to the room
+		ctx.accounts.player_nft_lock.set(nft_mint, player, room.key(), ctx.bumps.player_nft_lock);
+		let waiting_nft_lock = ctx.accounts.waiting_nft_lock.as_mut().ok_or(ErrorCode::NftLockMismatch)?;
+		waiting_nft_lock.room = room.key();
+
+		// Waiting player's stake moves from the queue; the caller matches it
+		move_lamports(&queue.to_account_info(), &room.to_account_info(), queue.stake_lamports)?;
+		let transfer_ix = anchor_lang::solana_program::system_instruction::transfer(
+			&player,
+			&room.key(),
+			queue.stake_lamports,
+		);
+		anchor_lang::solana_program::program::invoke(
+			&transfer_ix,
+			&[
+				ctx.accounts.player.to_account_info(),
+				room.to_account_info(),
+			],
+		)?;
+
+		emit!(QueueMatched {
+			queue: queue.key(),
+			room: room.key(),
+			creator: player,
+			challenger: waiting,
+		});
+		queue.waiting = None;
+		queue.waiting_nft = Pubkey::default();
+		queue.matches += 1;
+		Ok(())
+	}
+
+	// Matchmaking: Waiting player leaves the queue and gets the stake back
+	pub fn leave_queue(ctx: Context<LeaveQueue>) -> Result<()> {
+		let queue = &mut ctx.accounts.match_queue;
+		require!(queue.waiting == Some(ctx.accounts.player.key()), ErrorCode::Unauthorized);
+
+		move_lamports(
+			&queue.to_account_info(),
+			&ctx.accounts.player.to_account_info(),
+			queue.stake_lamports,
+		)?;
+		queue.waiting = None;
+		queue.waiting_nft = Pubkey::default();
+		Ok(())
+	}
+
+	// Matchmaking: Create a room with `seat_count` seats, each escrowing `stake_lamports`.
+	// The creator takes seat 0. `payout_bps` pays places in order (e.g. [6000, 3000, 1000])
+	// and must sum to 10000.
+	pub fn create_multi_room(
+		ctx: Context<CreateMultiRoom>,
+		room_id: u64,
+		stake_lamports: u64,
+		seat_count: u8,
+		payout_bps: Vec<u16>,
+		referee: Option<Pubkey>,
+		timeout_secs: i64,
+	) -> Result<()> {
+		require!(stake_lamports > 0, ErrorCode::InsufficientFunds);
+		require!(
+			(MIN_ROOM_SEATS..=MAX_ROOM_SEATS).contains(&seat_count),
+			ErrorCode::InvalidSeatCount
+		);
+		require!(
+			!payout_bps.is_empty()
+				&& payout_bps.len() <= seat_count as usize
+				&& payout_bps.iter().map(|bps| *bps as u32).sum::<u32>() == 10_000,
+			ErrorCode::InvalidPayoutTable
+		);
+		require!(
+			(MIN_ROOM_TIMEOUT_SECS..=MAX_ROOM_TIMEOUT_SECS).contains(&timeout_secs),
+			ErrorCode::InvalidRoomTimeout
+		);
+		let referee = referee.unwrap_or(ctx.accounts.marketplace.referee);
+		require!(referee != Pubkey::default(), ErrorCode::MissingReferee);
+
+		// Require creator to own a verified item of the gating collection
+		require!(ctx.accounts.creator_nft_token.amount >= 1, ErrorCode::Unauthorized);
+		verify_collection_item(
+			&ctx.accounts.nft_metadata,
+			&ctx.accounts.nft_mint.key(),
+			&ctx.accounts.collection.mint,
+		)?;
+
+		let marketplace = &ctx.accounts.marketplace;
+		let room = &mut ctx.accounts.multi_room;
+		room.creator = ctx.accounts.creator.key();
+		room.room_id = room_id;
+		room.stake_lamports = stake_lamports;
+		room.seat_count = seat_count;
+		room.seats = vec![ctx.accounts.creator.key()];
+		room.seat_nfts = vec![ctx.accounts.nft_mint.key()];
+		room.payout_bps = payout_bps;
+		room.status = RoomStatus::Waiting as u8;
+		room.referee = referee;
+		room.collection = ctx.accounts.collection.key();
+		room.timeout_secs = timeout_secs;
+		room.deadline = 0;
+		room.rake_bps = if ctx.accounts.rake_exemption.is_some() { 0 } else { marketplace.room_rake_bps };
+		room.rake_cap = marketplace.room_rake_cap;
+		room.treasury = marketplace.treasury;
+		room.bump = ctx.bumps.multi_room;
+
+		ctx.accounts.creator_nft_lock.set(
+			ctx.accounts.nft_mint.key(),
+			room.creator,
+			room.key(),
+			ctx.bumps.creator_nft_lock,
+		);
+
+		// Transfer stake from creator to the room (escrow)
+		let transfer_ix = anchor_lang::solana_program::system_instruction::transfer(
+			&ctx.accounts.creator.key(),
+			&room.key(),
+			stake_lamports,
+		);
+		anchor_lang::solana_program::program::invoke(
+			&transfer_ix,
+			&[
+				ctx.accounts.creator.to_account_info(),
+				room.to_account_info(),
+			],
+		)?;
+
+		Ok(())
+	}
+
+	// Matchmaking: Take the next free seat; the room starts when the last seat fills
+	pub fn join_multi_room(ctx: Context<JoinMultiRoom>) -> Result<()> {
+		let room = &mut ctx.accounts.multi_room;
+		require!(room.status == RoomStatus::Waiting as u8, ErrorCode::RoomNotWaiting);
+		let player = ctx.accounts.player.key();
+		require!(!room.seats.contains(&player), ErrorCode::AlreadySeated);
+
+		require!(ctx.accounts.player_nft_token.amount >= 1, ErrorCode::Unauthorized);
+		verify_collection_item(
+			&ctx.accounts.nft_metadata,
+			&ctx.accounts.nft_mint.key(),
+			&ctx.accounts.collection.mint,
+		)?;
+
+		// Transfer matching stake from the player to the room escrow
+		let transfer_ix = anchor_lang::solana_program::system_instruction::transfer(
+			&player,
+			&room.key(),
+			room.stake_lamports,
+		);
+		anchor_lang::solana_program::program::invoke(
+			&transfer_ix,
+			&[
+				ctx.accounts.player.to_account_info(),
+				room.to_account_info(),
+			],
+		)?;
+
+		room.seats.push(player);
+		room.seat_nfts.push(ctx.accounts.nft_mint.key());
+		ctx.accounts.player_nft_lock.set(
+			ctx.accounts.nft_mint.key(),
+			player,
+			room.key(),
+			ctx.bumps.player_nft_lock,
+		);
+		if room.seats.len() == room.seat_count as usize {
+			room.status = RoomStatus::Ongoing as u8;
+			room.deadline = Clock::get()?.unix_timestamp + room.timeout_secs;
+		}
+		Ok(())
+	}
+
+	// Matchmaking: Referee posts the finishing order as seat indices, one per paid place.
+	// Remaining accounts are the wallets of those seats in the same order, followed by
+	// every seated wallet and then every seat's NFT lock, both in seat order.
+	pub fn resolve_multi_room<'info>(
+		ctx: Context<'_, '_, 'info, 'info, ResolveMultiRoom<'info>>,
+		placements: Vec<u8>,
+	) -> Result<()> {
+		let room = &mut ctx.accounts.multi_room;
+		require!(room.status == RoomStatus::Ongoing as u8, ErrorCode::RoomNotOngoing);
+		require!(placements.len() == room.payout_bps.len(), ErrorCode::InvalidPlacements);
+		let seats = room.seats.len();
+		require!(
+			ctx.remaining_accounts.len() == placements.len() + 2 * seats,
+			ErrorCode::InvalidPlacements
+		);
+		let (placed, seat_accounts) = ctx.remaining_accounts.split_at(placements.len());
+		for (i, seat) in placements.iter().enumerate() {
+			require!((*seat as usize) < room.seats.len(), ErrorCode::InvalidPlacements);
+			require!(!placements[..i].contains(seat), ErrorCode::InvalidPlacements);
+			require_keys_eq!(
+				placed[i].key(),
+				room.seats[*seat as usize],
+				ErrorCode::InvalidPlacements
+			);
+		}
+
+		let room_info = room.to_account_info();
+		let rent_exempt = Rent::get()?.minimum_balance(room_info.data_len());
+		let pot = room_info.lamports().saturating_sub(rent_exempt);
+		let rake = rake_amount(pot, room.rake_bps, room.rake_cap);
+		if rake > 0 {
+			let treasury = ctx.accounts.treasury.as_ref().ok_or(ErrorCode::MissingTreasury)?;
+			move_lamports(&room_info, treasury, rake)?;
+		}
+
+		// Pay each place its share of the prize; rounding dust goes to first place
+		let prize = pot - rake;
+		let mut payouts: Vec<u64> = room
+			.payout_bps
+			.iter()
+			.map(|bps| (prize as u128 * *bps as u128 / 10_000) as u64)
+			.collect();
+		payouts[0] += prize - payouts.iter().sum::<u64>();
+		for (account, amount) in placed.iter().zip(&payouts) {
+			move_lamports(&room_info, account, *amount)?;
+		}
+		let (wallets, locks) = seat_accounts.split_at(seats);
+		release_seat_locks(room, wallets, locks)?;
+
+		room.status = RoomStatus::Closed as u8;
+		emit!(MultiRoomResolved {
+			room: room.key(),
+			placements: placed.iter().map(|account| account.key()).collect(),
+			payouts,
+			rake,
+		});
+		Ok(())
+	}
+
+	// Matchmaking: Refund every seat of a multi-player room. The creator may cancel while
+	// it is filling; anyone may once a started room passes its deadline. Remaining
+	// accounts are the seated wallets in seat order, followed by each seat's NFT lock.
+	pub fn refund_multi_room<'info>(
+		ctx: Context<'_, '_, 'info, 'info, RefundMultiRoom<'info>>,
+	) -> Result<()> {
+		let room = &mut ctx.accounts.multi_room;
+		if room.status == RoomStatus::Waiting as u8 {
+			require_keys_eq!(ctx.accounts.caller.key(), room.creator, ErrorCode::Unauthorized);
+		} else {
+			require!(room.status == RoomStatus::Ongoing as u8, ErrorCode::RoomNotOngoing);
+			require!(Clock::get()?.unix_timestamp > room.deadline, ErrorCode::RoomDeadlineNotReached);
+		}
+		let seats = room.seats.len();
+		require!(ctx.remaining_accounts.len() == 2 * seats, ErrorCode::InvalidPlacements);
+
+		let (wallets, locks) = ctx.remaining_accounts.split_at(seats);
+		let room_info = room.to_account_info();
+		for (account, seat) in wallets.iter().zip(&room.seats) {
+			require_keys_eq!(account.key(), *seat, ErrorCode::InvalidPlacements);
+			move_lamports(&room_info, account, room.stake_lamports)?;
+		}
+		release_seat_locks(room, wallets, locks)?;
+
+		room.status = RoomStatus::Closed as u8;
+		Ok(())
+	}
+
+	// Tournaments: Open a single-elimination tournament for `size` players (a power of two)
+	// gated by `collection`. Entry fees make up the prize pool, which the finishing places
+	// share by `payout_bps`: the champion, the finalist, then the losers of the later matches
+	// in bracket order. Bracket rooms carry no stake and are played in `mode`; Battle rooms
+	// need each NFT's type stats, so they are not offered.
+	#[allow(clippy::too_many_arguments)]
+	pub fn create_tournament(
+		ctx: Context<CreateTournament>,
+		tournament_id: u64,
+		entry_fee: u64,
+		size: u8,
+		mode: u8,
+		timeout_secs: i64,
+		payout_bps: Vec<u16>,
+		registration_deadline: i64,
+	) -> Result<()> {
+		require!(
+			(MIN_TOURNAMENT_SIZE..=MAX_TOURNAMENT_SIZE).contains(&size) && size.is_power_of_two(),
+			ErrorCode::InvalidBracketSize
+		);
+		require!(
+			registration_deadline > Clock::get()?.unix_timestamp,
+			ErrorCode::InvalidRegistrationDeadline
+		);
+		require!(mode < RoomMode::Battle as u8, ErrorCode::InvalidRoomMode);
+		require!(
+			(MIN_ROOM_TIMEOUT_SECS..=MAX_ROOM_TIMEOUT_SECS).contains(&timeout_secs),
+			ErrorCode::InvalidRoomTimeout
+		);
+		require!(
+			!payout_bps.is_empty()
+				&& payout_bps.len() <= size as usize
+				&& payout_bps.iter().map(|bps| *bps as u32).sum::<u32>() == 10_000,
+			ErrorCode::InvalidPayoutTable
+		);
+		let referee = if mode == RoomMode::Referee as u8 {
+			require!(ctx.accounts.marketplace.referee != Pubkey::default(), ErrorCode::MissingReferee);
+			ctx.accounts.marketplace.referee
+		} else {
+			Pubkey::default()
+		};
+
+		let tournament = &mut ctx.accounts.tournament;
+		tournament.organizer = ctx.accounts.organizer.key();
+		tournament.tournament_id = tournament_id;
+		tournament.collection = ctx.accounts.collection.key();
+		tournament.entry_fee = entry_fee;
+		tournament.size = size;
+		tournament.mode = mode;
+		tournament.referee = referee;
+		tournament.timeout_secs = timeout_secs;
+		tournament.registration_deadline = registration_deadline;
+		tournament.status = TournamentStatus::Registering as u8;
+		tournament.payout_bps = payout_bps;
+		tournament.entrants = Vec::new();
+		tournament.bracket = vec![Pubkey::default(); 2 * size as usize - 1];
+		tournament.rooms = vec![Pubkey::default(); size as usize - 1];
+		tournament.room_payers = vec![Pubkey::default(); size as usize - 1];
+		tournament.prize_pool = 0;
+		tournament.claimed = 0;
+		tournament.bump = ctx.bumps.tournament;
+		Ok(())
+	}
+
+	// Tournaments: Pay the entry fee and take a seat with a collection NFT, which stays locked
+	// until the player is knocked out or the tournament ends. Entries close at the
+	// registration deadline. The entry that fills the bracket seeds it by rating and starts
+	// the tournament.
+	pub fn enter_tournament(ctx: Context<EnterTournament>) -> Result<()> {
+		require!(ctx.accounts.player_nft_token.amount >= 1, ErrorCode::Unauthorized);
+		verify_collection_item(
+			&ctx.accounts.nft_metadata,
+			&ctx.accounts.nft_mint.key(),
+			&ctx.accounts.collection.mint,
+		)?;
+
+		let player = ctx.accounts.player.key();
+		let nft_mint = ctx.accounts.nft_mint.key();
+		ctx.accounts.player_profile.init_if_new(player, ctx.bumps.player_profile);
+		let tournament = &mut ctx.accounts.tournament;
+		require!(
+			tournament.status == TournamentStatus::Registering as u8,
+			ErrorCode::TournamentNotOpen
+		);
+		require!(
+			Clock::get()?.unix_timestamp <= tournament.registration_deadline,
+			ErrorCode::TournamentNotOpen
+		);
+		require!(tournament.entry(&player).is_none(), ErrorCode::AlreadyEntered);
+
+		let transfer_ix = anchor_lang::solana_program::system_instruction::transfer(
+			&player,
+			&tournament.key(),
+			tournament.entry_fee,
+		);
+		anchor_lang::solana_program::program::invoke(
+			&transfer_ix,
+			&[
+				ctx.accounts.player.to_account_info(),
+				tournament.to_account_info(),
+			],
+		)?;
+		ctx.accounts.player_nft_lock.set(nft_mint, player, tournament.key(), ctx.bumps.player_nft_lock);
+
+		tournament.entrants.push(TournamentEntry {
+			wallet: player,
+			nft: nft_mint,
+			rating: ctx.accounts.player_profile.rating,
+		});
+		if tournament.entrants.len() == tournament.size as usize {
+			tournament.seed();
+			tournament.status = TournamentStatus::Running as u8;
+		}
+		Ok(())
+	}
+
+	// Tournaments: Call off a tournament that has not filled: the organizer at any time, anyone
+	// once the registration deadline has passed. Remaining accounts are the entrant wallets,
+	// then their NFT locks, in entry order; each gets the entry fee back.
+	pub fn cancel_tournament<'info>(
+		ctx: Context<'_, '_, 'info, 'info, CancelTournament<'info>>,
+	) -> Result<()> {
+		let tournament = &ctx.accounts.tournament;
+		require!(
+			tournament.status == TournamentStatus::Registering as u8,
+			ErrorCode::TournamentNotOpen
+		);
+		require!(
+			ctx.accounts.caller.key() == tournament.organizer
+				|| Clock::get()?.unix_timestamp > tournament.registration_deadline,
+			ErrorCode::Unauthorized
+		);
+		let entrants = tournament.entrants.len();
+		require!(ctx.remaining_accounts.len() == 2 * entrants, ErrorCode::InvalidPlacements);
+
+		let (wallets, locks) = ctx.remaining_accounts.split_at(entrants);
+		let tournament_info = tournament.to_account_info();
+		for ((wallet, lock_info), entry) in wallets.iter().zip(locks).zip(&tournament.entrants) {
+			require_keys_eq!(wallet.key(), entry.wallet, ErrorCode::InvalidPlacements);
+			let lock = Account::<NftLock>::try_from(lock_info)?;
+			require!(
+				lock.mint == entry.nft && lock.room == tournament.key(),
+				ErrorCode::NftLockMismatch
+			);
+			move_lamports(&tournament_info, wallet, tournament.entry_fee)?;
+			lock.close(wallet.clone())?;
+		}
+		Ok(())
+	}
+
+	// Tournaments: Anyone opens the room for match `node` once both its players are known
+	// and gets the rent back when the result is reported. The upper-slot player is the room
+	// creator. It is an ordinary room without a stake, so it is played, timed out, disputed
+	// or forfeited like any other.
+	pub fn open_bracket_room(ctx: Context<OpenBracketRoom>, node: u8) -> Result<()> {
+		let tournament = &mut ctx.accounts.tournament;
+		require!(
+			tournament.status == TournamentStatus::Running as u8,
+			ErrorCode::TournamentNotRunning
+		);
+		let node = node as usize;
+		require!(node < tournament.rooms.len(), ErrorCode::MatchNotReady);
+		require!(tournament.rooms[node] == Pubkey::default(), ErrorCode::MatchAlreadyOpened);
+		let (creator, challenger) = tournament.match_players(node);
+		require!(
+			creator != Pubkey::default() && challenger != Pubkey::default(),
+			ErrorCode::MatchNotReady
+		);
+		let creator_nft_lock = &mut ctx.accounts.creator_nft_lock;
+		let challenger_nft_lock = &mut ctx.accounts.challenger_nft_lock;
+		require!(
+			creator_nft_lock.owner == creator && challenger_nft_lock.owner == challenger,
+			ErrorCode::NftLockMismatch
+		);
+
+		let marketplace = &ctx.accounts.marketplace;
+		let room = &mut ctx.accounts.room;
+		room.init(
+			creator,
+			node as u64,
+			tournament.collection,
+			creator_nft_lock.mint,
+			ctx.bumps.room,
+		);
+		room.challenger = Some(challenger);
+		room.challenger_nft = challenger_nft_lock.mint;
+		// Commit-reveal rooms start waiting on both commitments
+		room.status = if tournament.mode == RoomMode::Referee as u8 {
+			RoomStatus::Ongoing as u8
+		} else {
+			RoomStatus::Committed as u8
+		};
+		room.referee = tournament.referee;
+		room.mode = tournament.mode;
+		room.timeout_secs = tournament.timeout_secs;
+		room.deadline = Clock::get()?.unix_timestamp + tournament.timeout_secs;
+		// No rake and no season points; disputes follow the marketplace terms
+		room.snapshot_terms(marketplace, true);
+		room.season = None;
+		room.tournament = Some(tournament.key());
+
+		// Both entry NFTs are locked to the room while it is played
+		creator_nft_lock.room = room.key();
+		challenger_nft_lock.room = room.key();
+		tournament.rooms[node] = room.key();
+		tournament.room_payers[node] = ctx.accounts.payer.key();
+		Ok(())
+	}
+
+	// Tournaments: Anyone records the result of a settled bracket room and closes it, refunding
+	// the rent to whoever opened it. The winner advances with their NFT locked back to the
+	// tournament and the loser's NFT is released. A drawn or refunded room is replayed: both
+	// NFTs go back to the tournament and the match can be opened again, with the upper-slot
+	// player passed as `winner`. Reporting the final ends the tournament and fixes the prize
+	// pool.
+	pub fn report_bracket_room(ctx: Context<ReportBracketRoom>, node: u8) -> Result<()> {
+		let tournament = &mut ctx.accounts.tournament;
+		require!(
+			tournament.status == TournamentStatus::Running as u8,
+			ErrorCode::TournamentNotRunning
+		);
+		let node = node as usize;
+		let (upper, lower) = tournament.match_players(node);
+		let result = ctx.accounts.room.winner;
+		let winner = result.unwrap_or(upper);
+		let loser = if winner == upper { lower } else { upper };
+		require_keys_eq!(ctx.accounts.winner.key(), winner, ErrorCode::Unauthorized);
+		require_keys_eq!(ctx.accounts.loser.key(), loser, ErrorCode::Unauthorized);
+		require!(
+			ctx.accounts.winner_nft_lock.owner == winner && ctx.accounts.loser_nft_lock.owner == loser,
+			ErrorCode::NftLockMismatch
+		);
+
+		tournament.room_payers[node] = Pubkey::default();
+		// Nobody advances from a draw or a refund; the match is played again
+		if result.is_none() {
+			ctx.accounts.winner_nft_lock.room = tournament.key();
+			ctx.accounts.loser_nft_lock.room = tournament.key();
+			tournament.rooms[node] = Pubkey::default();
+			return Ok(());
+		}
+
+		tournament.bracket[node] = winner;
+		ctx.accounts.loser_nft_lock.close(ctx.accounts.loser.to_account_info())?;
+		if node > 0 {
+			ctx.accounts.winner_nft_lock.room = tournament.key();
+			return Ok(());
+		}
+
+		ctx.accounts.winner_nft_lock.close(ctx.accounts.winner.to_account_info())?;
+		let tournament_info = tournament.to_account_info();
+		let rent_exempt = Rent::get()?.minimum_balance(tournament_info.data_len());
+		tournament.prize_pool = tournament_info.lamports().saturating_sub(rent_exempt);
+		tournament.status = TournamentStatus::Finished as u8;
+
+		emit!(TournamentFinished {
+			tournament: tournament.key(),
+			prize_pool: tournament.prize_pool,
+			winners: (0..tournament.payout_bps.len()).map(|place| tournament.finisher(place)).collect(),
+		});
+		Ok(())
+	}
+
+	// Tournaments: A finisher claims the prize for `place` (0 = champion)
+	pub fn claim_tournament_prize(ctx: Context<ClaimTournamentPrize>, place: u8) -> Result<()> {
+		let tournament = &mut ctx.accounts.tournament;
+		require!(
+			tournament.status == TournamentStatus::Finished as u8,
+			ErrorCode::TournamentNotFinished
+		);
+		let place = place as usize;
+		require!(
+			place < tournament.payout_bps.len() && tournament.finisher(place) == ctx.accounts.winner.key(),
+			ErrorCode::Unauthorized
+		);
+		require!(tournament.claimed & (1 << place) == 0, ErrorCode::PrizeAlreadyClaimed);
+
+		let prize = (tournament.prize_pool as u128 * tournament.payout_bps[place] as u128 / 10_000) as u64;
+		tournament.claimed |= 1 << place;
+		move_lamports(
+			&tournament.to_account_info(),
+			&ctx.accounts.winner.to_account_info(),
+			prize,
+		)?;
+		Ok(())
+	}
+
+    // Presale: initialize with 1-day timer and 845 SOL target
+    pub fn initialize_presale(ctx: Context<InitializePresale>) -> Result<()> {
+        let presale = &mut ctx.accounts.presale;
+        presale.admin = ctx.accounts.admin.key();
+        presale.bump = ctx.bumps.presale;
+        presale.is_active = true;
+        let clock = Clock::get()?;
+        presale.start_ts = clock.unix_timestamp;
+        presale.end_ts = clock.unix_timestamp + 86_400; // 1 day
+        presale.total_raised = 0;
+        presale.target_lamports = 845u64.saturating_mul(1_000_000_000);
+        Ok(())
+    }
+
+    // Presale: restart/reset with 1-day timer; keeps same admin and target
+    pub fn restart_presale(ctx: Context<RestartPresale>) -> Result<()> {
+        let presale = &mut ctx.accounts.presale;
+        require!(ctx.accounts.admin.key() == presale.admin, ErrorCode::Unauthorized);
+
+        let clock = Clock::get()?;
+        presale.is_active = true;
+        presale.start_ts = clock.unix_timestamp;
+        presale.end_ts = clock.unix_timestamp + 86_400; // 1 day
+        presale.total_raised = 0;
+        Ok(())
+    }
+
+    // Presale: contribute SOL and record contributor
+    pub fn contribute_presale(ctx: Context<ContributePresale>, lamports: u64) -> Result<()> {
+        require!(lamports > 0, ErrorCode::InsufficientFunds);
+
+        let presale = &mut ctx.accounts.presale;
+        require!(presale.is_active, ErrorCode::PresaleNotActive);
+        let clock = Clock::get()?;
+        require!(clock.unix_timestamp <= presale.end_ts, ErrorCode::PresaleEnded);
+
+        // Transfer SOL from contributor to the presale PDA (escrow)
+        let ix = anchor_lang::solana_program::system_instruction::transfer(
+            &ctx.accounts.contributor.key(),
+            &presale.key(),
+            lamports,
+        );
+        anchor_lang::solana_program::program::invoke(
+            &ix,
+            &[
+                ctx.accounts.contributor.to_account_info(),
+                presale.to_account_info(),
+            ],
+        )?;
+
+        // Track contribution amount per contributor (accumulates)
+        let contrib = &mut ctx.accounts.contribution;
+        contrib.presale = presale.key();
+        contrib.contributor = ctx.accounts.contributor.key();
+        contrib.amount = contrib.amount.saturating_add(lamports);
+        contrib.bump = ctx.bumps.contribution;
+
+        presale.total_raised = presale.total_raised.saturating_add(lamports);
+        Ok(())
+    }
+
+    // Presale: end and withdraw funds to admin after timer or if target reached
+    pub fn end_presale(ctx: Context<EndPresale>) -> Result<()> {
+        let presale = &mut ctx.accounts.presale;
+        require!(presale.is_active, ErrorCode::PresaleNotActive);
+        require!(ctx.accounts.admin.key() == presale.admin, ErrorCode::Unauthorized);
+
+        let clock = Clock::get()?;
+        let reached_time = clock.unix_timestamp >= presale.end_ts;
+        let reached_target = presale.total_raised >= presale.target_lamports;
+        require!(reached_time || reached_target, ErrorCode::PresaleNotEnded);
+
+        // Transfer lamports by directly adjusting balances (source has data)
+        let presale_info = presale.to_account_info();
+        let admin_info = ctx.accounts.admin.to_account_info();
+        let presale_lamports = **presale_info.lamports.borrow();
+        let rent_exempt = Rent::get()?.minimum_balance(Presale::space());
+        let transferable = presale_lamports.saturating_sub(rent_exempt);
+        if transferable > 0 {
+            **presale_info.try_borrow_mut_lamports()? -= transferable;
+            **admin_info.try_borrow_mut_lamports()? += transferable;
+        }
+
+        presale.is_active = false;
+        Ok(())
+    }
+
+    // Staking: Initialize the staking pool with reward token and rate
+    pub fn initialize_stake_pool(
+        ctx: Context<InitializeStakePool>,
+        reward_rate_per_second: u64, // Reward tokens per second (base rate before multiplier)
+    ) -> Result<()> {
+        let stake_pool = &mut ctx.accounts.stake_pool;
+        stake_pool.admin = ctx.accounts.admin.key();
+        stake_pool.reward_token_mint = ctx.accounts.reward_token_mint.key();
+        stake_pool.reward_rate_per_second = reward_rate_per_second;
+        stake_pool.total_staked = 0;
+        stake_pool.bump = ctx.bumps.stake_pool;
+
+        msg!("Stake pool initialized with reward rate: {} tokens/second", reward_rate_per_second);
+        Ok(())
+    }
+
+    // Staking: Stake an NFT into the vault
+    pub fn stake_nft(ctx: Context<StakeNFT>) -> Result<()> {
+        let stake_account = &mut ctx.accounts.stake_account;
+        let nft_type = &ctx.accounts.nft_type;
+        let stake_pool = &mut ctx.accounts.stake_pool;
+
+        // Verify NFT metadata belongs to the collection
+        let metadata_account_info = ctx.accounts.nft_metadata.to_account_info();
+        let metadata: TokenMetadata = TokenMetadata::from_bytes(&metadata_account_info.data.borrow())?;
+        let collection = metadata.collection.ok_or(ErrorCode::InvalidNFTMint)?;
+        require!(collection.key == ctx.accounts.collection.mint, ErrorCode::InvalidNFTMint);
+
+        let clock = Clock::get()?;
+
+        // Initialize stake account
+        stake_account.owner = ctx.accounts.staker.key();
+        stake_account.nft_mint = ctx.accounts.nft_mint.key();
+        stake_account.nft_type = nft_type.key();
+        stake_account.stake_pool = stake_pool.key();
+        stake_account.stake_timestamp = clock.unix_timestamp;
+        stake_account.last_claim_timestamp = clock.unix_timestamp;
+        stake_account.stake_multiplier = nft_type.stake_multiplier;
+        stake_account.bump = ctx.bumps.stake_account;
+
+        // Transfer NFT from staker to vault
+        let transfer_cpi_accounts = anchor_spl::token::Transfer {
+            from: ctx.accounts.staker_nft_token_account.to_account_info(),
+            to: ctx.accounts.vault_nft_token_account.to_account_info(),
+            authority: ctx.accounts.staker.to_account_info(),
+        };
+        let transfer_cpi_ctx = CpiContext::new(
+            ctx.accounts.token_program.to_account_info(),
+            transfer_cpi_accounts,
+        );
+        anchor_spl::token::transfer(transfer_cpi_ctx, 1)?;
+
+        stake_pool.total_staked += 1;
+
+        msg!(
+            "NFT staked: {} with multiplier {}",
+            ctx.accounts.nft_mint.key(),
+            nft_type.stake_multiplier
+        );
+        Ok(())
+    }
+
+    // Staking: Unstake NFT and claim all pending rewards
+    pub fn unstake_nft(ctx: Context<UnstakeNFT>) -> Result<()> {
+        let stake_account = &ctx.accounts.stake_account;
+
+        require!(stake_account.owner == ctx.accounts.staker.key(), ErrorCode::Unauthorized);
+
+        let clock = Clock::get()?;
+
+        // Calculate and transfer pending rewards
+        let time_staked = clock.unix_timestamp.saturating_sub(stake_account.last_claim_timestamp);
+        let reward_rate_per_second = ctx.accounts.stake_pool.reward_rate_per_second;
+        let base_rewards = (time_staked as u64)
+            .saturating_mul(reward_rate_per_second);
+        let rewards = base_rewards
+            .saturating_mul(stake_account.stake_multiplier)
+            .saturating_div(10000); // Divide by 10000 because multiplier is in basis points
+
+        let pool_bump = ctx.accounts.stake_pool.bump;
+        if rewards > 0 {
+            let pool_seeds = &[
+                b"stake_pool".as_ref(),
+                &[pool_bump],
+            ];
+            let signer = &[&pool_seeds[..]];
+
+            let transfer_cpi_accounts = anchor_spl::token::Transfer {
+                from: ctx.accounts.reward_token_vault.to_account_info(),
+                to: ctx.accounts.staker_reward_token_account.to_account_info(),
+                authority: ctx.accounts.stake_pool.to_account_info(),
+            };
+            let transfer_cpi_ctx = CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                transfer_cpi_accounts,
+                signer,
+            );
+            anchor_spl::token::transfer(transfer_cpi_ctx, rewards)?;
+        }
+
+        // Transfer NFT back from vault to staker
+        let nft_mint_key = stake_account.nft_mint;
+        let staker_key = ctx.accounts.staker.key();
+        let stake_account_seeds = &[
+            b"stake_account",
+            staker_key.as_ref(),
+            nft_mint_key.as_ref(),
+            &[stake_account.bump],
+        ];
+        let signer = &[&stake_account_seeds[..]];
+
+        let nft_transfer_cpi_accounts = anchor_spl::token::Transfer {
+            from: ctx.accounts.vault_nft_token_account.to_account_info(),
+            to: ctx.accounts.staker_nft_token_account.to_account_info(),
+            authority: ctx.accounts.stake_account.to_account_info(),
+        };
+        let nft_transfer_cpi_ctx = CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
+            nft_transfer_cpi_accounts,
+            signer,
+        );
+        anchor_spl::token::transfer(nft_transfer_cpi_ctx, 1)?;
+
+        ctx.accounts.stake_pool.total_staked = ctx.accounts.stake_pool.total_staked.saturating_sub(1);
+
+        msg!(
+            "NFT unstaked: {}, rewards claimed: {}",
+            nft_mint_key,
+            rewards
+        );
+        Ok(())
+    }
+
+    // Staking: Claim rewards without unstaking
+    pub fn claim_rewards(ctx: Context<ClaimRewards>) -> Result<()> {
+        let stake_account = &mut ctx.accounts.stake_account;
+        let stake_pool = &ctx.accounts.stake_pool;
+
+        require!(stake_account.owner == ctx.accounts.staker.key(), ErrorCode::Unauthorized);
+
+        let clock = Clock::get()?;
+
+        // Calculate rewards since last claim
+        let time_since_last_claim = clock.unix_timestamp.saturating_sub(stake_account.last_claim_timestamp);
+        let base_rewards = (time_since_last_claim as u64)
+            .saturating_mul(stake_pool.reward_rate_per_second);
+        let rewards = base_rewards
+            .saturating_mul(stake_account.stake_multiplier)
+            .saturating_div(10000); // Divide by 10000 because multiplier is in basis points
+
+        if rewards > 0 {
+            let pool_seeds = &[
+                b"stake_pool".as_ref(),
+                &[stake_pool.bump],
+            ];
+            let signer = &[&pool_seeds[..]];
+
+            let transfer_cpi_accounts = anchor_spl::token::Transfer {
+                from: ctx.accounts.reward_token_vault.to_account_info(),
+                to: ctx.accounts.staker_reward_token_account.to_account_info(),
+                authority: ctx.accounts.stake_pool.to_account_info(),
+            };
+            let transfer_cpi_ctx = CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                transfer_cpi_accounts,
+                signer,
+            );
+            anchor_spl::token::transfer(transfer_cpi_ctx, rewards)?;
+
+            // Update last claim timestamp
+            stake_account.last_claim_timestamp = clock.unix_timestamp;
+
+            msg!("Rewards claimed: {}", rewards);
+        } else {
+            msg!("No rewards to claim");
+        }
+
+        Ok(())
+    }
+    // Staking: Stake a pNFT in place by delegating it to the stake account and locking it
+    pub fn stake_programmable_nft(ctx: Context<StakeProgrammableNFT>) -> Result<()> {
+        let nft_type = &ctx.accounts.nft_type;
+
+        // Verify NFT metadata belongs to the collection
+        let metadata_account_info = ctx.accounts.nft_metadata.to_account_info();
+        let metadata: TokenMetadata = TokenMetadata::from_bytes(&metadata_account_info.data.borrow())?;
+        require!(
+            metadata.token_standard == Some(TokenStandard::ProgrammableNonFungible),
+            ErrorCode::NotProgrammable
+        );
+        let collection = metadata.collection.ok_or(ErrorCode::InvalidNFTMint)?;
+        require!(collection.key == ctx.accounts.collection.mint, ErrorCode::InvalidNFTMint);
+
+        let clock = Clock::get()?;
+
+        let stake_account = &mut ctx.accounts.stake_account;
+        stake_account.owner = ctx.accounts.staker.key();
+        stake_account.nft_mint = ctx.accounts.nft_mint.key();
+        stake_account.nft_type = nft_type.key();
+        stake_account.stake_pool = ctx.accounts.stake_pool.key();
+        stake_account.stake_timestamp = clock.unix_timestamp;
+        stake_account.last_claim_timestamp = clock.unix_timestamp;
+        stake_account.stake_multiplier = nft_type.stake_multiplier;
+        stake_account.bump = ctx.bumps.stake_account;
+
+        let rules_program_key = ctx.accounts.authorization_rules_program.as_ref().map(|p| p.key());
+        let rules_key = ctx.accounts.authorization_rules.as_ref().map(|r| r.key());
+
+        let mut metadata_accounts = vec![
+            ctx.accounts.stake_account.to_account_info(),
+            ctx.accounts.staker.to_account_info(),
+            ctx.accounts.nft_metadata.to_account_info(),
+            ctx.accounts.nft_edition.to_account_info(),
+            ctx.accounts.token_record.to_account_info(),
+            ctx.accounts.nft_mint.to_account_info(),
+            ctx.accounts.staker_nft_token_account.to_account_info(),
+            ctx.accounts.system_program.to_account_info(),
+            ctx.accounts.sysvar_instructions.to_account_info(),
+            ctx.accounts.token_program.to_account_info(),
+            ctx.accounts.token_metadata_program.to_account_info(),
+        ];
+        if let Some(rules_program) = &ctx.accounts.authorization_rules_program {
+            metadata_accounts.push(rules_program.to_account_info());
+        }
+        if let Some(rules) = &ctx.accounts.authorization_rules {
+            metadata_accounts.push(rules.to_account_info());
+        }
+
+        // Delegate the token to the stake account (staking delegate role)
+        let delegate_ix = DelegateStakingV1 {
+            delegate_record: None,
+            delegate: ctx.accounts.stake_account.key(),
+            metadata: ctx.accounts.nft_metadata.key(),
+            master_edition: Some(ctx.accounts.nft_edition.key()),
+            token_record: Some(ctx.accounts.token_record.key()),
+            mint: ctx.accounts.nft_mint.key(),
+            token: ctx.accounts.staker_nft_token_account.key(),
+            authority: ctx.accounts.staker.key(),
+            payer: ctx.accounts.staker.key(),
+            system_program: ctx.accounts.system_program.key(),
+            sysvar_instructions: ctx.accounts.sysvar_instructions.key(),
+            spl_token_program: Some(ctx.accounts.token_program.key()),
+            authorization_rules_program: rules_program_key,
+            authorization_rules: rules_key,
+        }.instruction(DelegateStakingV1InstructionArgs {
+            amount: 1,
+            authorization_data: None,
+        });
+        anchor_lang::solana_program::program::invoke(&delegate_ix, &metadata_accounts)?;
+
+        // Lock the token as the delegate so it cannot leave the staker's wallet
+        let staker_key = ctx.accounts.staker.key();
+        let nft_mint_key = ctx.accounts.nft_mint.key();
+        let stake_account_seeds = &[
+            b"stake_account",
+            staker_key.as_ref(),
+            nft_mint_key.as_ref(),
+            &[ctx.bumps.stake_account],
+        ];
+        let signer = &[&stake_account_seeds[..]];
+
+        let lock_ix = LockV1 {
+            authority: ctx.accounts.stake_account.key(),
+            token_owner: Some(staker_key),
+            token: ctx.accounts.staker_nft_token_account.key(),
+            mint: nft_mint_key,
+            metadata: ctx.accounts.nft_metadata.key(),
+            edition: Some(ctx.accounts.nft_edition.key()),
+            token_record: Some(ctx.accounts.token_record.key()),
+            payer: staker_key,
+            system_program: ctx.accounts.system_program.key(),
+            sysvar_instructions: ctx.accounts.sysvar_instructions.key(),
+            spl_token_program: Some(ctx.accounts.token_program.key()),
+            authorization_rules_program: rules_program_key,
+            authorization_rules: rules_key,
+        }.instruction(LockV1InstructionArgs {
+            authorization_data: None,
+        });
+        anchor_lang::solana_program::program::invoke_signed(&lock_ix, &metadata_accounts, signer)?;
+
+        ctx.accounts.stake_pool.total_staked += 1;
+
+        msg!(
+            "pNFT staked: {} with multiplier {}",
+            nft_mint_key,
+            nft_type.stake_multiplier
+        );
+        Ok(())
+    }
+
+    // Staking: Unlock a staked pNFT, revoke the delegate and claim all pending rewards
+    pub fn unstake_programmable_nft(ctx: Context<UnstakeProgrammableNFT>) -> Result<()> {
+        let stake_account = &ctx.accounts.stake_account;
+
+        require!(stake_account.owner == ctx.accounts.staker.key(), ErrorCode::Unauthorized);
+
+        let clock = Clock::get()?;
+
+        // Calculate and transfer pending rewards
+        let time_staked = clock.unix_timestamp.saturating_sub(stake_account.last_claim_timestamp);
+        let base_rewards = (time_staked as u64)
+            .saturating_mul(ctx.accounts.stake_pool.reward_rate_per_second);
         let rewards = base_rewards
             .saturating_mul(stake_account.stake_multiplier)
             .saturating_div(10000); // Divide by 10000 because multiplier is in basis points
 
-        let pool_bump = ctx.accounts.stake_pool.bump;
-        if rewards > 0 {
-            let pool_seeds = &[
-                b"stake_pool".as_ref(),
-                &[pool_bump],
-            ];
-            let signer = &[&pool_seeds[..]];
+        if rewards > 0 {
+            let pool_seeds = &[
+                b"stake_pool".as_ref(),
+                &[ctx.accounts.stake_pool.bump],
+            ];
+            let signer = &[&pool_seeds[..]];
+
+            let transfer_cpi_accounts = anchor_spl::token::Transfer {
+                from: ctx.accounts.reward_token_vault.to_account_info(),
+                to: ctx.accounts.staker_reward_token_account.to_account_info(),
+                authority: ctx.accounts.stake_pool.to_account_info(),
+            };
+            let transfer_cpi_ctx = CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                transfer_cpi_accounts,
+                signer,
+            );
+            anchor_spl::token::transfer(transfer_cpi_ctx, rewards)?;
+        }
+
+        let nft_mint_key = stake_account.nft_mint;
+        let staker_key = ctx.accounts.staker.key();
+        let stake_account_seeds = &[
+            b"stake_account",
+            staker_key.as_ref(),
+            nft_mint_key.as_ref(),
+            &[stake_account.bump],
+        ];
+        let signer = &[&stake_account_seeds[..]];
+
+        let rules_program_key = ctx.accounts.authorization_rules_program.as_ref().map(|p| p.key());
+        let rules_key = ctx.accounts.authorization_rules.as_ref().map(|r| r.key());
+
+        let mut metadata_accounts = vec![
+            ctx.accounts.stake_account.to_account_info(),
+            ctx.accounts.staker.to_account_info(),
+            ctx.accounts.nft_metadata.to_account_info(),
+            ctx.accounts.nft_edition.to_account_info(),
+            ctx.accounts.token_record.to_account_info(),
+            ctx.accounts.nft_mint.to_account_info(),
+            ctx.accounts.staker_nft_token_account.to_account_info(),
+            ctx.accounts.system_program.to_account_info(),
+            ctx.accounts.sysvar_instructions.to_account_info(),
+            ctx.accounts.token_program.to_account_info(),
+            ctx.accounts.token_metadata_program.to_account_info(),
+        ];
+        if let Some(rules_program) = &ctx.accounts.authorization_rules_program {
+            metadata_accounts.push(rules_program.to_account_info());
+        }
+        if let Some(rules) = &ctx.accounts.authorization_rules {
+            metadata_accounts.push(rules.to_account_info());
+        }
+
+        // Unlock as the delegate
+        let unlock_ix = UnlockV1 {
+            authority: ctx.accounts.stake_account.key(),
+            token_owner: Some(staker_key),
+            token: ctx.accounts.staker_nft_token_account.key(),
+            mint: nft_mint_key,
+            metadata: ctx.accounts.nft_metadata.key(),
+            edition: Some(ctx.accounts.nft_edition.key()),
+            token_record: Some(ctx.accounts.token_record.key()),
+            payer: staker_key,
+            system_program: ctx.accounts.system_program.key(),
+            sysvar_instructions: ctx.accounts.sysvar_instructions.key(),
+            spl_token_program: Some(ctx.accounts.token_program.key()),
+            authorization_rules_program: rules_program_key,
+            authorization_rules: rules_key,
+        }.instruction(UnlockV1InstructionArgs {
+            authorization_data: None,
+        });
+        anchor_lang::solana_program::program::invoke_signed(&unlock_ix, &metadata_accounts, signer)?;
+
+        // Revoke the staking delegate as the token owner
+        let revoke_ix = RevokeStakingV1 {
+            delegate_record: None,
+            delegate: ctx.accounts.stake_account.key(),
+            metadata: ctx.accounts.nft_metadata.key(),
+            master_edition: Some(ctx.accounts.nft_edition.key()),
+            token_record: Some(ctx.accounts.token_record.key()),
+            mint: nft_mint_key,
+            token: ctx.accounts.staker_nft_token_account.key(),
+            authority: staker_key,
+            payer: staker_key,
+            system_program: ctx.accounts.system_program.key(),
+            sysvar_instructions: ctx.accounts.sysvar_instructions.key(),
+            spl_token_program: Some(ctx.accounts.token_program.key()),
+            authorization_rules_program: rules_program_key,
+            authorization_rules: rules_key,
+        }.instruction();
+        anchor_lang::solana_program::program::invoke(&revoke_ix, &metadata_accounts)?;
+
+        ctx.accounts.stake_pool.total_staked = ctx.accounts.stake_pool.total_staked.saturating_sub(1);
+
+        msg!(
+            "pNFT unstaked: {}, rewards claimed: {}",
+            nft_mint_key,
+            rewards
+        );
+        Ok(())
+    }
+}
+}
+
+pub use program_root::*;
+
+// Helpers
+// Read past a Borsh string at `offset`, returning the offset after it
+fn skip_borsh_string(data: &[u8], offset: usize) -> Result<usize> {
+    let len_bytes = data.get(offset..offset + 4).ok_or(ErrorCode::AccountLayoutMismatch)?;
+    let len = u32::from_le_bytes(len_bytes.try_into().unwrap()) as usize;
+    require!(data.len() >= offset + 4 + len, ErrorCode::AccountLayoutMismatch);
+    Ok(offset + 4 + len)
+}
+
+// Grow a program account whose newer fields were appended after `bump` to `space` bytes.
+// `fields_end` is where the original fields end and `tail_len` the fewest bytes the
+// appended fields take. An account shorter than `fields_end + tail_len` could never have
+// been read with the appended fields, so they were never written and are zeroed.
+fn grow_account_layout<'info>(
+    account: &AccountInfo<'info>,
+    payer: &AccountInfo<'info>,
+    system_program: &AccountInfo<'info>,
+    fields_end: usize,
+    tail_len: usize,
+    space: usize,
+) -> Result<()> {
+    require_keys_eq!(*account.owner, crate::ID, ErrorCode::AccountLayoutMismatch);
+    let old_len = account.data_len();
+    if old_len >= space {
+        return Ok(());
+    }
+
+    let rent_exempt = Rent::get()?.minimum_balance(space);
+    let top_up = rent_exempt.saturating_sub(account.lamports());
+    if top_up > 0 {
+        let ix = anchor_lang::solana_program::system_instruction::transfer(payer.key, account.key, top_up);
+        anchor_lang::solana_program::program::invoke(
+            &ix,
+            &[payer.clone(), account.clone(), system_program.clone()],
+        )?;
+    }
+    account.resize(space)?;
+    if old_len < fields_end + tail_len {
+        account.try_borrow_mut_data()?[fields_end..].fill(0);
+    }
+    Ok(())
+}
+
+// Where the fields a collection had before `is_sized` end, and its name length
+fn collection_fields_end(data: &[u8]) -> Result<(usize, usize)> {
+    require!(
+        data.len() >= 8 && &data[..8] == NFTCollection::DISCRIMINATOR,
+        ErrorCode::AccountLayoutMismatch
+    );
+    let name_end = skip_borsh_string(data, 8 + 32)?;
+    let uri_end = skip_borsh_string(data, skip_borsh_string(data, name_end)?)?;
+    // royalty, mint, is_active, bump
+    Ok((uri_end + 2 + 32 + 1 + 1, name_end - (8 + 32 + 4)))
+}
+
+// Where the fields an NFT type had before `programmable` end, and its name length
+fn nft_type_fields_end(data: &[u8]) -> Result<(usize, usize)> {
+    require!(
+        data.len() >= 8 && &data[..8] == NftType::DISCRIMINATOR,
+        ErrorCode::AccountLayoutMismatch
+    );
+    let name_end = skip_borsh_string(data, 8 + 32)?;
+    let uri_end = skip_borsh_string(data, name_end)?;
+    // price, max_supply, current_supply, stake_multiplier, bump
+    Ok((uri_end + 8 * 4 + 1, name_end - (8 + 32 + 4)))
+}
+
+// Bring a collection account to the current layout and load it, checking it is the
+// collection PDA its stored name and bump derive
+fn load_migrated_collection<'info>(
+    collection_info: &AccountInfo<'info>,
+    payer: &AccountInfo<'info>,
+    system_program: &AccountInfo<'info>,
+) -> Result<NFTCollection> {
+    let (fields_end, name_len) = collection_fields_end(&collection_info.try_borrow_data()?)?;
+    grow_account_layout(
+        collection_info,
+        payer,
+        system_program,
+        fields_end,
+        NFTCollection::APPENDED_FIELDS_MIN_LEN,
+        NFTCollection::space(name_len),
+    )?;
+
+    let collection = NFTCollection::try_deserialize(&mut &collection_info.try_borrow_data()?[..])?;
+    let address = Pubkey::create_program_address(
+        &[b"collection", collection.name_seed().as_ref(), &[collection.bump]],
+        &crate::ID,
+    )
+    .map_err(|_| ErrorCode::AccountLayoutMismatch)?;
+    require_keys_eq!(address, collection_info.key(), ErrorCode::AccountLayoutMismatch);
+    Ok(collection)
+}
+
+// Bring an NFT type account of `collection` to the current layout and load it, checking it
+// is the type PDA its stored name and bump derive
+fn load_migrated_nft_type<'info>(
+    nft_type_info: &AccountInfo<'info>,
+    collection: &Pubkey,
+    payer: &AccountInfo<'info>,
+    system_program: &AccountInfo<'info>,
+) -> Result<NftType> {
+    let (fields_end, name_len) = {
+        let data = nft_type_info.try_borrow_data()?;
+        let parsed = nft_type_fields_end(&data)?;
+        require!(&data[8..40] == collection.as_ref(), ErrorCode::AccountLayoutMismatch);
+        parsed
+    };
+    grow_account_layout(
+        nft_type_info,
+        payer,
+        system_program,
+        fields_end,
+        NftType::APPENDED_FIELDS_MIN_LEN,
+        NftType::space(name_len),
+    )?;
+
+    let nft_type = NftType::try_deserialize(&mut &nft_type_info.try_borrow_data()?[..])?;
+    let address = Pubkey::create_program_address(
+        &[b"type", collection.as_ref(), nft_type.name_seed().as_ref(), &[nft_type.bump]],
+        &crate::ID,
+    )
+    .map_err(|_| ErrorCode::AccountLayoutMismatch)?;
+    require_keys_eq!(address, nft_type_info.key(), ErrorCode::AccountLayoutMismatch);
+    Ok(nft_type)
+}
+
+// Move lamports out of a program-owned account that holds data
+fn move_lamports(from: &AccountInfo, to: &AccountInfo, amount: u64) -> Result<()> {
+    if amount == 0 {
+        return Ok(());
+    }
+    **from.try_borrow_mut_lamports()? -= amount;
+    **to.try_borrow_mut_lamports()? += amount;
+    Ok(())
+}
+
+// Token accounts passed to room instructions; only required for token-staked rooms
+pub struct RoomStakeAccounts<'a, 'info> {
+    pub vault: Option<&'a Account<'info, TokenAccount>>,
+    pub creator_token: Option<&'a Account<'info, TokenAccount>>,
+    pub challenger_token: Option<&'a Account<'info, TokenAccount>>,
+    pub token_program: Option<&'a Program<'info, Token>>,
+}
+
+// Rake destination accounts; only required when a rake is taken
+pub struct RoomTreasuryAccounts<'a, 'info> {
+    pub treasury: Option<&'a AccountInfo<'info>>,
+    pub treasury_token: Option<&'a Account<'info, TokenAccount>>,
+    pub season: Option<&'a Account<'info, Season>>, // Takes its rake share on lamport rooms
+}
+
+// Gating NFT locks, plus the NFT escrows and their destinations for rooms that wager NFTs
+pub struct RoomNftAccounts<'a, 'info> {
+    pub creator_lock: &'a Account<'info, NftLock>,
+    pub challenger_lock: &'a Account<'info, NftLock>,
+    pub creator_escrow: Option<&'a Account<'info, TokenAccount>>,
+    pub challenger_escrow: Option<&'a Account<'info, TokenAccount>>,
+    pub creator_nft_destination: Option<&'a Account<'info, TokenAccount>>, // Receives the creator's NFT
+    pub challenger_nft_destination: Option<&'a Account<'info, TokenAccount>>, // Receives the challenger's NFT
+}
+
+// Everything `settle_room` pays out to or releases; see `room_settle_accounts!`
+pub struct RoomSettleAccounts<'a, 'info> {
+    pub creator: AccountInfo<'info>,
+    pub challenger: AccountInfo<'info>,
+    pub stake: RoomStakeAccounts<'a, 'info>,
+    pub treasury: RoomTreasuryAccounts<'a, 'info>,
+    pub nfts: RoomNftAccounts<'a, 'info>,
+}
+
+// Record one game of a room's series and return whether the series is decided.
+// Single-game rooms are always decided. In a series, a drawn game is replayed and
+// each game resets the deadline; commit-reveal players commit again via `commit_choice`.
+fn record_series_game(room: &mut Account<Room>, winner: Option<Pubkey>) -> Result<bool> {
+    if room.best_of <= 1 {
+        return Ok(true);
+    }
+
+    room.games_played = room.games_played.saturating_add(1);
+    match winner {
+        Some(winner) if winner == room.creator => room.creator_game_wins += 1,
+        Some(_) => room.challenger_game_wins += 1,
+        None => {}
+    }
+    emit!(SeriesGameRecorded {
+        room: room.key(),
+        game: room.games_played,
+        winner,
+        creator_wins: room.creator_game_wins,
+        challenger_wins: room.challenger_game_wins,
+    });
+
+    let to_clinch = room.best_of / 2 + 1;
+    if room.creator_game_wins >= to_clinch || room.challenger_game_wins >= to_clinch {
+        return Ok(true);
+    }
+
+    room.deadline = Clock::get()?.unix_timestamp + room.timeout_secs;
+    if room.mode != RoomMode::Referee as u8 {
+        room.creator_commitment = [0u8; 32];
+        room.challenger_commitment = [0u8; 32];
+        room.creator_choice = None;
+        room.challenger_choice = None;
+        room.battle_entropy = [0u8; 32];
+        room.status = RoomStatus::Committed as u8;
+    }
+    Ok(false)
+}
+
+// Pay out a room's pot to `winner` (split on a draw) and record the result.
+// Lamport rooms pay everything above the room's rent, which goes back to the creator
+// on close; token rooms pay out the vault and close it. Pots with a winner pay the
+// room's rake to the treasury first; draws and refunds are not raked. Wagered NFTs all go
+// to the winner, or back to their owners on a draw. The gating NFT locks are released;
+// the room itself stays open for a rematch until its creator closes it.
+fn settle_room<'info>(
+    room: &mut Account<'info, Room>,
+    accounts: RoomSettleAccounts<'_, 'info>,
+    winner: Option<Pubkey>,
+) -> Result<(u64, u64)> {
+    let RoomSettleAccounts { creator, challenger, stake, treasury, nfts } = accounts;
+    let room_info = room.to_account_info();
+    let pot = if room.stake_mint.is_some() {
+        stake.vault.ok_or(ErrorCode::MissingStakeAccounts)?.amount
+    } else {
+        let rent_exempt = Rent::get()?.minimum_balance(Room::space(None));
+        room_info.lamports().saturating_sub(rent_exempt)
+    };
+    let rake = if winner.is_some() { room.rake(pot) } else { 0 };
+    let prize = pot - rake;
+    let (creator_payout, challenger_payout) = match winner {
+        Some(winner) if winner == room.creator => (prize, 0),
+        Some(_) => (0, prize),
+        // Draw: odd unit goes to the creator
+        None => (prize - prize / 2, prize / 2),
+    };
+    if room.stake_mint.is_some() {
+        pay_room_vault(
+            room,
+            &stake,
+            treasury.treasury_token,
+            &creator,
+            creator_payout,
+            challenger_payout,
+            rake,
+        )?;
+    } else {
+        // A live season takes its share of the rake into the prize pool
+        let now = Clock::get()?.unix_timestamp;
+        let season_share = match treasury.season {
+            Some(season) if season.is_live(now) => {
+                let share = (rake as u128 * season.rake_share_bps as u128 / 10_000) as u64;
+                move_lamports(&room_info, &season.to_account_info(), share)?;
+                share
+            }
+            _ => 0,
+        };
+        if rake > season_share {
+            let treasury = treasury.treasury.ok_or(ErrorCode::MissingTreasury)?;
+            move_lamports(&room_info, treasury, rake - season_share)?;
+        }
+        move_lamports(&room_info, &creator, creator_payout)?;
+        move_lamports(&room_info, &challenger, challenger_payout)?;
+    }
+
+    if room.wager_nfts {
+        let (
+            Some(creator_escrow),
+            Some(challenger_escrow),
+            Some(creator_nft_destination),
+            Some(challenger_nft_destination),
+            Some(token_program),
+        ) = (
+            nfts.creator_escrow,
+            nfts.challenger_escrow,
+            nfts.creator_nft_destination,
+            nfts.challenger_nft_destination,
+            stake.token_program,
+        )
+        else {
+            return err!(ErrorCode::MissingNftEscrow);
+        };
+        let challenger_key = challenger.key();
+        let (creator_nft_to, challenger_nft_to) = match winner {
+            Some(winner) => (winner, winner),
+            None => (room.creator, challenger_key),
+        };
+        release_room_nft(room, creator_escrow, creator_nft_destination, creator_nft_to, &creator, token_program)?;
+        release_room_nft(
+            room,
+            challenger_escrow,
+            challenger_nft_destination,
+            challenger_nft_to,
+            &challenger,
+            token_program,
+        )?;
+    }
+
+    // Bracket rooms keep their locks until the tournament records the result
+    if room.tournament.is_none() {
+        nfts.creator_lock.close(creator)?;
+        nfts.challenger_lock.close(challenger.clone())?;
+    }
+
+    room.status = RoomStatus::Closed as u8;
+    room.winner = winner;
+    emit!(RoomResolved {
+        room: room.key(),
+        creator: room.creator,
+        challenger: challenger.key(),
+        winner,
+        stake_mint: room.stake_mint,
+        creator_payout,
+        challenger_payout,
+        rake,
+    });
+    Ok((creator_payout, challenger_payout))
+}
+
+// Update both players' profiles with a settled room's result
+fn record_room_result(
+    creator_profile: &mut PlayerProfile,
+    challenger_profile: &mut PlayerProfile,
+    room: &Account<Room>,
+    (creator_payout, challenger_payout): (u64, u64),
+    season: Option<&mut Account<Season>>,
+) -> Result<()> {
+    require!(
+        season.as_ref().map(|season| season.key()) == room.season,
+        ErrorCode::SeasonMismatch
+    );
+    let (creator_result, challenger_result) = match room.winner {
+        Some(winner) if winner == room.creator => (MatchResult::Win, MatchResult::Loss),
+        Some(_) => (MatchResult::Loss, MatchResult::Win),
+        None => (MatchResult::Draw, MatchResult::Draw),
+    };
+    let creator_delta = elo_delta(creator_profile.rating, challenger_profile.rating, creator_result);
+    let challenger_delta = elo_delta(challenger_profile.rating, creator_profile.rating, challenger_result);
+
+    // Wagered and won totals are in lamports; token-staked rooms do not count towards them
+    let lamports = room.stake_mint.is_none();
+    let timestamp = Clock::get()?.unix_timestamp;
+    creator_profile.record(MatchRecord {
+        room: room.key(),
+        opponent: challenger_profile.wallet,
+        result: creator_result as u8,
+        rating_delta: creator_delta,
+        stake: if lamports { room.stake_lamports } else { 0 },
+        payout: if lamports { creator_payout } else { 0 },
+        timestamp,
+    });
+    challenger_profile.record(MatchRecord {
+        room: room.key(),
+        opponent: creator_profile.wallet,
+        result: challenger_result as u8,
+        rating_delta: challenger_delta,
+        stake: if lamports { room.stake_lamports } else { 0 },
+        payout: if lamports { challenger_payout } else { 0 },
+        timestamp,
+    });
+
+    // Rooms settled while their season is live feed its leaderboard
+    if let Some(season) = season.filter(|season| season.is_live(timestamp)) {
+        for (profile, result) in [(creator_profile, creator_result), (challenger_profile, challenger_result)] {
+            let points = match result {
+                MatchResult::Win => SEASON_WIN_POINTS,
+                MatchResult::Draw => SEASON_DRAW_POINTS,
+                MatchResult::Loss => 0,
+            };
+            let total = profile.add_season_points(season.key(), points);
+            season.rank(profile.wallet, total);
+        }
+    }
+    Ok(())
+}
+
+// Elo rating change for a player with `rating` after `result` against `opponent_rating`
+pub fn elo_delta(rating: u32, opponent_rating: u32, result: MatchResult) -> i32 {
+    let diff = opponent_rating as i64 - rating as i64;
+    let bucket = (diff.unsigned_abs().min(800) / 25) as usize;
+    let expected = if diff >= 0 {
+        ELO_EXPECTED_PER_MILLE[bucket]
+    } else {
+        1000 - ELO_EXPECTED_PER_MILLE[bucket]
+    };
+    let score = match result {
+        MatchResult::Win => 1000,
+        MatchResult::Draw => 500,
+        MatchResult::Loss => 0,
+    };
+    (ELO_K_FACTOR * (score - expected) / 1000) as i32
+}
+
+// Close the NFT lock of every seat in a multi-player room, returning each lock's rent to
+// its seat. `wallets` and `locks` are in seat order.
+fn release_seat_locks<'info>(
+    room: &Account<'info, MultiRoom>,
+    wallets: &'info [AccountInfo<'info>],
+    locks: &'info [AccountInfo<'info>],
+) -> Result<()> {
+    for (i, (wallet, lock_info)) in wallets.iter().zip(locks).enumerate() {
+        require_keys_eq!(wallet.key(), room.seats[i], ErrorCode::InvalidPlacements);
+        let lock = Account::<NftLock>::try_from(lock_info)?;
+        require!(
+            lock.mint == room.seat_nfts[i] && lock.room == room.key(),
+            ErrorCode::NftLockMismatch
+        );
+        lock.close(wallet.clone())?;
+    }
+    Ok(())
+}
+
+// Send an escrowed NFT to `recipient` and close the escrow, returning its rent to the
+// player who opened it
+fn release_room_nft<'info>(
+    room: &Account<'info, Room>,
+    escrow: &Account<'info, TokenAccount>,
+    destination: &Account<'info, TokenAccount>,
+    recipient: Pubkey,
+    rent_to: &AccountInfo<'info>,
+    token_program: &Program<'info, Token>,
+) -> Result<()> {
+    require!(
+        destination.owner == recipient && destination.mint == escrow.mint,
+        ErrorCode::InvalidNftDestination
+    );
+
+    let owner = room.seed_owner();
+    let room_id = room.room_id.to_le_bytes();
+    let seeds = &[
+        room.seed_prefix(),
+        owner.as_ref(),
+        room_id.as_ref(),
+        &[room.bump],
+    ];
+    let signer = &[&seeds[..]];
+
+    let transfer_cpi_accounts = anchor_spl::token::Transfer {
+        from: escrow.to_account_info(),
+        to: destination.to_account_info(),
+        authority: room.to_account_info(),
+    };
+    let transfer_cpi_ctx = CpiContext::new_with_signer(
+        token_program.to_account_info(),
+        transfer_cpi_accounts,
+        signer,
+    );
+    anchor_spl::token::transfer(transfer_cpi_ctx, escrow.amount)?;
+
+    let close_cpi_accounts = CloseAccount {
+        account: escrow.to_account_info(),
+        destination: rent_to.clone(),
+        authority: room.to_account_info(),
+    };
+    let close_cpi_ctx = CpiContext::new_with_signer(
+        token_program.to_account_info(),
+        close_cpi_accounts,
+        signer,
+    );
+    anchor_spl::token::close_account(close_cpi_ctx)
+}
+
+// Pay token payouts and rake out of a room vault and close it, returning its rent to the creator
+fn pay_room_vault<'info>(
+    room: &Account<'info, Room>,
+    stake: &RoomStakeAccounts<'_, 'info>,
+    treasury_token: Option<&Account<'info, TokenAccount>>,
+    creator: &AccountInfo<'info>,
+    creator_payout: u64,
+    challenger_payout: u64,
+    rake: u64,
+) -> Result<()> {
+    let (Some(vault), Some(creator_token), Some(token_program)) =
+        (stake.vault, stake.creator_token, stake.token_program)
+    else {
+        return err!(ErrorCode::MissingStakeAccounts);
+    };
+    require_keys_eq!(creator_token.owner, room.creator, ErrorCode::InvalidStakeAccount);
+
+    let owner = room.seed_owner();
+    let room_id = room.room_id.to_le_bytes();
+    let seeds = &[
+        room.seed_prefix(),
+        owner.as_ref(),
+        room_id.as_ref(),
+        &[room.bump],
+    ];
+    let signer = &[&seeds[..]];
+
+    let mut payouts = vec![(creator_token, creator_payout)];
+    if challenger_payout > 0 {
+        let challenger_token = stake.challenger_token.ok_or(ErrorCode::MissingStakeAccounts)?;
+        require!(
+            Some(challenger_token.owner) == room.challenger,
+            ErrorCode::InvalidStakeAccount
+        );
+        payouts.push((challenger_token, challenger_payout));
+    }
+    if rake > 0 {
+        // Owner checked against room.treasury by the account constraints
+        payouts.push((treasury_token.ok_or(ErrorCode::MissingTreasury)?, rake));
+    }
+    for (to, amount) in payouts {
+        if amount == 0 {
+            continue;
+        }
+        let transfer_cpi_accounts = anchor_spl::token::Transfer {
+            from: vault.to_account_info(),
+            to: to.to_account_info(),
+            authority: room.to_account_info(),
+        };
+        let transfer_cpi_ctx = CpiContext::new_with_signer(
+            token_program.to_account_info(),
+            transfer_cpi_accounts,
+            signer,
+        );
+        anchor_spl::token::transfer(transfer_cpi_ctx, amount)?;
+    }
+
+    let close_cpi_accounts = CloseAccount {
+        account: vault.to_account_info(),
+        destination: creator.clone(),
+        authority: room.to_account_info(),
+    };
+    let close_cpi_ctx = CpiContext::new_with_signer(
+        token_program.to_account_info(),
+        close_cpi_accounts,
+        signer,
+    );
+    anchor_spl::token::close_account(close_cpi_ctx)
+}
+
+// Rake on a pot at `rake_bps`, limited by `rake_cap` unless it is 0
+pub fn rake_amount(pot: u64, rake_bps: u16, rake_cap: u64) -> u64 {
+    let rake = (pot as u128 * rake_bps as u128 / 10_000) as u64;
+    if rake_cap > 0 {
+        rake.min(rake_cap)
+    } else {
+        rake
+    }
+}
+
+// Check that a Token Metadata account (owner checked by the caller's constraints)
+// describes `nft_mint` as a verified item of `collection_mint`
+fn verify_collection_item(metadata_info: &AccountInfo, nft_mint: &Pubkey, collection_mint: &Pubkey) -> Result<()> {
+    let metadata: TokenMetadata = TokenMetadata::from_bytes(&metadata_info.data.borrow())?;
+    require_keys_eq!(metadata.mint, *nft_mint, ErrorCode::InvalidMetadata);
+    let collection = metadata.collection.ok_or(ErrorCode::NftNotInCollection)?;
+    require!(
+        collection.verified && collection.key == *collection_mint,
+        ErrorCode::NftNotInCollection
+    );
+    Ok(())
+}
+
+// Stats of a collection item, taken from the type it was minted as
+fn item_battle_stats(item_type: Option<&Account<ItemType>>, nft_type: Option<&Account<NftType>>) -> Result<BattleStats> {
+    let (Some(item_type), Some(nft_type)) = (item_type, nft_type) else {
+        return err!(ErrorCode::NftTypeMismatch);
+    };
+    require_keys_eq!(item_type.nft_type, nft_type.key(), ErrorCode::NftTypeMismatch);
+    Ok(nft_type.battle_stats)
+}
+
+// Simulate a battle between two heroes. The faster hero strikes first (ties go to a coin
+// flip from the seed), each strike rolls 90-110% damage and a rarity-based critical hit,
+// and defense absorbs half its value. A hero at zero HP loses; after MAX_BATTLE_ROUNDS
+// the hero with more HP left wins.
+pub fn resolve_battle(creator: &BattleStats, challenger: &BattleStats, seed: &[u8; 32]) -> GameOutcome {
+    let heroes = [creator, challenger];
+    let mut hp = heroes.map(|hero| BATTLE_BASE_HP + 2 * hero.defense as u32);
+    let creator_first = match creator.speed.cmp(&challenger.speed) {
+        std::cmp::Ordering::Greater => true,
+        std::cmp::Ordering::Less => false,
+        std::cmp::Ordering::Equal => seed[0] & 1 == 0,
+    };
+    let mut attacker = if creator_first { 0 } else { 1 };
+
+    for strike in 0..MAX_BATTLE_ROUNDS * 2 {
+        let defender = 1 - attacker;
+        let roll = anchor_lang::solana_program::hash::hashv(&[seed, &strike.to_le_bytes()]).to_bytes();
+        let mut damage = (heroes[attacker].attack as u32 + 10) * (90 + roll[0] as u32 % 21) / 100;
+        if (roll[1] as u32 % 100) < 5 + 5 * heroes[attacker].rarity as u32 {
+            damage *= 2;
+        }
+        damage = damage.saturating_sub(heroes[defender].defense as u32 / 2).max(1);
+        hp[defender] = hp[defender].saturating_sub(damage);
+        if hp[defender] == 0 {
+            return if defender == 1 { GameOutcome::CreatorWins } else { GameOutcome::ChallengerWins };
+        }
+        attacker = defender;
+    }
+
+    match hp[0].cmp(&hp[1]) {
+        std::cmp::Ordering::Greater => GameOutcome::CreatorWins,
+        std::cmp::Ordering::Less => GameOutcome::ChallengerWins,
+        std::cmp::Ordering::Equal => GameOutcome::Draw,
+    }
+}
+
+// Wallet a gameplay instruction acts for: the signer itself, or the wallet that delegated
+// `scope` to it through an unexpired session
+fn acting_wallet(signer: &Pubkey, session: Option<&Account<SessionKey>>, scope: u8) -> Result<Pubkey> {
+    let Some(session) = session else {
+        return Ok(*signer);
+    };
+    require_keys_eq!(session.session_key, *signer, ErrorCode::InvalidSession);
+    require!(session.scope & scope != 0, ErrorCode::InvalidSession);
+    require!(Clock::get()?.unix_timestamp < session.expires_at, ErrorCode::SessionExpired);
+    Ok(session.wallet)
+}
+
+// Commitment a player submits for a commit-reveal room
+pub fn room_commitment(choice: u8, salt: &[u8; 32], player: &Pubkey) -> [u8; 32] {
+    anchor_lang::solana_program::hash::hashv(&[&[choice], salt, player.as_ref()]).to_bytes()
+}
+
+// Hash of a private room's invite secret. The secret is visible on-chain once used to join.
+pub fn invite_hash(secret: &[u8; 32]) -> [u8; 32] {
+    anchor_lang::solana_program::hash::hash(secret).to_bytes()
+}
+
+pub fn name_hash(name: &str) -> [u8; 32] {
+    anchor_lang::solana_program::hash::hash(name.as_bytes()).to_bytes()
+}
+
+fn validate_name(name: &str, max_len: usize) -> Result<()> {
+    require!(!name.is_empty(), ErrorCode::EmptyName);
+    require!(name.len() <= max_len, ErrorCode::NameTooLong);
+    Ok(())
+}
+
+// Longest prefix of `name` that fits in `max_len` bytes without splitting a character
+fn truncate_name(name: &str, max_len: usize) -> &str {
+    let mut end = name.len().min(max_len);
+    while !name.is_char_boundary(end) {
+        end -= 1;
+    }
+    &name[..end]
+}
+
+// Metadata name for an item, e.g. "Hero #12", kept within the Metaplex name limit
+fn item_name(type_name: &str, number: u64) -> String {
+    let suffix = format!(" #{}", number);
+    let prefix = truncate_name(type_name, mpl_token_metadata::MAX_NAME_LENGTH - suffix.len());
+    format!("{}{}", prefix, suffix)
+}
+
+// Number of the item a metadata name was minted as by `item_name`, if it is one of this type's.
+// Token Metadata pads names with NULs, which are ignored.
+fn item_number(metadata_name: &str, type_name: &str) -> Option<u64> {
+    let name = metadata_name.trim_end_matches('\0');
+    let (_, number) = name.rsplit_once(" #")?;
+    let number: u64 = number.parse().ok()?;
+    (item_name(type_name, number) == name).then_some(number)
+}
+
+fn validate_creators(creators: &[CollectionCreator]) -> Result<()> {
+    require!(creators.len() <= mpl_token_metadata::MAX_CREATOR_LIMIT, ErrorCode::TooManyCreators);
+    if creators.is_empty() {
+        return Ok(());
+    }
+
+    let total_share: u16 = creators.iter().map(|c| c.share as u16).sum();
+    require!(total_share == 100, ErrorCode::InvalidCreatorShares);
+    for (i, creator) in creators.iter().enumerate() {
+        require!(
+            !creators[..i].iter().any(|other| other.address == creator.address),
+            ErrorCode::InvalidCreatorShares
+        );
+    }
+    Ok(())
+}
+
+// Pay a primary sale to each creator by share; rounding dust goes to the first creator.
+fn pay_creators<'info>(
+    buyer: &AccountInfo<'info>,
+    creator_accounts: &[AccountInfo<'info>],
+    creators: &[CollectionCreator],
+    price: u64,
+) -> Result<()> {
+    require!(creator_accounts.len() == creators.len(), ErrorCode::InvalidCreatorAccounts);
+
+    let amounts: Vec<u64> = creators
+        .iter()
+        .map(|c| (price as u128 * c.share as u128 / 100) as u64)
+        .collect();
+    let dust = price - amounts.iter().sum::<u64>();
+
+    for (i, (creator, account)) in creators.iter().zip(creator_accounts).enumerate() {
+        require_keys_eq!(account.key(), creator.address, ErrorCode::InvalidCreatorAccounts);
+
+        let amount = if i == 0 { amounts[i] + dust } else { amounts[i] };
+        if amount == 0 {
+            continue;
+        }
+
+        let transfer_ix = anchor_lang::solana_program::system_instruction::transfer(
+            buyer.key,
+            account.key,
+            amount,
+        );
+        anchor_lang::solana_program::program::invoke(
+            &transfer_ix,
+            &[buyer.clone(), account.clone()],
+        )?;
+    }
+    Ok(())
+}
+
+// Account Structures
+#[derive(Accounts)]
+pub struct InitializeMarketplace<'info> {
+    #[account(
+        init,
+        payer = admin,
+        space = Marketplace::space(),
+        seeds = [b"marketplace"],
+        bump
+    )]
+    pub marketplace: Account<'info, Marketplace>,
+    
+    #[account(mut)]
+    pub admin: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct MigrateMarketplace<'info> {
+    /// CHECK: Marketplace in a possibly older layout; discriminator and admin checked in handler
+    #[account(mut, seeds = [b"marketplace"], bump, owner = crate::ID)]
+    pub marketplace: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub admin: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateMarketplaceConfig<'info> {
+    #[account(
+        mut,
+        seeds = [b"marketplace"],
+        bump = marketplace.bump,
+        has_one = admin,
+    )]
+    pub marketplace: Account<'info, Marketplace>,
+
+    pub admin: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(creator: Pubkey)]
+pub struct AddAllowedCreator<'info> {
+    #[account(
+        seeds = [b"marketplace"],
+        bump = marketplace.bump,
+        has_one = admin,
+    )]
+    pub marketplace: Account<'info, Marketplace>,
+
+    #[account(
+        init,
+        payer = admin,
+        space = AllowedCreator::space(),
+        seeds = [b"allowed_creator", marketplace.key().as_ref(), creator.as_ref()],
+        bump
+    )]
+    pub allowed_creator: Account<'info, AllowedCreator>,
+
+    #[account(mut)]
+    pub admin: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct RemoveAllowedCreator<'info> {
+    #[account(
+        seeds = [b"marketplace"],
+        bump = marketplace.bump,
+        has_one = admin,
+    )]
+    pub marketplace: Account<'info, Marketplace>,
+
+    #[account(
+        mut,
+        close = admin,
+        seeds = [b"allowed_creator", marketplace.key().as_ref(), allowed_creator.creator.as_ref()],
+        bump = allowed_creator.bump
+    )]
+    pub allowed_creator: Account<'info, AllowedCreator>,
+
+    #[account(mut)]
+    pub admin: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(wallet: Pubkey)]
+pub struct AddRakeExemption<'info> {
+    #[account(
+        seeds = [b"marketplace"],
+        bump = marketplace.bump,
+        has_one = admin,
+    )]
+    pub marketplace: Account<'info, Marketplace>,
+
+    #[account(
+        init,
+        payer = admin,
+        space = RakeExemption::space(),
+        seeds = [b"rake_exempt", marketplace.key().as_ref(), wallet.as_ref()],
+        bump
+    )]
+    pub rake_exemption: Account<'info, RakeExemption>,
+
+    #[account(mut)]
+    pub admin: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct RemoveRakeExemption<'info> {
+    #[account(
+        seeds = [b"marketplace"],
+        bump = marketplace.bump,
+        has_one = admin,
+    )]
+    pub marketplace: Account<'info, Marketplace>,
+
+    #[account(
+        mut,
+        close = admin,
+        seeds = [b"rake_exempt", marketplace.key().as_ref(), rake_exemption.wallet.as_ref()],
+        bump = rake_exemption.bump
+    )]
+    pub rake_exemption: Account<'info, RakeExemption>,
+
+    #[account(mut)]
+    pub admin: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetCollectionVerified<'info> {
+    #[account(
+        seeds = [b"marketplace"],
+        bump = marketplace.bump,
+        has_one = admin,
+    )]
+    pub marketplace: Account<'info, Marketplace>,
+
+    #[account(
+        mut,
+        seeds = [b"collection", collection.name_seed().as_ref()],
+        bump = collection.bump,
+    )]
+    pub collection: Account<'info, NFTCollection>,
+
+    pub admin: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(collection_name: String)]
+pub struct CreateNFTCollection<'info> {
+    #[account(
+        mut,
+        seeds = [b"marketplace"],
+        bump = marketplace.bump
+    )]
+    pub marketplace: Box<Account<'info, Marketplace>>,
+
+    #[account(
+        init,
+        payer = admin,
+        space = NFTCollection::space(collection_name.len()),
+        seeds = [b"collection", name_hash(&collection_name).as_ref()],
+        bump
+    )]
+    pub collection: Box<Account<'info, NFTCollection>>,
+
+    // Required when the policy is Allowlisted and the creator is not the marketplace admin
+    #[account(
+        seeds = [b"allowed_creator", marketplace.key().as_ref(), admin.key().as_ref()],
+        bump = allowed_creator.bump
+    )]
+    pub allowed_creator: Option<Account<'info, AllowedCreator>>,
+
+    /// CHECK: Marketplace treasury receiving the listing fee (Permissionless policy)
+    #[account(mut, address = marketplace.treasury)]
+    pub treasury: Option<UncheckedAccount<'info>>,
+
+    #[account(
+        init,
+        payer = admin,
+        space = CollectionIndex::space(),
+        seeds = [
+            b"collection_index",
+            marketplace.key().as_ref(),
+            &marketplace.total_collections.to_le_bytes(),
+        ],
+        bump
+    )]
+    pub collection_index: Box<Account<'info, CollectionIndex>>,
+
+    // Reverse entry so the collection cannot be indexed twice
+    #[account(
+        init,
+        payer = admin,
+        space = IndexedEntry::space(),
+        seeds = [b"collection_indexed", collection.key().as_ref()],
+        bump
+    )]
+    pub collection_indexed: Box<Account<'info, IndexedEntry>>,
+
+    #[account(
+        init,
+        payer = admin,
+        mint::decimals = 0,
+        mint::authority = admin,
+        mint::freeze_authority = admin,
+    )]
+    pub collection_mint: Account<'info, Mint>,
+
+    #[account(
+        init_if_needed,
+        payer = admin,
+        associated_token::mint = collection_mint,
+        associated_token::authority = admin,
+    )]
+    pub admin_token_account: Account<'info, TokenAccount>,
+
+    /// CHECK: Metadata account
+    #[account(
+        mut,
+        seeds = [
+            b"metadata",
+            token_metadata_program.key().as_ref(),
+            collection_mint.key().as_ref(),
+        ],
+        bump,
+        seeds::program = token_metadata_program.key(),
+    )]
+    pub collection_metadata: UncheckedAccount<'info>,
+
+    /// CHECK: Master edition account
+    #[account(
+        mut,
+        seeds = [
+            b"metadata",
+            token_metadata_program.key().as_ref(),
+            collection_mint.key().as_ref(),
+            b"edition",
+        ],
+        bump,
+        seeds::program = token_metadata_program.key(),
+    )]
+    pub collection_master_edition: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub admin: Signer<'info>,
+    pub system_program: Program<'info, System>,
+    pub token_program: Program<'info, Token>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    /// CHECK: Token Metadata Program
+    #[account(constraint = token_metadata_program.key() == mpl_token_metadata::ID)]
+    pub token_metadata_program: UncheckedAccount<'info>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+pub struct MigrateCollection<'info> {
+    /// CHECK: Collection in a possibly older layout; discriminator, seeds and admin checked in handler
+    #[account(mut, owner = crate::ID)]
+    pub collection: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub admin: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct MigrateNftType<'info> {
+    #[account(
+        seeds = [b"collection", collection.name_seed().as_ref()],
+        bump = collection.bump,
+        has_one = admin,
+    )]
+    pub collection: Account<'info, NFTCollection>,
+
+    /// CHECK: NFT type in a possibly older layout; discriminator, collection and seeds checked in handler
+    #[account(mut, owner = crate::ID)]
+    pub nft_type: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub admin: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct MigrateCollectionToSized<'info> {
+    /// CHECK: Collection in a possibly older layout; discriminator, seeds and admin checked in handler
+    #[account(mut, owner = crate::ID)]
+    pub collection: UncheckedAccount<'info>,
+
+    /// CHECK: Collection mint account (must match stored collection.mint, checked in handler)
+    pub collection_mint: UncheckedAccount<'info>,
+
+    /// CHECK: Collection metadata PDA
+    #[account(
+        mut,
+        seeds = [
+            b"metadata",
+            token_metadata_program.key().as_ref(),
+            collection_mint.key().as_ref(),
+        ],
+        bump,
+        seeds::program = token_metadata_program.key(),
+    )]
+    pub collection_metadata: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub admin: Signer<'info>,
+    /// CHECK: Token Metadata Program
+    #[account(constraint = token_metadata_program.key() == mpl_token_metadata::ID)]
+    pub token_metadata_program: UncheckedAccount<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(type_name: String)]
+pub struct CreateNFTType<'info> {
+    #[account(
+        mut,
+        seeds = [b"collection", collection.name_seed().as_ref()],
+        bump = collection.bump,
+    )]
+    pub collection: Account<'info, NFTCollection>,
+
+    #[account(
+        init,
+        payer = admin,
+        space = NftType::space(type_name.len()),
+        seeds = [b"type", collection.key().as_ref(), name_hash(&type_name).as_ref()],
+        bump,
+    )]
+    pub nft_type: Account<'info, NftType>,
+
+    #[account(
+        init,
+        payer = admin,
+        space = NftTypeIndex::space(),
+        seeds = [
+            b"type_index",
+            collection.key().as_ref(),
+            &collection.total_types.to_le_bytes(),
+        ],
+        bump,
+    )]
+    pub type_index: Account<'info, NftTypeIndex>,
+
+    // Reverse entry so the type cannot be indexed twice
+    #[account(
+        init,
+        payer = admin,
+        space = IndexedEntry::space(),
+        seeds = [b"type_indexed", nft_type.key().as_ref()],
+        bump
+    )]
+    pub type_indexed: Box<Account<'info, IndexedEntry>>,
+
+    #[account(mut, constraint = admin.key() == collection.admin)]
+    pub admin: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SetBattleStats<'info> {
+    #[account(
+        seeds = [b"collection", collection.name_seed().as_ref()],
+        bump = collection.bump,
+    )]
+    pub collection: Account<'info, NFTCollection>,
+
+    #[account(
+        mut,
+        seeds = [b"type", collection.key().as_ref(), nft_type.name_seed().as_ref()],
+        bump = nft_type.bump,
+        constraint = nft_type.collection == collection.key(),
+    )]
+    pub nft_type: Account<'info, NftType>,
+
+    #[account(constraint = admin.key() == collection.admin)]
+    pub admin: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(index: u64)]
+pub struct IndexExistingCollection<'info> {
+    #[account(
+        seeds = [b"marketplace"],
+        bump = marketplace.bump,
+        has_one = admin,
+    )]
+    pub marketplace: Account<'info, Marketplace>,
+
+    /// CHECK: Collection in a possibly older layout; discriminator and seeds checked in handler
+    #[account(mut, owner = crate::ID)]
+    pub collection: UncheckedAccount<'info>,
+
+    #[account(
+        init,
+        payer = admin,
+        space = CollectionIndex::space(),
+        seeds = [b"collection_index", marketplace.key().as_ref(), &index.to_le_bytes()],
+        bump
+    )]
+    pub collection_index: Account<'info, CollectionIndex>,
+
+    // Reverse entry so the collection cannot be indexed twice
+    #[account(
+        init,
+        payer = admin,
+        space = IndexedEntry::space(),
+        seeds = [b"collection_indexed", collection.key().as_ref()],
+        bump
+    )]
+    pub collection_indexed: Box<Account<'info, IndexedEntry>>,
+
+    #[account(mut)]
+    pub admin: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct IndexExistingNftType<'info> {
+    #[account(
+        mut,
+        seeds = [b"collection", collection.name_seed().as_ref()],
+        bump = collection.bump,
+        has_one = admin,
+    )]
+    pub collection: Account<'info, NFTCollection>,
+
+    #[account(
+        seeds = [b"type", collection.key().as_ref(), nft_type.name_seed().as_ref()],
+        bump = nft_type.bump,
+        constraint = nft_type.collection == collection.key(),
+    )]
+    pub nft_type: Account<'info, NftType>,
+
+    #[account(
+        init,
+        payer = admin,
+        space = NftTypeIndex::space(),
+        seeds = [
+            b"type_index",
+            collection.key().as_ref(),
+            &collection.total_types.to_le_bytes(),
+        ],
+        bump,
+    )]
+    pub type_index: Account<'info, NftTypeIndex>,
+
+    // Reverse entry so the type cannot be indexed twice
+    #[account(
+        init,
+        payer = admin,
+        space = IndexedEntry::space(),
+        seeds = [b"type_indexed", nft_type.key().as_ref()],
+        bump
+    )]
+    pub type_indexed: Box<Account<'info, IndexedEntry>>,
+
+    #[account(mut)]
+    pub admin: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct BackfillItemType<'info> {
+    #[account(
+        seeds = [b"collection", collection.name_seed().as_ref()],
+        bump = collection.bump,
+        has_one = admin,
+    )]
+    pub collection: Account<'info, NFTCollection>,
+
+    #[account(
+        seeds = [b"type", collection.key().as_ref(), nft_type.name_seed().as_ref()],
+        bump = nft_type.bump,
+        constraint = nft_type.collection == collection.key() @ ErrorCode::NftTypeMismatch,
+    )]
+    pub nft_type: Account<'info, NftType>,
+
+    pub nft_mint: Account<'info, Mint>,
+
+    /// CHECK: Metadata account of the item mint, owned by Token Metadata
+    #[account(
+        owner = mpl_token_metadata::ID @ ErrorCode::InvalidMetadata,
+        seeds = [
+            b"metadata",
+            token_metadata_program.key().as_ref(),
+            nft_mint.key().as_ref(),
+        ],
+        bump,
+        seeds::program = token_metadata_program.key(),
+    )]
+    pub nft_metadata: UncheckedAccount<'info>,
+
+    #[account(
+        init,
+        payer = admin,
+        space = ItemType::space(),
+        seeds = [b"item_type", nft_mint.key().as_ref()],
+        bump
+    )]
+    pub item_type: Account<'info, ItemType>,
+
+    #[account(mut)]
+    pub admin: Signer<'info>,
+    /// CHECK: Token Metadata Program
+    #[account(constraint = token_metadata_program.key() == mpl_token_metadata::ID)]
+    pub token_metadata_program: UncheckedAccount<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(type_name: String)]
+pub struct MintNFTFromCollection<'info> {
+    #[account(
+        mut,
+        seeds = [b"collection", collection.name_seed().as_ref()],
+        bump = collection.bump,
+    )]
+    pub collection: Account<'info, NFTCollection>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"type",
+            collection.key().as_ref(),
+            nft_type.name_seed().as_ref(),
+        ],
+        bump = nft_type.bump,
+        constraint = nft_type.collection == collection.key(),
+        constraint = nft_type.name == type_name,
+    )]
+    pub nft_type: Account<'info, NftType>,
+
+    #[account(
+        init,
+        payer = buyer,
+        mint::decimals = 0,
+        mint::authority = collection.admin,
+    )]
+    pub nft_mint: Account<'info, Mint>,
+
+    // Records the type this item is minted as
+    #[account(
+        init,
+        payer = buyer,
+        space = ItemType::space(),
+        seeds = [b"item_type", nft_mint.key().as_ref()],
+        bump
+    )]
+    pub item_type: Box<Account<'info, ItemType>>,
+
+    #[account(
+        init_if_needed,
+        payer = buyer,
+        associated_token::mint = nft_mint,
+        associated_token::authority = buyer,
+    )]
+    pub buyer_token_account: Account<'info, TokenAccount>,
+
+    /// CHECK: NFT Metadata account
+    #[account(
+        mut,
+        seeds = [
+            b"metadata",
+            token_metadata_program.key().as_ref(),
+            nft_mint.key().as_ref(),
+        ],
+        bump,
+        seeds::program = token_metadata_program.key(),
+    )]
+    pub nft_metadata: UncheckedAccount<'info>,
+
+    /// CHECK: Collection metadata PDA (for the collection mint)
+    #[account(
+        mut,
+        seeds = [
+            b"metadata",
+            token_metadata_program.key().as_ref(),
+            collection_mint_account.key().as_ref(),
+        ],
+        bump,
+        seeds::program = token_metadata_program.key(),
+    )]
+    pub collection_metadata: UncheckedAccount<'info>,
+
+    /// CHECK: Collection master edition PDA
+    #[account(
+        mut,
+        seeds = [
+            b"metadata",
+            token_metadata_program.key().as_ref(),
+            collection_mint_account.key().as_ref(),
+            b"edition",
+        ],
+        bump,
+        seeds::program = token_metadata_program.key(),
+    )]
+    pub collection_master_edition: UncheckedAccount<'info>,
+
+    /// CHECK: Collection mint account (must match stored collection.mint)
+    #[account(constraint = collection_mint_account.key() == collection.mint)]
+    pub collection_mint_account: UncheckedAccount<'info>,
+
+    /// CHECK: Collection admin (receives payment) and authority to verify collection
+    #[account(mut, constraint = collection_admin.key() == collection.admin)]
+    pub collection_admin: Signer<'info>,
+
+    #[account(mut)]
+    pub buyer: Signer<'info>,
+    pub system_program: Program<'info, System>,
+    pub token_program: Program<'info, Token>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    /// CHECK: Token Metadata Program
+    pub token_metadata_program: UncheckedAccount<'info>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+#[instruction(type_name: String)]
+pub struct MintProgrammableNFTFromCollection<'info> {
+    #[account(
+        seeds = [b"collection", collection.name_seed().as_ref()],
+        bump = collection.bump,
+    )]
+    pub collection: Box<Account<'info, NFTCollection>>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"type",
+            collection.key().as_ref(),
+            nft_type.name_seed().as_ref(),
+        ],
+        bump = nft_type.bump,
+        constraint = nft_type.collection == collection.key(),
+        constraint = nft_type.name == type_name,
+    )]
+    pub nft_type: Box<Account<'info, NftType>>,
+
+    /// CHECK: New mint keypair, initialized by Token Metadata CreateV1
+    #[account(mut)]
+    pub nft_mint: Signer<'info>,
+
+    // Records the type this item is minted as
+    #[account(
+        init,
+        payer = buyer,
+        space = ItemType::space(),
+        seeds = [b"item_type", nft_mint.key().as_ref()],
+        bump
+    )]
+    pub item_type: Box<Account<'info, ItemType>>,
+
+    /// CHECK: Buyer's associated token account, created by Token Metadata MintV1
+    #[account(
+        mut,
+        address = anchor_spl::associated_token::get_associated_token_address(&buyer.key(), &nft_mint.key()),
+    )]
+    pub buyer_token_account: UncheckedAccount<'info>,
+
+    /// CHECK: NFT Metadata account
+    #[account(
+        mut,
+        seeds = [
+            b"metadata",
+            token_metadata_program.key().as_ref(),
+            nft_mint.key().as_ref(),
+        ],
+        bump,
+        seeds::program = token_metadata_program.key(),
+    )]
+    pub nft_metadata: UncheckedAccount<'info>,
+
+    /// CHECK: NFT master edition account
+    #[account(
+        mut,
+        seeds = [
+            b"metadata",
+            token_metadata_program.key().as_ref(),
+            nft_mint.key().as_ref(),
+            b"edition",
+        ],
+        bump,
+        seeds::program = token_metadata_program.key(),
+    )]
+    pub nft_master_edition: UncheckedAccount<'info>,
+
+    /// CHECK: Token record of the buyer's token account
+    #[account(
+        mut,
+        seeds = [
+            b"metadata",
+            token_metadata_program.key().as_ref(),
+            nft_mint.key().as_ref(),
+            b"token_record",
+            buyer_token_account.key().as_ref(),
+        ],
+        bump,
+        seeds::program = token_metadata_program.key(),
+    )]
+    pub token_record: UncheckedAccount<'info>,
+
+    /// CHECK: Collection metadata PDA (for the collection mint)
+    #[account(
+        mut,
+        seeds = [
+            b"metadata",
+            token_metadata_program.key().as_ref(),
+            collection_mint_account.key().as_ref(),
+        ],
+        bump,
+        seeds::program = token_metadata_program.key(),
+    )]
+    pub collection_metadata: UncheckedAccount<'info>,
+
+    /// CHECK: Collection master edition PDA
+    #[account(
+        seeds = [
+            b"metadata",
+            token_metadata_program.key().as_ref(),
+            collection_mint_account.key().as_ref(),
+            b"edition",
+        ],
+        bump,
+        seeds::program = token_metadata_program.key(),
+    )]
+    pub collection_master_edition: UncheckedAccount<'info>,
+
+    /// CHECK: Collection mint account (must match stored collection.mint)
+    #[account(constraint = collection_mint_account.key() == collection.mint)]
+    pub collection_mint_account: UncheckedAccount<'info>,
+
+    /// CHECK: Collection admin (receives payment), update authority and authority to verify collection
+    #[account(mut, constraint = collection_admin.key() == collection.admin)]
+    pub collection_admin: Signer<'info>,
+
+    #[account(mut)]
+    pub buyer: Signer<'info>,
+    pub system_program: Program<'info, System>,
+    pub token_program: Program<'info, Token>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    /// CHECK: Token Metadata Program
+    #[account(constraint = token_metadata_program.key() == mpl_token_metadata::ID)]
+    pub token_metadata_program: UncheckedAccount<'info>,
+    /// CHECK: Instructions sysvar
+    #[account(address = sysvar_instructions::ID)]
+    pub sysvar_instructions: UncheckedAccount<'info>,
+    /// CHECK: Token Auth Rules program, required when the type has a rule set
+    #[account(address = TOKEN_AUTH_RULES_PROGRAM_ID)]
+    pub authorization_rules_program: Option<UncheckedAccount<'info>>,
+    /// CHECK: Rule set account, must match nft_type.rule_set (checked in handler)
+    pub authorization_rules: Option<UncheckedAccount<'info>>,
+}
+
+#[derive(Accounts)]
+#[instruction(wallet: Pubkey)]
+pub struct CreatePlayerProfile<'info> {
+	#[account(
+		init,
+		payer = payer,
+		space = PlayerProfile::space(),
+		seeds = [b"profile", wallet.as_ref()],
+		bump
+	)]
+	pub profile: Box<Account<'info, PlayerProfile>>,
+
+	#[account(mut)]
+	pub payer: Signer<'info>,
+	pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(room_id: u64)]
+pub struct CreateRoom<'info> {
+	#[account(seeds = [b"marketplace"], bump = marketplace.bump)]
+	pub marketplace: Account<'info, Marketplace>,
+
+	#[account(
+		init,
+		payer = creator,
+		space = Room::space(None),
+		seeds = [b"room", creator.key().as_ref(), &room_id.to_le_bytes()],
+		bump
+	)]
+	pub room: Account<'info, Room>,
+
+	#[account(mut)]
+	pub creator: Signer<'info>,
+
+	#[account(
+		init_if_needed,
+		payer = creator,
+		space = PlayerProfile::space(),
+		seeds = [b"profile", creator.key().as_ref()],
+		bump
+	)]
+	pub creator_profile: Box<Account<'info, PlayerProfile>>,
+
+	// Present if the creator is exempt from the room rake
+	#[account(
+		seeds = [b"rake_exempt", marketplace.key().as_ref(), creator.key().as_ref()],
+		bump = rake_exemption.bump
+	)]
+	pub rake_exemption: Option<Box<Account<'info, RakeExemption>>>,
+
+	// Registered collection the room is gated by
+	#[account(
+		seeds = [b"collection", collection.name_seed().as_ref()],
+		bump = collection.bump,
+		constraint = collection.verified @ ErrorCode::CollectionNotVerified,
+	)]
+	pub collection: Box<Account<'info, NFTCollection>>,
+
+	/// CHECK: Mint of an NFT the creator owns
+	pub nft_mint: Account<'info, Mint>,
+
+	/// CHECK: Metadata account of the provided NFT mint, owned by Token Metadata
+	#[account(
+		owner = mpl_token_metadata::ID @ ErrorCode::InvalidMetadata,
+		seeds = [
+			b"metadata",
+			token_metadata_program.key().as_ref(),
+			nft_mint.key().as_ref(),
+		],
+		bump,
+		seeds::program = token_metadata_program.key(),
+	)]
+	pub nft_metadata: UncheckedAccount<'info>,
+
+	// Battle rooms only: type the gating NFT was minted as, whose stats it fights with
+	#[account(constraint = nft_type.collection == collection.key() @ ErrorCode::NftTypeMismatch)]
+	pub nft_type: Option<Box<Account<'info, NftType>>>,
+	#[account(seeds = [b"item_type", nft_mint.key().as_ref()], bump = item_type.bump)]
+	pub item_type: Option<Box<Account<'info, ItemType>>>,
+
+	#[account(
+		mut,
+		constraint = creator_nft_token.owner == creator.key(),
+		constraint = creator_nft_token.mint == nft_mint.key(),
+	)]
+	pub creator_nft_token: Account<'info, TokenAccount>,
+
+	// Held while the NFT gates this room so it cannot gate another at the same time
+	#[account(
+		init,
+		payer = creator,
+		space = NftLock::space(),
+		seeds = [b"nft_lock", nft_mint.key().as_ref()],
+		bump
+	)]
+	pub creator_nft_lock: Box<Account<'info, NftLock>>,
+
+	// NFT-wager rooms only: program-owned escrow for the creator's NFT
+	#[account(
+		init,
+		payer = creator,
+		token::mint = nft_mint,
+		token::authority = room,
+		seeds = [b"room_nft", room.key().as_ref(), nft_mint.key().as_ref()],
+		bump
+	)]
+	pub creator_nft_escrow: Option<Box<Account<'info, TokenAccount>>>,
+
+	// Token-staked rooms only: the stake mint, the room vault and the creator's source account
+	pub stake_mint: Option<Box<Account<'info, Mint>>>,
+	#[account(
+		init,
+		payer = creator,
+		token::mint = stake_mint,
+		token::authority = room,
+		seeds = [b"room_vault", room.key().as_ref()],
+		bump
+	)]
+	pub room_vault: Option<Box<Account<'info, TokenAccount>>>,
+	#[account(mut)]
+	pub creator_stake_token: Option<Box<Account<'info, TokenAccount>>>,
+	pub token_program: Option<Program<'info, Token>>,
+
+	pub system_program: Program<'info, System>,
+	/// CHECK: Token Metadata Program
+	#[account(address = mpl_token_metadata::ID)]
+	pub token_metadata_program: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct JoinRoom<'info> {
+	#[account(mut, has_one = creator, seeds = [room.seed_prefix(), room.seed_owner().as_ref(), &room.room_id.to_le_bytes()], bump = room.bump)]
+	pub room: Account<'info, Room>,
+
+	/// CHECK: only used as seed and authority check
+	pub creator: UncheckedAccount<'info>,
+
+	#[account(mut)]
+	pub challenger: Signer<'info>,
+
+	#[account(
+		init_if_needed,
+		payer = challenger,
+		space = PlayerProfile::space(),
+		seeds = [b"profile", challenger.key().as_ref()],
+		bump
+	)]
+	pub challenger_profile: Box<Account<'info, PlayerProfile>>,
+
+	// Must be the collection the room was created with
+	#[account(
+		address = room.collection @ ErrorCode::CollectionMismatch,
+		constraint = collection.verified @ ErrorCode::CollectionNotVerified,
+	)]
+	pub collection: Box<Account<'info, NFTCollection>>,
+
+	/// CHECK: Mint of an NFT the challenger owns
+	pub nft_mint: Account<'info, Mint>,
+
+	/// CHECK: Metadata account of the provided NFT mint, owned by Token Metadata
+	#[account(
+		owner = mpl_token_metadata::ID @ ErrorCode::InvalidMetadata,
+		seeds = [
+			b"metadata",
+			token_metadata_program.key().as_ref(),
+			nft_mint.key().as_ref(),
+		],
+		bump,
+		seeds::program = token_metadata_program.key(),
+	)]
+	pub nft_metadata: UncheckedAccount<'info>,
+
+	// Battle rooms only: type the gating NFT was minted as, whose stats it fights with
+	#[account(constraint = nft_type.collection == collection.key() @ ErrorCode::NftTypeMismatch)]
+	pub nft_type: Option<Box<Account<'info, NftType>>>,
+	#[account(seeds = [b"item_type", nft_mint.key().as_ref()], bump = item_type.bump)]
+	pub item_type: Option<Box<Account<'info, ItemType>>>,
+
+	#[account(
+		mut,
+		constraint = challenger_nft_token.owner == challenger.key(),
+		constraint = challenger_nft_token.mint == nft_mint.key(),
+	)]
+	pub challenger_nft_token: Account<'info, TokenAccount>,
+
+	// Held while the NFT gates this room so it cannot gate another at the same time
+	#[account(
+		init,
+		payer = challenger,
+		space = NftLock::space(),
+		seeds = [b"nft_lock", nft_mint.key().as_ref()],
+		bump
+	)]
+	pub challenger_nft_lock: Box<Account<'info, NftLock>>,
+
+	// NFT-wager rooms only: program-owned escrow for the challenger's NFT
+	#[account(
+		init,
+		payer = challenger,
+		token::mint = nft_mint,
+		token::authority = room,
+		seeds = [b"room_nft", room.key().as_ref(), nft_mint.key().as_ref()],
+		bump
+	)]
+	pub challenger_nft_escrow: Option<Box<Account<'info, TokenAccount>>>,
+
+	// Token-staked rooms only
+	#[account(mut, seeds = [b"room_vault", room.key().as_ref()], bump)]
+	pub room_vault: Option<Box<Account<'info, TokenAccount>>>,
+	#[account(mut)]
+	pub challenger_stake_token: Option<Box<Account<'info, TokenAccount>>>,
+	pub token_program: Option<Program<'info, Token>>,
+
+	pub system_program: Program<'info, System>,
+	/// CHECK: Token Metadata Program
+	#[account(address = mpl_token_metadata::ID)]
+	pub token_metadata_program: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct CreateSession<'info> {
+	#[account(
+		init_if_needed,
+		payer = wallet,
+		space = SessionKey::space(),
+		seeds = [b"session", wallet.key().as_ref()],
+		bump
+	)]
+	pub session: Account<'info, SessionKey>,
+
+	#[account(mut)]
+	pub wallet: Signer<'info>,
+	pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct RevokeSession<'info> {
+	#[account(
+		mut,
+		close = wallet,
+		has_one = wallet,
+		seeds = [b"session", wallet.key().as_ref()],
+		bump = session.bump
+	)]
+	pub session: Account<'info, SessionKey>,
+
+	#[account(mut)]
+	pub wallet: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct CommitChoice<'info> {
+	#[account(
+		mut,
+		seeds = [room.seed_prefix(), room.seed_owner().as_ref(), &room.room_id.to_le_bytes()],
+		bump = room.bump
+	)]
+	pub room: Account<'info, Room>,
+
+	pub player: Signer<'info>,
+
+	// Present when `player` is a session key acting for a wallet
+	#[account(seeds = [b"session", session.wallet.as_ref()], bump = session.bump)]
+	pub session: Option<Account<'info, SessionKey>>,
+}
+
+#[derive(Accounts)]
+pub struct Rematch<'info> {
+	#[account(
+		mut,
+		has_one = creator,
+		seeds = [room.seed_prefix(), room.seed_owner().as_ref(), &room.room_id.to_le_bytes()],
+		bump = room.bump
+	)]
+	pub room: Account<'info, Room>,
+
+	#[account(mut)]
+	pub creator: Signer<'info>,
+
+	#[account(seeds = [b"marketplace"], bump = marketplace.bump)]
+	pub marketplace: Box<Account<'info, Marketplace>>,
+
+	// Present if the creator is exempt from the room rake
+	#[account(
+		seeds = [b"rake_exempt", marketplace.key().as_ref(), creator.key().as_ref()],
+		bump = rake_exemption.bump
+	)]
+	pub rake_exemption: Option<Box<Account<'info, RakeExemption>>>,
+
+	// The room's collection must still be verified
+	#[account(
+		address = room.collection @ ErrorCode::CollectionMismatch,
+		constraint = collection.verified @ ErrorCode::CollectionNotVerified,
+	)]
+	pub collection: Box<Account<'info, NFTCollection>>,
+
+	// Creator must still hold the NFT that gated the room
+	#[account(
+		constraint = creator_nft_token.owner == creator.key(),
+		constraint = creator_nft_token.mint == room.creator_nft,
+	)]
+	pub creator_nft_token: Account<'info, TokenAccount>,
+
+	#[account(
+		init,
+		payer = creator,
+		space = NftLock::space(),
+		seeds = [b"nft_lock", room.creator_nft.as_ref()],
+		bump
+	)]
+	pub creator_nft_lock: Box<Account<'info, NftLock>>,
+	pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct CloseRoom<'info> {
+	#[account(
+		mut,
+		close = creator,
+		has_one = creator,
+		seeds = [room.seed_prefix(), room.seed_owner().as_ref(), &room.room_id.to_le_bytes()],
+		bump = room.bump,
+		constraint = room.status == RoomStatus::Closed as u8 @ ErrorCode::RoomNotClosed,
+		constraint = !room.bet_pool_open @ ErrorCode::BetPoolUnsettled,
+		constraint = room.tournament.is_none() @ ErrorCode::TournamentRoom,
+	)]
+	pub room: Account<'info, Room>,
+
+	#[account(mut)]
+	pub creator: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct CreateBetPool<'info> {
+	#[account(seeds = [b"marketplace"], bump = marketplace.bump)]
+	pub marketplace: Box<Account<'info, Marketplace>>,
+
+	#[account(
+		mut,
+		seeds = [room.seed_prefix(), room.seed_owner().as_ref(), &room.room_id.to_le_bytes()],
+		bump = room.bump
+	)]
+	pub room: Account<'info, Room>,
+
+	#[account(
+		init,
+		payer = payer,
+		space = BetPool::space(),
+		seeds = [b"bet_pool", room.key().as_ref()],
+		bump
+	)]
+	pub bet_pool: Account<'info, BetPool>,
+
+	#[account(mut)]
+	pub payer: Signer<'info>,
+	pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct PlaceBet<'info> {
+	#[account(
+		seeds = [room.seed_prefix(), room.seed_owner().as_ref(), &room.room_id.to_le_bytes()],
+		bump = room.bump
+	)]
+	pub room: Account<'info, Room>,
+
+	#[account(
+		mut,
+		has_one = room,
+		seeds = [b"bet_pool", room.key().as_ref()],
+		bump = bet_pool.bump
+	)]
+	pub bet_pool: Account<'info, BetPool>,
+
+	#[account(
+		init_if_needed,
+		payer = bettor,
+		space = Bet::space(),
+		seeds = [b"bet", bet_pool.key().as_ref(), bettor.key().as_ref()],
+		bump
+	)]
+	pub bet: Account<'info, Bet>,
+
+	#[account(mut)]
+	pub bettor: Signer<'info>,
+	pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SettleBetPool<'info> {
+	#[account(
+		mut,
+		seeds = [room.seed_prefix(), room.seed_owner().as_ref(), &room.room_id.to_le_bytes()],
+		bump = room.bump
+	)]
+	pub room: Account<'info, Room>,
+
+	#[account(
+		mut,
+		has_one = room,
+		seeds = [b"bet_pool", room.key().as_ref()],
+		bump = bet_pool.bump
+	)]
+	pub bet_pool: Account<'info, BetPool>,
+
+	/// CHECK: Pool treasury, receives the house fee
+	#[account(mut, address = bet_pool.treasury @ ErrorCode::Unauthorized)]
+	pub treasury: Option<UncheckedAccount<'info>>,
+}
+
+#[derive(Accounts)]
+pub struct ClaimBet<'info> {
+	#[account(mut, seeds = [b"bet_pool", bet_pool.room.as_ref()], bump = bet_pool.bump)]
+	pub bet_pool: Account<'info, BetPool>,
+
+	#[account(
+		mut,
+		close = bettor,
+		has_one = bettor,
+		seeds = [b"bet", bet_pool.key().as_ref(), bettor.key().as_ref()],
+		bump = bet.bump
+	)]
+	pub bet: Account<'info, Bet>,
+
+	/// CHECK: Bettor, receives the payout and the bet's rent
+	#[account(mut)]
+	pub bettor: UncheckedAccount<'info>,
+}
 
-            let transfer_cpi_accounts = anchor_spl::token::Transfer {
-                from: ctx.accounts.reward_token_vault.to_account_info(),
-                to: ctx.accounts.staker_reward_token_account.to_account_info(),
-                authority: ctx.accounts.stake_pool.to_account_info(),
-            };
-            let transfer_cpi_ctx = CpiContext::new_with_signer(
-                ctx.accounts.token_program.to_account_info(),
-                transfer_cpi_accounts,
-                signer,
-            );
-            anchor_spl::token::transfer(transfer_cpi_ctx, rewards)?;
-        }
+#[derive(Accounts)]
+pub struct CloseBetPool<'info> {
+	#[account(
+		mut,
+		close = payer,
+		has_one = payer,
+		seeds = [b"bet_pool", bet_pool.room.as_ref()],
+		bump = bet_pool.bump
+	)]
+	pub bet_pool: Account<'info, BetPool>,
 
-        // Transfer NFT back from vault to staker
-        let nft_mint_key = stake_account.nft_mint;
-        let staker_key = ctx.accounts.staker.key();
-        let stake_account_seeds = &[
-            b"stake_account",
-            staker_key.as_ref(),
-            nft_mint_key.as_ref(),
-            &[stake_account.bump],
-        ];
-        let signer = &[&stake_account_seeds[..]];
+	/// CHECK: Opened the pool, receives its rent
+	#[account(mut)]
+	pub payer: UncheckedAccount<'info>,
+}
 
-        let nft_transfer_cpi_accounts = anchor_spl::token::Transfer {
-            from: ctx.accounts.vault_nft_token_account.to_account_info(),
-            to: ctx.accounts.staker_nft_token_account.to_account_info(),
-            authority: ctx.accounts.stake_account.to_account_info(),
-        };
-        let nft_transfer_cpi_ctx = CpiContext::new_with_signer(
-            ctx.accounts.token_program.to_account_info(),
-            nft_transfer_cpi_accounts,
-            signer,
-        );
-        anchor_spl::token::transfer(nft_transfer_cpi_ctx, 1)?;
+#[derive(Accounts)]
+pub struct RevealChoice<'info> {
+	#[account(
+		mut,
+		has_one = creator,
+		seeds = [room.seed_prefix(), room.seed_owner().as_ref(), &room.room_id.to_le_bytes()],
+		bump = room.bump
+	)]
+	pub room: Account<'info, Room>,
 
-        ctx.accounts.stake_pool.total_staked = ctx.accounts.stake_pool.total_staked.saturating_sub(1);
+	/// CHECK: Room creator, receives its payout
+	#[account(mut)]
+	pub creator: UncheckedAccount<'info>,
 
-        msg!(
-            "NFT unstaked: {}, rewards claimed: {}",
-            nft_mint_key,
-            rewards
-        );
-        Ok(())
-    }
+	/// CHECK: Room challenger, receives its payout
+	#[account(mut, constraint = room.challenger == Some(challenger.key()) @ ErrorCode::Unauthorized)]
+	pub challenger: UncheckedAccount<'info>,
+
+	#[account(mut, seeds = [b"profile", creator.key().as_ref()], bump = creator_profile.bump)]
+	pub creator_profile: Box<Account<'info, PlayerProfile>>,
+
+	#[account(mut, seeds = [b"profile", challenger.key().as_ref()], bump = challenger_profile.bump)]
+	pub challenger_profile: Box<Account<'info, PlayerProfile>>,
+
+	// Locks on both gating NFTs, released once the room settles
+	#[account(
+		mut,
+		seeds = [b"nft_lock", room.creator_nft.as_ref()],
+		bump = creator_nft_lock.bump,
+		constraint = creator_nft_lock.room == room.key() @ ErrorCode::NftLockMismatch,
+	)]
+	pub creator_nft_lock: Box<Account<'info, NftLock>>,
+	#[account(
+		mut,
+		seeds = [b"nft_lock", room.challenger_nft.as_ref()],
+		bump = challenger_nft_lock.bump,
+		constraint = challenger_nft_lock.room == room.key() @ ErrorCode::NftLockMismatch,
+	)]
+	pub challenger_nft_lock: Box<Account<'info, NftLock>>,
+
+	pub player: Signer<'info>,
+
+	// Present when `player` is a session key acting for a wallet
+	#[account(seeds = [b"session", session.wallet.as_ref()], bump = session.bump)]
+	pub session: Option<Account<'info, SessionKey>>,
+
+	// Token-staked rooms only: the room vault and each player's payout account
+	#[account(mut, seeds = [b"room_vault", room.key().as_ref()], bump)]
+	pub room_vault: Option<Box<Account<'info, TokenAccount>>>,
+	#[account(mut)]
+	pub creator_stake_token: Option<Box<Account<'info, TokenAccount>>>,
+	#[account(mut)]
+	pub challenger_stake_token: Option<Box<Account<'info, TokenAccount>>>,
+	pub token_program: Option<Program<'info, Token>>,
+
+	/// CHECK: Room treasury, receives the rake on lamport rooms
+	#[account(mut, address = room.treasury @ ErrorCode::Unauthorized)]
+	pub treasury: Option<UncheckedAccount<'info>>,
+	#[account(mut, constraint = treasury_stake_token.owner == room.treasury @ ErrorCode::InvalidStakeAccount)]
+	pub treasury_stake_token: Option<Box<Account<'info, TokenAccount>>>,
+
+	// Required when the room belongs to a season
+	#[account(mut, seeds = [b"season".as_ref(), &season.season_id.to_le_bytes()], bump = season.bump)]
+	pub season: Option<Box<Account<'info, Season>>>,
+
+	// NFT-wager rooms only: both escrows and the accounts receiving each NFT
+	#[account(mut, seeds = [b"room_nft", room.key().as_ref(), room.creator_nft.as_ref()], bump)]
+	pub creator_nft_escrow: Option<Box<Account<'info, TokenAccount>>>,
+	#[account(mut, seeds = [b"room_nft", room.key().as_ref(), room.challenger_nft.as_ref()], bump)]
+	pub challenger_nft_escrow: Option<Box<Account<'info, TokenAccount>>>,
+	#[account(mut)]
+	pub creator_nft_destination: Option<Box<Account<'info, TokenAccount>>>,
+	#[account(mut)]
+	pub challenger_nft_destination: Option<Box<Account<'info, TokenAccount>>>,
+}
+
+#[derive(Accounts)]
+pub struct RefundLegacyRoom<'info> {
+	/// CHECK: Room in the original layout, which Account<Room> cannot read; the data,
+	/// discriminator and address are checked in the handler
+	#[account(mut, owner = crate::ID)]
+	pub room: UncheckedAccount<'info>,
+
+	/// CHECK: Room creator, checked against the room data; receives the rest of the balance
+	#[account(mut)]
+	pub creator: UncheckedAccount<'info>,
+
+	/// CHECK: Challenger of a joined room, checked against the room data
+	#[account(mut)]
+	pub challenger: Option<UncheckedAccount<'info>>,
+}
+
+#[derive(Accounts)]
+pub struct CancelRoom<'info> {
+	#[account(
+		mut,
+		close = creator,
+		has_one = creator,
+		seeds = [room.seed_prefix(), room.seed_owner().as_ref(), &room.room_id.to_le_bytes()],
+		bump = room.bump
+	)]
+	pub room: Account<'info, Room>,
+
+	#[account(mut)]
+	pub creator: Signer<'info>,
+
+	// Lock on the creator's gating NFT, released with the room
+	#[account(
+		mut,
+		close = creator,
+		seeds = [b"nft_lock", room.creator_nft.as_ref()],
+		bump = creator_nft_lock.bump,
+		constraint = creator_nft_lock.room == room.key() @ ErrorCode::NftLockMismatch,
+	)]
+	pub creator_nft_lock: Box<Account<'info, NftLock>>,
+
+	// Token-staked rooms only: the room vault and the creator's refund account
+	#[account(mut, seeds = [b"room_vault", room.key().as_ref()], bump)]
+	pub room_vault: Option<Box<Account<'info, TokenAccount>>>,
+	#[account(mut)]
+	pub creator_stake_token: Option<Box<Account<'info, TokenAccount>>>,
+	pub token_program: Option<Program<'info, Token>>,
+
+	// NFT-wager rooms only: the creator's escrow and the account receiving the NFT back
+	#[account(mut, seeds = [b"room_nft", room.key().as_ref(), room.creator_nft.as_ref()], bump)]
+	pub creator_nft_escrow: Option<Box<Account<'info, TokenAccount>>>,
+	#[account(mut)]
+	pub creator_nft_destination: Option<Box<Account<'info, TokenAccount>>>,
+}
+
+#[derive(Accounts)]
+pub struct TimeoutRoom<'info> {
+	#[account(
+		mut,
+		has_one = creator,
+		seeds = [room.seed_prefix(), room.seed_owner().as_ref(), &room.room_id.to_le_bytes()],
+		bump = room.bump
+	)]
+	pub room: Account<'info, Room>,
+
+	/// CHECK: Room creator, receives its payout
+	#[account(mut)]
+	pub creator: UncheckedAccount<'info>,
+
+	/// CHECK: Room challenger, receives its payout
+	#[account(mut, constraint = room.challenger == Some(challenger.key()) @ ErrorCode::Unauthorized)]
+	pub challenger: UncheckedAccount<'info>,
+
+	#[account(mut, seeds = [b"profile", creator.key().as_ref()], bump = creator_profile.bump)]
+	pub creator_profile: Box<Account<'info, PlayerProfile>>,
+
+	#[account(mut, seeds = [b"profile", challenger.key().as_ref()], bump = challenger_profile.bump)]
+	pub challenger_profile: Box<Account<'info, PlayerProfile>>,
+
+	// Locks on both gating NFTs, released once the room settles
+	#[account(
+		mut,
+		seeds = [b"nft_lock", room.creator_nft.as_ref()],
+		bump = creator_nft_lock.bump,
+		constraint = creator_nft_lock.room == room.key() @ ErrorCode::NftLockMismatch,
+	)]
+	pub creator_nft_lock: Box<Account<'info, NftLock>>,
+	#[account(
+		mut,
+		seeds = [b"nft_lock", room.challenger_nft.as_ref()],
+		bump = challenger_nft_lock.bump,
+		constraint = challenger_nft_lock.room == room.key() @ ErrorCode::NftLockMismatch,
+	)]
+	pub challenger_nft_lock: Box<Account<'info, NftLock>>,
+
+	// Token-staked rooms only: the room vault and each player's payout account
+	#[account(mut, seeds = [b"room_vault", room.key().as_ref()], bump)]
+	pub room_vault: Option<Box<Account<'info, TokenAccount>>>,
+	#[account(mut)]
+	pub creator_stake_token: Option<Box<Account<'info, TokenAccount>>>,
+	#[account(mut)]
+	pub challenger_stake_token: Option<Box<Account<'info, TokenAccount>>>,
+	pub token_program: Option<Program<'info, Token>>,
+
+	/// CHECK: Room treasury, receives the rake on lamport rooms
+	#[account(mut, address = room.treasury @ ErrorCode::Unauthorized)]
+	pub treasury: Option<UncheckedAccount<'info>>,
+	#[account(mut, constraint = treasury_stake_token.owner == room.treasury @ ErrorCode::InvalidStakeAccount)]
+	pub treasury_stake_token: Option<Box<Account<'info, TokenAccount>>>,
+
+	// Required when the room belongs to a season
+	#[account(mut, seeds = [b"season".as_ref(), &season.season_id.to_le_bytes()], bump = season.bump)]
+	pub season: Option<Box<Account<'info, Season>>>,
+
+	// NFT-wager rooms only: both escrows and the accounts receiving each NFT
+	#[account(mut, seeds = [b"room_nft", room.key().as_ref(), room.creator_nft.as_ref()], bump)]
+	pub creator_nft_escrow: Option<Box<Account<'info, TokenAccount>>>,
+	#[account(mut, seeds = [b"room_nft", room.key().as_ref(), room.challenger_nft.as_ref()], bump)]
+	pub challenger_nft_escrow: Option<Box<Account<'info, TokenAccount>>>,
+	#[account(mut)]
+	pub creator_nft_destination: Option<Box<Account<'info, TokenAccount>>>,
+	#[account(mut)]
+	pub challenger_nft_destination: Option<Box<Account<'info, TokenAccount>>>,
+}
+
+#[derive(Accounts)]
+pub struct ForfeitRoom<'info> {
+	#[account(
+		mut,
+		has_one = creator,
+		seeds = [room.seed_prefix(), room.seed_owner().as_ref(), &room.room_id.to_le_bytes()],
+		bump = room.bump
+	)]
+	pub room: Account<'info, Room>,
+
+	/// CHECK: Room creator, receives its payout
+	#[account(mut)]
+	pub creator: UncheckedAccount<'info>,
+
+	/// CHECK: Room challenger, receives its payout
+	#[account(mut, constraint = room.challenger == Some(challenger.key()) @ ErrorCode::Unauthorized)]
+	pub challenger: UncheckedAccount<'info>,
+
+	#[account(mut, seeds = [b"profile", creator.key().as_ref()], bump = creator_profile.bump)]
+	pub creator_profile: Box<Account<'info, PlayerProfile>>,
+
+	#[account(mut, seeds = [b"profile", challenger.key().as_ref()], bump = challenger_profile.bump)]
+	pub challenger_profile: Box<Account<'info, PlayerProfile>>,
+
+	// Locks on both gating NFTs, released once the room settles
+	#[account(
+		mut,
+		seeds = [b"nft_lock", room.creator_nft.as_ref()],
+		bump = creator_nft_lock.bump,
+		constraint = creator_nft_lock.room == room.key() @ ErrorCode::NftLockMismatch,
+	)]
+	pub creator_nft_lock: Box<Account<'info, NftLock>>,
+	#[account(
+		mut,
+		seeds = [b"nft_lock", room.challenger_nft.as_ref()],
+		bump = challenger_nft_lock.bump,
+		constraint = challenger_nft_lock.room == room.key() @ ErrorCode::NftLockMismatch,
+	)]
+	pub challenger_nft_lock: Box<Account<'info, NftLock>>,
+
+	// The conceding player, or a session key acting for them
+	pub player: Signer<'info>,
+
+	// Present when `player` is a session key acting for a wallet
+	#[account(seeds = [b"session", session.wallet.as_ref()], bump = session.bump)]
+	pub session: Option<Account<'info, SessionKey>>,
+
+	// Token-staked rooms only: the room vault and each player's payout account
+	#[account(mut, seeds = [b"room_vault", room.key().as_ref()], bump)]
+	pub room_vault: Option<Box<Account<'info, TokenAccount>>>,
+	#[account(mut)]
+	pub creator_stake_token: Option<Box<Account<'info, TokenAccount>>>,
+	#[account(mut)]
+	pub challenger_stake_token: Option<Box<Account<'info, TokenAccount>>>,
+	pub token_program: Option<Program<'info, Token>>,
+
+	/// CHECK: Room treasury, receives the rake on lamport rooms
+	#[account(mut, address = room.treasury @ ErrorCode::Unauthorized)]
+	pub treasury: Option<UncheckedAccount<'info>>,
+	#[account(mut, constraint = treasury_stake_token.owner == room.treasury @ ErrorCode::InvalidStakeAccount)]
+	pub treasury_stake_token: Option<Box<Account<'info, TokenAccount>>>,
+
+	// Required when the room belongs to a season
+	#[account(mut, seeds = [b"season".as_ref(), &season.season_id.to_le_bytes()], bump = season.bump)]
+	pub season: Option<Box<Account<'info, Season>>>,
+
+	// NFT-wager rooms only: both escrows and the accounts receiving each NFT
+	#[account(mut, seeds = [b"room_nft", room.key().as_ref(), room.creator_nft.as_ref()], bump)]
+	pub creator_nft_escrow: Option<Box<Account<'info, TokenAccount>>>,
+	#[account(mut, seeds = [b"room_nft", room.key().as_ref(), room.challenger_nft.as_ref()], bump)]
+	pub challenger_nft_escrow: Option<Box<Account<'info, TokenAccount>>>,
+	#[account(mut)]
+	pub creator_nft_destination: Option<Box<Account<'info, TokenAccount>>>,
+	#[account(mut)]
+	pub challenger_nft_destination: Option<Box<Account<'info, TokenAccount>>>,
+}
+
+#[derive(Accounts)]
+pub struct ResolveRoom<'info> {
+	#[account(
+		mut,
+		has_one = creator,
+		has_one = referee,
+		seeds = [room.seed_prefix(), room.seed_owner().as_ref(), &room.room_id.to_le_bytes()],
+		bump = room.bump
+	)]
+	pub room: Account<'info, Room>,
+
+	/// CHECK: Room creator, receives its payout
+	#[account(mut)]
+	pub creator: UncheckedAccount<'info>,
+
+	/// CHECK: Room challenger, receives its payout
+	#[account(mut, constraint = room.challenger == Some(challenger.key()) @ ErrorCode::Unauthorized)]
+	pub challenger: UncheckedAccount<'info>,
+
+	#[account(mut, seeds = [b"profile", creator.key().as_ref()], bump = creator_profile.bump)]
+	pub creator_profile: Box<Account<'info, PlayerProfile>>,
+
+	#[account(mut, seeds = [b"profile", challenger.key().as_ref()], bump = challenger_profile.bump)]
+	pub challenger_profile: Box<Account<'info, PlayerProfile>>,
+
+	// Locks on both gating NFTs, released once the room settles
+	#[account(
+		mut,
+		seeds = [b"nft_lock", room.creator_nft.as_ref()],
+		bump = creator_nft_lock.bump,
+		constraint = creator_nft_lock.room == room.key() @ ErrorCode::NftLockMismatch,
+	)]
+	pub creator_nft_lock: Box<Account<'info, NftLock>>,
+	#[account(
+		mut,
+		seeds = [b"nft_lock", room.challenger_nft.as_ref()],
+		bump = challenger_nft_lock.bump,
+		constraint = challenger_nft_lock.room == room.key() @ ErrorCode::NftLockMismatch,
+	)]
+	pub challenger_nft_lock: Box<Account<'info, NftLock>>,
+
+	pub referee: Signer<'info>,
+
+	// Token-staked rooms only: the room vault and each player's payout account
+	#[account(mut, seeds = [b"room_vault", room.key().as_ref()], bump)]
+	pub room_vault: Option<Box<Account<'info, TokenAccount>>>,
+	#[account(mut)]
+	pub creator_stake_token: Option<Box<Account<'info, TokenAccount>>>,
+	#[account(mut)]
+	pub challenger_stake_token: Option<Box<Account<'info, TokenAccount>>>,
+	pub token_program: Option<Program<'info, Token>>,
+
+	/// CHECK: Room treasury, receives the rake on lamport rooms
+	#[account(mut, address = room.treasury @ ErrorCode::Unauthorized)]
+	pub treasury: Option<UncheckedAccount<'info>>,
+	#[account(mut, constraint = treasury_stake_token.owner == room.treasury @ ErrorCode::InvalidStakeAccount)]
+	pub treasury_stake_token: Option<Box<Account<'info, TokenAccount>>>,
+
+	// Required when the room belongs to a season
+	#[account(mut, seeds = [b"season".as_ref(), &season.season_id.to_le_bytes()], bump = season.bump)]
+	pub season: Option<Box<Account<'info, Season>>>,
+
+	// NFT-wager rooms only: both escrows and the accounts receiving each NFT
+	#[account(mut, seeds = [b"room_nft", room.key().as_ref(), room.creator_nft.as_ref()], bump)]
+	pub creator_nft_escrow: Option<Box<Account<'info, TokenAccount>>>,
+	#[account(mut, seeds = [b"room_nft", room.key().as_ref(), room.challenger_nft.as_ref()], bump)]
+	pub challenger_nft_escrow: Option<Box<Account<'info, TokenAccount>>>,
+	#[account(mut)]
+	pub creator_nft_destination: Option<Box<Account<'info, TokenAccount>>>,
+	#[account(mut)]
+	pub challenger_nft_destination: Option<Box<Account<'info, TokenAccount>>>,
+}
+
+#[derive(Accounts)]
+pub struct DisputeResult<'info> {
+	#[account(
+		mut,
+		seeds = [room.seed_prefix(), room.seed_owner().as_ref(), &room.room_id.to_le_bytes()],
+		bump = room.bump
+	)]
+	pub room: Account<'info, Room>,
+
+	#[account(
+		init,
+		payer = disputer,
+		space = RoomDispute::space(),
+		seeds = [b"room_dispute", room.key().as_ref()],
+		bump
+	)]
+	pub dispute: Account<'info, RoomDispute>,
+
+	#[account(mut)]
+	pub disputer: Signer<'info>,
+	pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct FinalizeResult<'info> {
+	#[account(
+		mut,
+		has_one = creator,
+		seeds = [room.seed_prefix(), room.seed_owner().as_ref(), &room.room_id.to_le_bytes()],
+		bump = room.bump
+	)]
+	pub room: Account<'info, Room>,
+
+	/// CHECK: Room creator, receives its payout
+	#[account(mut)]
+	pub creator: UncheckedAccount<'info>,
+
+	/// CHECK: Room challenger, receives its payout
+	#[account(mut, constraint = room.challenger == Some(challenger.key()) @ ErrorCode::Unauthorized)]
+	pub challenger: UncheckedAccount<'info>,
+
+	#[account(mut, seeds = [b"profile", creator.key().as_ref()], bump = creator_profile.bump)]
+	pub creator_profile: Box<Account<'info, PlayerProfile>>,
+
+	#[account(mut, seeds = [b"profile", challenger.key().as_ref()], bump = challenger_profile.bump)]
+	pub challenger_profile: Box<Account<'info, PlayerProfile>>,
+
+	// Locks on both gating NFTs, released once the room settles
+	#[account(
+		mut,
+		seeds = [b"nft_lock", room.creator_nft.as_ref()],
+		bump = creator_nft_lock.bump,
+		constraint = creator_nft_lock.room == room.key() @ ErrorCode::NftLockMismatch,
+	)]
+	pub creator_nft_lock: Box<Account<'info, NftLock>>,
+	#[account(
+		mut,
+		seeds = [b"nft_lock", room.challenger_nft.as_ref()],
+		bump = challenger_nft_lock.bump,
+		constraint = challenger_nft_lock.room == room.key() @ ErrorCode::NftLockMismatch,
+	)]
+	pub challenger_nft_lock: Box<Account<'info, NftLock>>,
+
+	// Token-staked rooms only: the room vault and each player's payout account
+	#[account(mut, seeds = [b"room_vault", room.key().as_ref()], bump)]
+	pub room_vault: Option<Box<Account<'info, TokenAccount>>>,
+	#[account(mut)]
+	pub creator_stake_token: Option<Box<Account<'info, TokenAccount>>>,
+	#[account(mut)]
+	pub challenger_stake_token: Option<Box<Account<'info, TokenAccount>>>,
+	pub token_program: Option<Program<'info, Token>>,
+
+	/// CHECK: Room treasury, receives the rake on lamport rooms
+	#[account(mut, address = room.treasury @ ErrorCode::Unauthorized)]
+	pub treasury: Option<UncheckedAccount<'info>>,
+	#[account(mut, constraint = treasury_stake_token.owner == room.treasury @ ErrorCode::InvalidStakeAccount)]
+	pub treasury_stake_token: Option<Box<Account<'info, TokenAccount>>>,
+
+	// Required when the room belongs to a season
+	#[account(mut, seeds = [b"season".as_ref(), &season.season_id.to_le_bytes()], bump = season.bump)]
+	pub season: Option<Box<Account<'info, Season>>>,
+
+	// NFT-wager rooms only: both escrows and the accounts receiving each NFT
+	#[account(mut, seeds = [b"room_nft", room.key().as_ref(), room.creator_nft.as_ref()], bump)]
+	pub creator_nft_escrow: Option<Box<Account<'info, TokenAccount>>>,
+	#[account(mut, seeds = [b"room_nft", room.key().as_ref(), room.challenger_nft.as_ref()], bump)]
+	pub challenger_nft_escrow: Option<Box<Account<'info, TokenAccount>>>,
+	#[account(mut)]
+	pub creator_nft_destination: Option<Box<Account<'info, TokenAccount>>>,
+	#[account(mut)]
+	pub challenger_nft_destination: Option<Box<Account<'info, TokenAccount>>>,
+
+	// Disputed rooms past their arbitration window only: the dispute, closed back to the
+	// disputer with the bond
+	#[account(
+		mut,
+		has_one = room,
+		seeds = [b"room_dispute", room.key().as_ref()],
+		bump = dispute.bump
+	)]
+	pub dispute: Option<Box<Account<'info, RoomDispute>>>,
+	/// CHECK: Player who disputed; matched against the dispute
+	#[account(mut)]
+	pub disputer: Option<UncheckedAccount<'info>>,
+}
+
+#[derive(Accounts)]
+pub struct ArbitrateRoom<'info> {
+	#[account(seeds = [b"marketplace"], bump = marketplace.bump, has_one = admin)]
+	pub marketplace: Box<Account<'info, Marketplace>>,
+
+	pub admin: Signer<'info>,
+
+	#[account(
+		mut,
+		has_one = creator,
+		seeds = [room.seed_prefix(), room.seed_owner().as_ref(), &room.room_id.to_le_bytes()],
+		bump = room.bump
+	)]
+	pub room: Account<'info, Room>,
+
+	/// CHECK: Room creator, receives its payout
+	#[account(mut)]
+	pub creator: UncheckedAccount<'info>,
+
+	/// CHECK: Room challenger, receives its payout
+	#[account(mut, constraint = room.challenger == Some(challenger.key()) @ ErrorCode::Unauthorized)]
+	pub challenger: UncheckedAccount<'info>,
+
+	#[account(mut, seeds = [b"profile", creator.key().as_ref()], bump = creator_profile.bump)]
+	pub creator_profile: Box<Account<'info, PlayerProfile>>,
 
-    // Staking: Claim rewards without unstaking
-    pub fn claim_rewards(ctx: Context<ClaimRewards>) -> Result<()> {
-        let stake_account = &mut ctx.accounts.stake_account;
-        let stake_pool = &ctx.accounts.stake_pool;
+	#[account(mut, seeds = [b"profile", challenger.key().as_ref()], bump = challenger_profile.bump)]
+	pub challenger_profile: Box<Account<'info, PlayerProfile>>,
 
-        require!(stake_account.owner == ctx.accounts.staker.key(), ErrorCode::Unauthorized);
+	// Locks on both gating NFTs, released once the room settles
+	#[account(
+		mut,
+		seeds = [b"nft_lock", room.creator_nft.as_ref()],
+		bump = creator_nft_lock.bump,
+		constraint = creator_nft_lock.room == room.key() @ ErrorCode::NftLockMismatch,
+	)]
+	pub creator_nft_lock: Box<Account<'info, NftLock>>,
+	#[account(
+		mut,
+		seeds = [b"nft_lock", room.challenger_nft.as_ref()],
+		bump = challenger_nft_lock.bump,
+		constraint = challenger_nft_lock.room == room.key() @ ErrorCode::NftLockMismatch,
+	)]
+	pub challenger_nft_lock: Box<Account<'info, NftLock>>,
 
-        let clock = Clock::get()?;
+	#[account(
+		mut,
+		close = disputer,
+		has_one = room,
+		has_one = disputer,
+		seeds = [b"room_dispute", room.key().as_ref()],
+		bump = dispute.bump
+	)]
+	pub dispute: Box<Account<'info, RoomDispute>>,
 
-        // Calculate rewards since last claim
-        let time_since_last_claim = clock.unix_timestamp.saturating_sub(stake_account.last_claim_timestamp);
-        let base_rewards = (time_since_last_claim as u64)
-            .saturating_mul(stake_pool.reward_rate_per_second);
-        let rewards = base_rewards
-            .saturating_mul(stake_account.stake_multiplier)
-            .saturating_div(10000); // Divide by 10000 because multiplier is in basis points
+	/// CHECK: Player who disputed; receives the dispute rent and any refunded bond
+	#[account(mut)]
+	pub disputer: UncheckedAccount<'info>,
 
-        if rewards > 0 {
-            let pool_seeds = &[
-                b"stake_pool".as_ref(),
-                &[stake_pool.bump],
-            ];
-            let signer = &[&pool_seeds[..]];
+	// Token-staked rooms only: the room vault and each player's payout account
+	#[account(mut, seeds = [b"room_vault", room.key().as_ref()], bump)]
+	pub room_vault: Option<Box<Account<'info, TokenAccount>>>,
+	#[account(mut)]
+	pub creator_stake_token: Option<Box<Account<'info, TokenAccount>>>,
+	#[account(mut)]
+	pub challenger_stake_token: Option<Box<Account<'info, TokenAccount>>>,
+	pub token_program: Option<Program<'info, Token>>,
+
+	/// CHECK: Room treasury, receives the rake on lamport rooms and any slashed bond
+	#[account(mut, address = room.treasury @ ErrorCode::Unauthorized)]
+	pub treasury: Option<UncheckedAccount<'info>>,
+	#[account(mut, constraint = treasury_stake_token.owner == room.treasury @ ErrorCode::InvalidStakeAccount)]
+	pub treasury_stake_token: Option<Box<Account<'info, TokenAccount>>>,
+
+	// Required when the room belongs to a season
+	#[account(mut, seeds = [b"season".as_ref(), &season.season_id.to_le_bytes()], bump = season.bump)]
+	pub season: Option<Box<Account<'info, Season>>>,
+
+	// NFT-wager rooms only: both escrows and the accounts receiving each NFT
+	#[account(mut, seeds = [b"room_nft", room.key().as_ref(), room.creator_nft.as_ref()], bump)]
+	pub creator_nft_escrow: Option<Box<Account<'info, TokenAccount>>>,
+	#[account(mut, seeds = [b"room_nft", room.key().as_ref(), room.challenger_nft.as_ref()], bump)]
+	pub challenger_nft_escrow: Option<Box<Account<'info, TokenAccount>>>,
+	#[account(mut)]
+	pub creator_nft_destination: Option<Box<Account<'info, TokenAccount>>>,
+	#[account(mut)]
+	pub challenger_nft_destination: Option<Box<Account<'info, TokenAccount>>>,
+}
 
-            let transfer_cpi_accounts = anchor_spl::token::Transfer {
-                from: ctx.accounts.reward_token_vault.to_account_info(),
-                to: ctx.accounts.staker_reward_token_account.to_account_info(),
-                authority: ctx.accounts.stake_pool.to_account_info(),
-            };
-            let transfer_cpi_ctx = CpiContext::new_with_signer(
-                ctx.accounts.token_program.to_account_info(),
-                transfer_cpi_accounts,
-                signer,
-            );
-            anchor_spl::token::transfer(transfer_cpi_ctx, rewards)?;
+#[derive(Accounts)]
+#[instruction(season_id: u64, start_ts: i64, end_ts: i64, payout_bps: Vec<u16>)]
+pub struct CreateSeason<'info> {
+	#[account(
+		mut,
+		seeds = [b"marketplace"],
+		bump = marketplace.bump,
+		has_one = admin,
+	)]
+	pub marketplace: Box<Account<'info, Marketplace>>,
 
-            // Update last claim timestamp
-            stake_account.last_claim_timestamp = clock.unix_timestamp;
+	#[account(
+		init,
+		payer = admin,
+		space = Season::space(payout_bps.len()),
+		seeds = [b"season".as_ref(), &season_id.to_le_bytes()],
+		bump
+	)]
+	pub season: Box<Account<'info, Season>>,
 
-            msg!("Rewards claimed: {}", rewards);
-        } else {
-            msg!("No rewards to claim");
-        }
+	// The marketplace's current season, if it has one
+	#[account(seeds = [b"season".as_ref(), &previous_season.season_id.to_le_bytes()], bump = previous_season.bump)]
+	pub previous_season: Option<Box<Account<'info, Season>>>,
 
-        Ok(())
-    }
+	#[account(mut)]
+	pub admin: Signer<'info>,
+	pub system_program: Program<'info, System>,
 }
 
-// Account Structures
 #[derive(Accounts)]
-pub struct InitializeMarketplace<'info> {
-    #[account(
-        init,
-        payer = admin,
-        space = 8 + 32 + 2 + 8 + 1,
-        seeds = [b"marketplace"],
-        bump
-    )]
-    pub marketplace: Account<'info, Marketplace>,
-    
-    #[account(mut)]
-    pub admin: Signer<'info>,
-    pub system_program: Program<'info, System>,
+pub struct FundSeason<'info> {
+	#[account(mut, seeds = [b"season".as_ref(), &season.season_id.to_le_bytes()], bump = season.bump)]
+	pub season: Box<Account<'info, Season>>,
+
+	#[account(mut)]
+	pub funder: Signer<'info>,
+	pub system_program: Program<'info, System>,
 }
 
 #[derive(Accounts)]
-#[instruction(collection_name: String)]
-pub struct CreateNFTCollection<'info> {
-    #[account(
-        mut,
-        seeds = [b"marketplace"],
-        bump = marketplace.bump
-    )]
-    pub marketplace: Account<'info, Marketplace>,
+pub struct FinalizeSeason<'info> {
+	#[account(mut, seeds = [b"season".as_ref(), &season.season_id.to_le_bytes()], bump = season.bump)]
+	pub season: Box<Account<'info, Season>>,
+}
 
-    #[account(
-        init,
-        payer = admin,
-        space = 8 + 32 + 4 + collection_name.len() + 4 + 10 + 4 + 200 + 2 + 32 + 1 + 1,
-        seeds = [b"collection", collection_name.as_bytes()],
-        bump
-    )]
-    pub collection: Account<'info, NFTCollection>,
+#[derive(Accounts)]
+pub struct ClaimSeasonPrize<'info> {
+	#[account(mut, seeds = [b"season".as_ref(), &season.season_id.to_le_bytes()], bump = season.bump)]
+	pub season: Box<Account<'info, Season>>,
 
-    #[account(
-        init,
-        payer = admin,
-        mint::decimals = 0,
-        mint::authority = admin,
-        mint::freeze_authority = admin,
-    )]
-    pub collection_mint: Account<'info, Mint>,
+	#[account(mut)]
+	pub winner: Signer<'info>,
+}
 
-    #[account(
-        init_if_needed,
-        payer = admin,
-        associated_token::mint = collection_mint,
-        associated_token::authority = admin,
-    )]
-    pub admin_token_account: Account<'info, TokenAccount>,
+#[derive(Accounts)]
+pub struct SweepSeason<'info> {
+	#[account(seeds = [b"marketplace"], bump = marketplace.bump, has_one = admin)]
+	pub marketplace: Box<Account<'info, Marketplace>>,
 
-    /// CHECK: Metadata account
-    #[account(
-        mut,
-        seeds = [
-            b"metadata",
-            token_metadata_program.key().as_ref(),
-            collection_mint.key().as_ref(),
-        ],
-        bump,
-        seeds::program = token_metadata_program.key(),
-    )]
-    pub collection_metadata: UncheckedAccount<'info>,
+	#[account(mut, seeds = [b"season".as_ref(), &season.season_id.to_le_bytes()], bump = season.bump)]
+	pub season: Box<Account<'info, Season>>,
 
-    /// CHECK: Master edition account
-    #[account(
-        mut,
-        seeds = [
-            b"metadata",
-            token_metadata_program.key().as_ref(),
-            collection_mint.key().as_ref(),
-            b"edition",
-        ],
-        bump,
-        seeds::program = token_metadata_program.key(),
-    )]
-    pub collection_master_edition: UncheckedAccount<'info>,
+	/// CHECK: Marketplace treasury, receives the unclaimed prize pool
+	#[account(mut, address = marketplace.treasury @ ErrorCode::Unauthorized)]
+	pub treasury: UncheckedAccount<'info>,
 
-    #[account(mut)]
-    pub admin: Signer<'info>,
-    pub system_program: Program<'info, System>,
-    pub token_program: Program<'info, Token>,
-    pub associated_token_program: Program<'info, AssociatedToken>,
-    /// CHECK: Token Metadata Program
-    #[account(constraint = token_metadata_program.key() == mpl_token_metadata::ID)]
-    pub token_metadata_program: UncheckedAccount<'info>,
-    pub rent: Sysvar<'info, Rent>,
+	pub admin: Signer<'info>,
 }
 
 #[derive(Accounts)]
-#[instruction(type_name: String)]
-pub struct CreateNFTType<'info> {
-    #[account(
-        mut,
-        seeds = [b"collection", collection.name.as_bytes()],
-        bump = collection.bump,
-    )]
-    pub collection: Account<'info, NFTCollection>,
+#[instruction(stake_lamports: u64)]
+pub struct CreateMatchQueue<'info> {
+	#[account(
+		seeds = [b"marketplace"],
+		bump = marketplace.bump,
+		has_one = admin,
+	)]
+	pub marketplace: Box<Account<'info, Marketplace>>,
 
-    #[account(
-        init,
-        payer = admin,
-        space = 8
-            + 32
-            + 4 + type_name.len()
-            + 4 + 200
-            + 8
-            + 8
-            + 8
-            + 8
-            + 1,
-        seeds = [b"type", collection.key().as_ref(), type_name.as_bytes()],
-        bump,
-    )]
-    pub nft_type: Account<'info, NftType>,
+	#[account(
+		seeds = [b"collection", collection.name_seed().as_ref()],
+		bump = collection.bump,
+		constraint = collection.verified @ ErrorCode::CollectionNotVerified,
+	)]
+	pub collection: Box<Account<'info, NFTCollection>>,
 
-    #[account(mut, constraint = admin.key() == collection.admin)]
-    pub admin: Signer<'info>,
-    pub system_program: Program<'info, System>,
+	#[account(
+		init,
+		payer = admin,
+		space = MatchQueue::space(),
+		seeds = [b"match_queue", collection.key().as_ref(), &stake_lamports.to_le_bytes()],
+		bump
+	)]
+	pub match_queue: Account<'info, MatchQueue>,
+
+	#[account(mut)]
+	pub admin: Signer<'info>,
+	pub system_program: Program<'info, System>,
 }
 
 #[derive(Accounts)]
-#[instruction(type_name: String)]
-pub struct MintNFTFromCollection<'info> {
-    #[account(
-        mut,
-        seeds = [b"collection", collection.name.as_bytes()],
-        bump = collection.bump,
-    )]
-    pub collection: Account<'info, NFTCollection>,
+pub struct Enqueue<'info> {
+	#[account(seeds = [b"marketplace"], bump = marketplace.bump)]
+	pub marketplace: Box<Account<'info, Marketplace>>,
 
-    #[account(
-        mut,
-        seeds = [
-            b"type",
-            collection.key().as_ref(),
-            type_name.as_bytes(),
-        ],
-        bump = nft_type.bump,
-        constraint = nft_type.collection == collection.key(),
-    )]
-    pub nft_type: Account<'info, NftType>,
+	#[account(
+		mut,
+		seeds = [
+			b"match_queue",
+			match_queue.collection.as_ref(),
+			&match_queue.stake_lamports.to_le_bytes(),
+		],
+		bump = match_queue.bump
+	)]
+	pub match_queue: Box<Account<'info, MatchQueue>>,
 
-    #[account(
-        init,
-        payer = buyer,
-        mint::decimals = 0,
-        mint::authority = collection.admin,
-    )]
-    pub nft_mint: Account<'info, Mint>,
+	// Room created when the caller is paired with the waiting player
+	#[account(
+		init,
+		payer = player,
+		space = Room::space(None),
+		seeds = [b"room", player.key().as_ref(), &match_queue.next_room_id().to_le_bytes()],
+		bump
+	)]
+	pub room: Option<Box<Account<'info, Room>>>,
 
-    #[account(
-        init_if_needed,
-        payer = buyer,
-        associated_token::mint = nft_mint,
-        associated_token::authority = buyer,
-    )]
-    pub buyer_token_account: Account<'info, TokenAccount>,
+	#[account(mut)]
+	pub player: Signer<'info>,
 
-    /// CHECK: NFT Metadata account
-    #[account(
-        mut,
-        seeds = [
-            b"metadata",
-            token_metadata_program.key().as_ref(),
-            nft_mint.key().as_ref(),
-        ],
-        bump,
-        seeds::program = token_metadata_program.key(),
-    )]
-    pub nft_metadata: UncheckedAccount<'info>,
+	#[account(
+		init_if_needed,
+		payer = player,
+		space = PlayerProfile::space(),
+		seeds = [b"profile", player.key().as_ref()],
+		bump
+	)]
+	pub player_profile: Box<Account<'info, PlayerProfile>>,
 
-    /// CHECK: Collection metadata PDA (for the collection mint)
-    #[account(
-        mut,
-        seeds = [
-            b"metadata",
-            token_metadata_program.key().as_ref(),
-            collection_mint_account.key().as_ref(),
-        ],
-        bump,
-        seeds::program = token_metadata_program.key(),
-    )]
-    pub collection_metadata: UncheckedAccount<'info>,
+	// Present if the caller is exempt from the room rake
+	#[account(
+		seeds = [b"rake_exempt", marketplace.key().as_ref(), player.key().as_ref()],
+		bump = rake_exemption.bump
+	)]
+	pub rake_exemption: Option<Box<Account<'info, RakeExemption>>>,
+
+	#[account(
+		address = match_queue.collection @ ErrorCode::CollectionMismatch,
+		constraint = collection.verified @ ErrorCode::CollectionNotVerified,
+	)]
+	pub collection: Box<Account<'info, NFTCollection>>,
+
+	/// CHECK: Mint of an NFT the player owns
+	pub nft_mint: Account<'info, Mint>,
+
+	/// CHECK: Metadata account of the provided NFT mint, owned by Token Metadata
+	#[account(
+		owner = mpl_token_metadata::ID @ ErrorCode::InvalidMetadata,
+		seeds = [
+			b"metadata",
+			token_metadata_program.key().as_ref(),
+			nft_mint.key().as_ref(),
+		],
+		bump,
+		seeds::program = token_metadata_program.key(),
+	)]
+	pub nft_metadata: UncheckedAccount<'info>,
 
-    /// CHECK: Collection master edition PDA
-    #[account(
-        mut,
-        seeds = [
-            b"metadata",
-            token_metadata_program.key().as_ref(),
-            collection_mint_account.key().as_ref(),
-            b"edition",
-        ],
-        bump,
-        seeds::program = token_metadata_program.key(),
-    )]
-    pub collection_master_edition: UncheckedAccount<'info>,
+	#[account(
+		constraint = player_nft_token.owner == player.key(),
+		constraint = player_nft_token.mint == nft_mint.key(),
+	)]
+	pub player_nft_token: Account<'info, TokenAccount>,
 
-    /// CHECK: Collection mint account (must match stored collection.mint)
-    #[account(constraint = collection_mint_account.key() == collection.mint)]
-    pub collection_mint_account: UncheckedAccount<'info>,
+	// Held while the NFT gates this seat so it cannot gate another at the same time
+	#[account(
+		init,
+		payer = player,
+		space = NftLock::space(),
+		seeds = [b"nft_lock", nft_mint.key().as_ref()],
+		bump
+	)]
+	pub player_nft_lock: Box<Account<'info, NftLock>>,
 
-    /// CHECK: Collection admin (receives payment) and authority to verify collection
-    #[account(mut, constraint = collection_admin.key() == collection.admin)]
-    pub collection_admin: Signer<'info>,
+	// Lock on the waiting player's NFT; required when pairing, moved to the new room
+	#[account(
+		mut,
+		seeds = [b"nft_lock", match_queue.waiting_nft.as_ref()],
+		bump = waiting_nft_lock.bump,
+		constraint = waiting_nft_lock.room == match_queue.key() @ ErrorCode::NftLockMismatch,
+	)]
+	pub waiting_nft_lock: Option<Box<Account<'info, NftLock>>>,
+	pub system_program: Program<'info, System>,
+	/// CHECK: Token Metadata Program
+	#[account(address = mpl_token_metadata::ID)]
+	pub token_metadata_program: UncheckedAccount<'info>,
+}
 
-    #[account(mut)]
-    pub buyer: Signer<'info>,
-    pub system_program: Program<'info, System>,
-    pub token_program: Program<'info, Token>,
-    pub associated_token_program: Program<'info, AssociatedToken>,
-    /// CHECK: Token Metadata Program
-    pub token_metadata_program: UncheckedAccount<'info>,
-    pub rent: Sysvar<'info, Rent>,
+#[derive(Accounts)]
+pub struct LeaveQueue<'info> {
+	#[account(
+		mut,
+		seeds = [
+			b"match_queue",
+			match_queue.collection.as_ref(),
+			&match_queue.stake_lamports.to_le_bytes(),
+		],
+		bump = match_queue.bump
+	)]
+	pub match_queue: Account<'info, MatchQueue>,
+
+	#[account(mut)]
+	pub player: Signer<'info>,
+
+	#[account(
+		mut,
+		close = player,
+		seeds = [b"nft_lock", match_queue.waiting_nft.as_ref()],
+		bump = player_nft_lock.bump,
+		constraint = player_nft_lock.room == match_queue.key() @ ErrorCode::NftLockMismatch,
+	)]
+	pub player_nft_lock: Account<'info, NftLock>,
 }
 
 #[derive(Accounts)]
-#[instruction(room_id: u64)]
-pub struct CreateRoom<'info> {
+#[instruction(room_id: u64, stake_lamports: u64, seat_count: u8, payout_bps: Vec<u16>)]
+pub struct CreateMultiRoom<'info> {
+	#[account(seeds = [b"marketplace"], bump = marketplace.bump)]
+	pub marketplace: Box<Account<'info, Marketplace>>,
+
 	#[account(
 		init,
 		payer = creator,
-		space = Room::space(None),
-		seeds = [b"room", creator.key().as_ref(), &room_id.to_le_bytes()],
+		space = MultiRoom::space(seat_count, payout_bps.len()),
+		seeds = [b"multi_room", creator.key().as_ref(), &room_id.to_le_bytes()],
 		bump
 	)]
-	pub room: Account<'info, Room>,
+	pub multi_room: Box<Account<'info, MultiRoom>>,
 
 	#[account(mut)]
 	pub creator: Signer<'info>,
 
+	// Present if the creator is exempt from the room rake
+	#[account(
+		seeds = [b"rake_exempt", marketplace.key().as_ref(), creator.key().as_ref()],
+		bump = rake_exemption.bump
+	)]
+	pub rake_exemption: Option<Box<Account<'info, RakeExemption>>>,
+
+	// Registered collection the room is gated by
+	#[account(
+		seeds = [b"collection", collection.name_seed().as_ref()],
+		bump = collection.bump,
+		constraint = collection.verified @ ErrorCode::CollectionNotVerified,
+	)]
+	pub collection: Box<Account<'info, NFTCollection>>,
+
 	/// CHECK: Mint of an NFT the creator owns
 	pub nft_mint: Account<'info, Mint>,
 
-	/// CHECK: Metadata account of the provided NFT mint
+	/// CHECK: Metadata account of the provided NFT mint, owned by Token Metadata
 	#[account(
+		owner = mpl_token_metadata::ID @ ErrorCode::InvalidMetadata,
 		seeds = [
 			b"metadata",
 			token_metadata_program.key().as_ref(),
@@ -925,35 +5847,52 @@ pub struct CreateRoom<'info> {
 	)]
 	pub nft_metadata: UncheckedAccount<'info>,
 
-	/// CHECK: Collection mint that rooms should be gated by
-	pub collection_mint: Account<'info, Mint>,
-
 	#[account(
 		constraint = creator_nft_token.owner == creator.key(),
 		constraint = creator_nft_token.mint == nft_mint.key(),
 	)]
 	pub creator_nft_token: Account<'info, TokenAccount>,
+
+	// Held while the NFT gates this room so it cannot gate another at the same time
+	#[account(
+		init,
+		payer = creator,
+		space = NftLock::space(),
+		seeds = [b"nft_lock", nft_mint.key().as_ref()],
+		bump
+	)]
+	pub creator_nft_lock: Box<Account<'info, NftLock>>,
 	pub system_program: Program<'info, System>,
 	/// CHECK: Token Metadata Program
+	#[account(address = mpl_token_metadata::ID)]
 	pub token_metadata_program: UncheckedAccount<'info>,
 }
 
 #[derive(Accounts)]
-pub struct JoinRoom<'info> {
-	#[account(mut, has_one = creator, seeds = [b"room", creator.key().as_ref(), &room.room_id.to_le_bytes()], bump = room.bump)]
-	pub room: Account<'info, Room>,
+pub struct JoinMultiRoom<'info> {
+	#[account(
+		mut,
+		seeds = [b"multi_room", multi_room.creator.as_ref(), &multi_room.room_id.to_le_bytes()],
+		bump = multi_room.bump
+	)]
+	pub multi_room: Box<Account<'info, MultiRoom>>,
 
-	/// CHECK: only used as seed and authority check
-	pub creator: UncheckedAccount<'info>,
+	// Must be the collection the room was created with
+	#[account(
+		address = multi_room.collection @ ErrorCode::CollectionMismatch,
+		constraint = collection.verified @ ErrorCode::CollectionNotVerified,
+	)]
+	pub collection: Box<Account<'info, NFTCollection>>,
 
 	#[account(mut)]
-	pub challenger: Signer<'info>,
+	pub player: Signer<'info>,
 
-	/// CHECK: Mint of an NFT the challenger owns
+	/// CHECK: Mint of an NFT the player owns
 	pub nft_mint: Account<'info, Mint>,
 
-	/// CHECK: Metadata account of the provided NFT mint
+	/// CHECK: Metadata account of the provided NFT mint, owned by Token Metadata
 	#[account(
+		owner = mpl_token_metadata::ID @ ErrorCode::InvalidMetadata,
 		seeds = [
 			b"metadata",
 			token_metadata_program.key().as_ref(),
@@ -964,43 +5903,371 @@ pub struct JoinRoom<'info> {
 	)]
 	pub nft_metadata: UncheckedAccount<'info>,
 
-	/// CHECK: Collection mint that rooms should be gated by
-	pub collection_mint: Account<'info, Mint>,
+	#[account(
+		constraint = player_nft_token.owner == player.key(),
+		constraint = player_nft_token.mint == nft_mint.key(),
+	)]
+	pub player_nft_token: Account<'info, TokenAccount>,
 
+	// Held while the NFT gates this seat so it cannot gate another at the same time
 	#[account(
-		constraint = challenger_nft_token.owner == challenger.key(),
-		constraint = challenger_nft_token.mint == nft_mint.key(),
+		init,
+		payer = player,
+		space = NftLock::space(),
+		seeds = [b"nft_lock", nft_mint.key().as_ref()],
+		bump
 	)]
-	pub challenger_nft_token: Account<'info, TokenAccount>,
+	pub player_nft_lock: Box<Account<'info, NftLock>>,
 	pub system_program: Program<'info, System>,
 	/// CHECK: Token Metadata Program
+	#[account(address = mpl_token_metadata::ID)]
 	pub token_metadata_program: UncheckedAccount<'info>,
 }
 
 #[derive(Accounts)]
-pub struct ResolveRoom<'info> {
+pub struct ResolveMultiRoom<'info> {
 	#[account(
 		mut,
 		close = creator,
-		seeds = [b"room", creator.key().as_ref(), &room.room_id.to_le_bytes()],
-		bump = room.bump
+		has_one = creator,
+		has_one = referee,
+		seeds = [b"multi_room", creator.key().as_ref(), &multi_room.room_id.to_le_bytes()],
+		bump = multi_room.bump
 	)]
-	pub room: Account<'info, Room>,
+	pub multi_room: Box<Account<'info, MultiRoom>>,
+
+	/// CHECK: Room creator, receives the room rent
+	#[account(mut)]
+	pub creator: UncheckedAccount<'info>,
+
+	pub referee: Signer<'info>,
+
+	/// CHECK: Room treasury, receives the rake
+	#[account(mut, address = multi_room.treasury @ ErrorCode::Unauthorized)]
+	pub treasury: Option<UncheckedAccount<'info>>,
+}
+
+#[derive(Accounts)]
+pub struct RefundMultiRoom<'info> {
+	#[account(
+		mut,
+		close = creator,
+		has_one = creator,
+		seeds = [b"multi_room", creator.key().as_ref(), &multi_room.room_id.to_le_bytes()],
+		bump = multi_room.bump
+	)]
+	pub multi_room: Box<Account<'info, MultiRoom>>,
+
+	/// CHECK: Room creator, receives the room rent
+	#[account(mut)]
+	pub creator: UncheckedAccount<'info>,
+
+	pub caller: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(tournament_id: u64, entry_fee: u64, size: u8, mode: u8, timeout_secs: i64, payout_bps: Vec<u16>)]
+pub struct CreateTournament<'info> {
+	#[account(seeds = [b"marketplace"], bump = marketplace.bump)]
+	pub marketplace: Box<Account<'info, Marketplace>>,
+
+	#[account(
+		init,
+		payer = organizer,
+		space = Tournament::space(size, payout_bps.len()),
+		seeds = [b"tournament", organizer.key().as_ref(), &tournament_id.to_le_bytes()],
+		bump
+	)]
+	pub tournament: Box<Account<'info, Tournament>>,
+
+	#[account(mut)]
+	pub organizer: Signer<'info>,
+
+	// Registered collection entrants are gated by
+	#[account(
+		seeds = [b"collection", collection.name_seed().as_ref()],
+		bump = collection.bump,
+		constraint = collection.verified @ ErrorCode::CollectionNotVerified,
+	)]
+	pub collection: Box<Account<'info, NFTCollection>>,
+	pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct EnterTournament<'info> {
+	#[account(
+		mut,
+		seeds = [
+			b"tournament",
+			tournament.organizer.as_ref(),
+			&tournament.tournament_id.to_le_bytes(),
+		],
+		bump = tournament.bump
+	)]
+	pub tournament: Box<Account<'info, Tournament>>,
+
+	#[account(mut)]
+	pub player: Signer<'info>,
+
+	#[account(
+		init_if_needed,
+		payer = player,
+		space = PlayerProfile::space(),
+		seeds = [b"profile", player.key().as_ref()],
+		bump
+	)]
+	pub player_profile: Box<Account<'info, PlayerProfile>>,
+
+	#[account(
+		address = tournament.collection @ ErrorCode::CollectionMismatch,
+		constraint = collection.verified @ ErrorCode::CollectionNotVerified,
+	)]
+	pub collection: Box<Account<'info, NFTCollection>>,
+
+	/// CHECK: Mint of an NFT the player owns
+	pub nft_mint: Account<'info, Mint>,
+
+	/// CHECK: Metadata account of the provided NFT mint, owned by Token Metadata
+	#[account(
+		owner = mpl_token_metadata::ID @ ErrorCode::InvalidMetadata,
+		seeds = [
+			b"metadata",
+			token_metadata_program.key().as_ref(),
+			nft_mint.key().as_ref(),
+		],
+		bump,
+		seeds::program = token_metadata_program.key(),
+	)]
+	pub nft_metadata: UncheckedAccount<'info>,
+
+	#[account(
+		constraint = player_nft_token.owner == player.key(),
+		constraint = player_nft_token.mint == nft_mint.key(),
+	)]
+	pub player_nft_token: Account<'info, TokenAccount>,
+
+	// Held while the player is in the tournament so the NFT cannot gate anything else
+	#[account(
+		init,
+		payer = player,
+		space = NftLock::space(),
+		seeds = [b"nft_lock", nft_mint.key().as_ref()],
+		bump
+	)]
+	pub player_nft_lock: Box<Account<'info, NftLock>>,
+	pub system_program: Program<'info, System>,
+	/// CHECK: Token Metadata Program
+	#[account(address = mpl_token_metadata::ID)]
+	pub token_metadata_program: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct CancelTournament<'info> {
+	#[account(
+		mut,
+		close = organizer,
+		has_one = organizer,
+		seeds = [b"tournament", organizer.key().as_ref(), &tournament.tournament_id.to_le_bytes()],
+		bump = tournament.bump
+	)]
+	pub tournament: Box<Account<'info, Tournament>>,
+
+	/// CHECK: Tournament organizer, receives the rent
+	#[account(mut)]
+	pub organizer: UncheckedAccount<'info>,
+
+	// The organizer, or anyone once registration has closed
+	pub caller: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(node: u8)]
+pub struct OpenBracketRoom<'info> {
+	#[account(seeds = [b"marketplace"], bump = marketplace.bump)]
+	pub marketplace: Box<Account<'info, Marketplace>>,
+
+	#[account(
+		mut,
+		seeds = [
+			b"tournament",
+			tournament.organizer.as_ref(),
+			&tournament.tournament_id.to_le_bytes(),
+		],
+		bump = tournament.bump
+	)]
+	pub tournament: Box<Account<'info, Tournament>>,
+
+	#[account(
+		init,
+		payer = payer,
+		space = Room::space(None),
+		seeds = [b"bracket_room", tournament.key().as_ref(), &(node as u64).to_le_bytes()],
+		bump
+	)]
+	pub room: Box<Account<'info, Room>>,
+
+	#[account(
+		mut,
+		seeds = [b"nft_lock", creator_nft_lock.mint.as_ref()],
+		bump = creator_nft_lock.bump,
+		constraint = creator_nft_lock.room == tournament.key() @ ErrorCode::NftLockMismatch,
+	)]
+	pub creator_nft_lock: Box<Account<'info, NftLock>>,
+
+	#[account(
+		mut,
+		seeds = [b"nft_lock", challenger_nft_lock.mint.as_ref()],
+		bump = challenger_nft_lock.bump,
+		constraint = challenger_nft_lock.room == tournament.key() @ ErrorCode::NftLockMismatch,
+	)]
+	pub challenger_nft_lock: Box<Account<'info, NftLock>>,
+
+	#[account(mut)]
+	pub payer: Signer<'info>,
+	pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(node: u8)]
+pub struct ReportBracketRoom<'info> {
+	#[account(
+		mut,
+		seeds = [
+			b"tournament",
+			tournament.organizer.as_ref(),
+			&tournament.tournament_id.to_le_bytes(),
+		],
+		bump = tournament.bump
+	)]
+	pub tournament: Box<Account<'info, Tournament>>,
+
+	#[account(
+		mut,
+		close = payer,
+		seeds = [b"bracket_room", tournament.key().as_ref(), &room.room_id.to_le_bytes()],
+		bump = room.bump,
+		constraint = tournament.rooms.get(node as usize) == Some(&room.key()) @ ErrorCode::MatchNotReady,
+		constraint = room.status == RoomStatus::Closed as u8 @ ErrorCode::RoomNotClosed,
+		constraint = !room.bet_pool_open @ ErrorCode::BetPoolUnsettled,
+	)]
+	pub room: Box<Account<'info, Room>>,
+
+	/// CHECK: Opened the room, receives its rent
+	#[account(
+		mut,
+		constraint = tournament.room_payers.get(node as usize) == Some(&payer.key()) @ ErrorCode::Unauthorized,
+	)]
+	pub payer: UncheckedAccount<'info>,
+
+	/// CHECK: Player who advances, checked against the room result
+	#[account(mut)]
+	pub winner: UncheckedAccount<'info>,
+
+	/// CHECK: Player who is knocked out, checked against the room result
+	#[account(mut)]
+	pub loser: UncheckedAccount<'info>,
+
+	#[account(
+		mut,
+		seeds = [b"nft_lock", winner_nft_lock.mint.as_ref()],
+		bump = winner_nft_lock.bump,
+		constraint = winner_nft_lock.room == room.key() @ ErrorCode::NftLockMismatch,
+	)]
+	pub winner_nft_lock: Box<Account<'info, NftLock>>,
+
+	#[account(
+		mut,
+		seeds = [b"nft_lock", loser_nft_lock.mint.as_ref()],
+		bump = loser_nft_lock.bump,
+		constraint = loser_nft_lock.room == room.key() @ ErrorCode::NftLockMismatch,
+	)]
+	pub loser_nft_lock: Box<Account<'info, NftLock>>,
+}
+
+#[derive(Accounts)]
+pub struct ClaimTournamentPrize<'info> {
+	#[account(
+		mut,
+		seeds = [
+			b"tournament",
+			tournament.organizer.as_ref(),
+			&tournament.tournament_id.to_le_bytes(),
+		],
+		bump = tournament.bump
+	)]
+	pub tournament: Box<Account<'info, Tournament>>,
+
+	#[account(mut)]
+	pub winner: Signer<'info>,
+}
+
+// State Structs
+#[account]
+pub struct Marketplace {
+    pub admin: Pubkey,
+    pub fee_bps: u16,
+    pub total_collections: u64,
+    pub bump: u8,
+    // Fields below were added later; older accounts are grown with `migrate_marketplace`
+    pub creation_policy: u8, // CreationPolicy
+    pub listing_fee_lamports: u64, // Paid by non-admin creators under the Permissionless policy
+    pub treasury: Pubkey,
+    pub referee: Pubkey, // Default referee for matchmaking rooms
+    pub room_rake_bps: u16, // Rake on room pots with a winner
+    pub room_rake_cap: u64, // Max rake per lamport room; 0 = uncapped
+    pub bet_fee_bps: u16, // House fee on spectator bet pools
+    pub dispute_window_secs: i64, // Snapshotted onto rooms at creation
+    pub dispute_bond_lamports: u64,
+    pub current_season: Option<Pubkey>, // Season new rooms are entered into
+}
+
+impl Marketplace {
+    pub fn space() -> usize {
+        8 + // discriminator
+        32 + // admin
+        2 + // fee_bps
+        8 + // total_collections
+        1 + // bump
+        1 + // creation_policy
+        8 + // listing_fee_lamports
+        32 + // treasury
+        32 + // referee
+        2 + // room_rake_bps
+        8 + // room_rake_cap
+        2 + // bet_fee_bps
+        8 + // dispute_window_secs
+        8 + // dispute_bond_lamports
+        1 + 32 // current_season
+    }
+}
 
-	#[account(mut)]
-	pub creator: Signer<'info>,
-	pub system_program: Program<'info, System>,
+#[account]
+pub struct AllowedCreator {
+    pub marketplace: Pubkey,
+    pub creator: Pubkey,
+    pub bump: u8,
 }
 
-// State Structs
+impl AllowedCreator {
+    pub fn space() -> usize {
+        8 + 32 + 32 + 1
+    }
+}
+
+// Rooms created by this wallet are not raked
 #[account]
-pub struct Marketplace {
-    pub admin: Pubkey,
-    pub fee_bps: u16,
-    pub total_collections: u64,
+pub struct RakeExemption {
+    pub marketplace: Pubkey,
+    pub wallet: Pubkey,
     pub bump: u8,
 }
 
+impl RakeExemption {
+    pub fn space() -> usize {
+        8 + 32 + 32 + 1
+    }
+}
+
 #[account]
 pub struct Presale {
     pub admin: Pubkey,
@@ -1057,6 +6324,120 @@ pub struct NFTCollection {
     pub mint: Pubkey,
     pub is_active: bool,
     pub bump: u8,
+    // Fields below were appended after `bump`; collections created before them are brought
+    // to this layout by migrate_collection
+    pub is_sized: bool,
+    pub creators: Vec<CollectionCreator>, // Up to 5 creators; shares sum to 100
+    pub total_types: u64, // Number of NftTypeIndex entries for this collection
+    pub hashed_seed: bool, // PDA seeded by name hash; false for collections seeded by the raw name
+    pub verified: bool, // Curated by the marketplace admin
+}
+
+impl NFTCollection {
+    // is_sized, empty creators, total_types, hashed_seed, verified
+    pub const APPENDED_FIELDS_MIN_LEN: usize = 1 + 4 + 8 + 1 + 1;
+
+    pub fn space(name_len: usize) -> usize {
+        8 + // discriminator
+        32 + // admin
+        4 + name_len + // name
+        4 + mpl_token_metadata::MAX_SYMBOL_LENGTH + // symbol
+        4 + mpl_token_metadata::MAX_URI_LENGTH + // uri
+        2 + // royalty
+        32 + // mint
+        1 + // is_active
+        1 + // bump
+        1 + // is_sized
+        4 + mpl_token_metadata::MAX_CREATOR_LIMIT * (32 + 1) + // creators
+        8 + // total_types
+        1 + // hashed_seed
+        1 // verified
+    }
+
+    // Name component of the collection PDA seeds
+    pub fn name_seed(&self) -> Vec<u8> {
+        if self.hashed_seed {
+            name_hash(&self.name).to_vec()
+        } else {
+            self.name.as_bytes().to_vec()
+        }
+    }
+
+    // Creators receiving primary sales; collections without splits pay the admin
+    pub fn creator_splits(&self) -> Vec<CollectionCreator> {
+        if self.creators.is_empty() {
+            vec![CollectionCreator { address: self.admin, share: 100 }]
+        } else {
+            self.creators.clone()
+        }
+    }
+
+    // Metadata creators array; only the admin signs mints, so only the admin is verified
+    pub fn metadata_creators(&self) -> Vec<Creator> {
+        self.creator_splits()
+            .into_iter()
+            .map(|c| Creator {
+                address: c.address,
+                verified: c.address == self.admin,
+                share: c.share,
+            })
+            .collect()
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Eq)]
+pub struct CollectionCreator {
+    pub address: Pubkey,
+    pub share: u8,
+}
+
+// Registry entry: [b"collection_index", marketplace, index] -> collection
+#[account]
+pub struct CollectionIndex {
+    pub marketplace: Pubkey,
+    pub collection: Pubkey,
+    pub index: u64,
+    pub bump: u8,
+}
+
+impl CollectionIndex {
+    pub fn space() -> usize {
+        8 + 32 + 32 + 8 + 1
+    }
+}
+
+// Reverse registry entry: [b"collection_indexed", collection] or [b"type_indexed", nft_type]
+// -> index. Created with the index entry, so an item cannot be indexed twice.
+#[account]
+pub struct IndexedEntry {
+    pub index: u64,
+    pub bump: u8,
+}
+
+impl IndexedEntry {
+    pub fn space() -> usize {
+        8 + 8 + 1
+    }
+
+    pub fn set(&mut self, index: u64, bump: u8) {
+        self.index = index;
+        self.bump = bump;
+    }
+}
+
+// Registry entry: [b"type_index", collection, index] -> nft_type
+#[account]
+pub struct NftTypeIndex {
+    pub collection: Pubkey,
+    pub nft_type: Pubkey,
+    pub index: u64,
+    pub bump: u8,
+}
+
+impl NftTypeIndex {
+    pub fn space() -> usize {
+        8 + 32 + 32 + 8 + 1
+    }
 }
 
 #[account]
@@ -1069,35 +6450,731 @@ pub struct NftType {
     pub current_supply: u64,
     pub stake_multiplier: u64, // Multiplier for staking rewards (basis points, e.g., 10000 = 1x)
     pub bump: u8,
+    // Fields below were appended after `bump`; types created before them are brought to
+    // this layout by migrate_nft_type
+    pub programmable: bool, // Minted as a Metaplex programmable NFT (pNFT)
+    pub rule_set: Option<Pubkey>, // Token Auth Rules rule set enforced on pNFT transfers
+    pub hashed_seed: bool, // PDA seeded by name hash; false for types seeded by the raw name
+    pub battle_stats: BattleStats, // Zero until set with `set_battle_stats`
+}
+
+impl NftType {
+    // programmable, no rule_set, hashed_seed, battle_stats
+    pub const APPENDED_FIELDS_MIN_LEN: usize = 1 + 1 + 1 + BattleStats::SIZE;
+
+    pub fn space(name_len: usize) -> usize {
+        8 + // discriminator
+        32 + // collection
+        4 + name_len + // name
+        4 + mpl_token_metadata::MAX_URI_LENGTH + // uri
+        8 + // price
+        8 + // max_supply
+        8 + // current_supply
+        8 + // stake_multiplier
+        1 + // bump
+        1 + // programmable
+        1 + 32 + // rule_set
+        1 + // hashed_seed
+        BattleStats::SIZE // battle_stats
+    }
+
+    // Name component of the type PDA seeds
+    pub fn name_seed(&self) -> Vec<u8> {
+        if self.hashed_seed {
+            name_hash(&self.name).to_vec()
+        } else {
+            self.name.as_bytes().to_vec()
+        }
+    }
+}
+
+// Type an item was minted as: [b"item_type", nft_mint]. Written by the mint instructions, or
+// by backfill_item_type for older items; battle rooms read an item's stats through it.
+#[account]
+pub struct ItemType {
+    pub nft_type: Pubkey,
+    pub bump: u8,
+}
+
+impl ItemType {
+    pub fn space() -> usize {
+        8 + 32 + 1
+    }
+}
+
+// What an NFT type fights with in battle rooms
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default)]
+pub struct BattleStats {
+    pub attack: u16,
+    pub defense: u16,
+    pub speed: u16, // Faster hero strikes first
+    pub rarity: u8, // 0 (common) to MAX_RARITY; raises the critical hit chance
+}
+
+impl BattleStats {
+    pub const SIZE: usize = 2 + 2 + 2 + 1;
+}
+
+// Head-to-head room: [b"room", creator, room_id], or [b"bracket_room", tournament, node]
+// for a tournament match
+#[account]
+#[derive(Default)]
+pub struct Room {
+	pub creator: Pubkey,
+	pub challenger: Option<Pubkey>,
+	pub room_id: u64,
+	pub stake_lamports: u64, // Per-player stake; in stake_mint base units for token rooms
+	pub status: u8,
+	pub bump: u8,
+	// Fields below were added after rooms first shipped; rooms allocated with only the
+	// fields above are refunded through refund_legacy_room
+	pub referee: Pubkey, // Signs resolve_room
+	pub winner: Option<Pubkey>, // Set on resolve; None after a draw
+	pub mode: u8, // RoomMode
+	pub creator_commitment: [u8; 32],
+	pub challenger_commitment: [u8; 32],
+	pub creator_choice: Option<u8>, // Set when revealed
+	pub challenger_choice: Option<u8>,
+	pub timeout_secs: i64, // Match length allowed once the room is full
+	pub deadline: i64, // Set on join; resolve or reveal before it, timeout_room after it
+	pub collection: Pubkey, // NFTCollection both players' NFTs must belong to
+	pub stake_mint: Option<Pubkey>, // SPL token stake held in the room vault; None for lamports
+	pub rake_bps: u16, // Snapshot of the marketplace rake; 0 for exempt creators
+	pub rake_cap: u64, // Lamports; 0 (uncapped) for token rooms
+	pub treasury: Pubkey, // Receives the rake
+	pub creator_nft: Pubkey, // Mint of the NFT each player gated with
+	pub challenger_nft: Pubkey,
+	pub wager_nfts: bool, // Gating NFTs are escrowed and go to the winner
+	pub invited_challenger: Option<Pubkey>, // Only wallet allowed to join a private room
+	pub invite_hash: Option<[u8; 32]>, // Hash of the invite secret for a private room
+	pub best_of: u8, // Games in the series; 1 for a single game
+	pub creator_game_wins: u8,
+	pub challenger_game_wins: u8,
+	pub games_played: u8, // Including drawn games
+	pub bet_pool_open: bool, // A spectator bet pool must settle before the room closes or rematches
+	pub creator_stats: BattleStats, // Battle rooms: stats of each player's NFT type
+	pub challenger_stats: BattleStats,
+	pub battle_entropy: [u8; 32], // XOR of the revealed salts in the current game
+	pub dispute_window_secs: i64, // 0 = referee results pay out immediately
+	pub dispute_bond: u64, // Lamports a player posts to dispute a result
+	pub proposed_winner: Option<Pubkey>, // Referee result awaiting its dispute window
+	pub dispute_deadline: i64,
+	pub season: Option<Pubkey>, // Season the room's result counts towards
+	pub tournament: Option<Pubkey>, // Tournament this room is a bracket match of
+}
+
+// Room layout before referees, modes and the later fields were added; only read by
+// refund_legacy_room
+#[derive(AnchorDeserialize)]
+pub struct LegacyRoom {
+	pub creator: Pubkey,
+	pub challenger: Option<Pubkey>,
+	pub room_id: u64,
+	pub stake_lamports: u64,
+	pub status: u8,
+	pub bump: u8,
+}
+
+impl LegacyRoom {
+	pub const SPACE: usize = 8 + 32 + (1 + 32) + 8 + 8 + 1 + 1;
+}
+
+impl Room {
+	pub fn space(_name: Option<&str>) -> usize {
+		// discriminator
+		8 +
+		// creator
+		32 +
+		// challenger (Option<Pubkey>) -> 1 + 32
+		1 + 32 +
+		// room_id
+		8 +
+		// stake_lamports
+		8 +
+		// status
+		1 +
+		// bump
+		1 +
+		// referee
+		32 +
+		// winner (Option<Pubkey>) -> 1 + 32
+		1 + 32 +
+		// mode
+		1 +
+		// creator_commitment, challenger_commitment
+		32 + 32 +
+		// creator_choice, challenger_choice (Option<u8>) -> 2 each
+		2 + 2 +
+		// timeout_secs, deadline
+		8 + 8 +
+		// collection
+		32 +
+		// stake_mint (Option<Pubkey>) -> 1 + 32
+		1 + 32 +
+		// rake_bps, rake_cap, treasury
+		2 + 8 + 32 +
+		// creator_nft, challenger_nft
+		32 + 32 +
+		// wager_nfts
+		1 +
+		// invited_challenger, invite_hash
+		(1 + 32) + (1 + 32) +
+		// best_of, creator_game_wins, challenger_game_wins, games_played
+		1 + 1 + 1 + 1 +
+		// bet_pool_open
+		1 +
+		// creator_stats, challenger_stats, battle_entropy
+		BattleStats::SIZE * 2 + 32 +
+		// dispute_window_secs, dispute_bond, proposed_winner, dispute_deadline
+		8 + 8 + (1 + 32) + 8 +
+		// season
+		1 + 32 +
+		// tournament
+		1 + 32
+	}
+
+	// Start a new room with every other field reset; the caller sets the mode, players,
+	// stake and terms that apply
+	pub fn init(&mut self, creator: Pubkey, room_id: u64, collection: Pubkey, creator_nft: Pubkey, bump: u8) {
+		*self = Room {
+			creator,
+			room_id,
+			collection,
+			creator_nft,
+			status: RoomStatus::Waiting as u8,
+			best_of: 1,
+			bump,
+			..Default::default()
+		};
+	}
+
+	// PDA seeds besides the room id: bracket rooms live under their tournament so nobody can
+	// take a match's address ahead of it being opened
+	pub fn seed_prefix(&self) -> &'static [u8] {
+		if self.tournament.is_some() { b"bracket_room" } else { b"room" }
+	}
+
+	pub fn seed_owner(&self) -> Pubkey {
+		self.tournament.unwrap_or(self.creator)
+	}
+
+	// Fix the marketplace's current rake, dispute terms and season for the room.
+	// The rake cap is denominated in lamports, so token rooms are uncapped; set stake_mint first.
+	pub fn snapshot_terms(&mut self, marketplace: &Marketplace, rake_exempt: bool) {
+		self.rake_bps = if rake_exempt { 0 } else { marketplace.room_rake_bps };
+		self.rake_cap = if self.stake_mint.is_none() { marketplace.room_rake_cap } else { 0 };
+		self.treasury = marketplace.treasury;
+		self.dispute_window_secs = marketplace.dispute_window_secs;
+		self.dispute_bond = marketplace.dispute_bond_lamports;
+		self.season = marketplace.current_season;
+	}
+
+	// In play with nothing about the result public yet: no reveal and no finished game
+	pub fn accepts_bets(&self) -> bool {
+		(self.status == RoomStatus::Ongoing as u8 || self.status == RoomStatus::Committed as u8)
+			&& self.games_played == 0
+			&& self.creator_choice.is_none()
+			&& self.challenger_choice.is_none()
+	}
+
+	// Joined and not yet settled
+	pub fn is_in_play(&self) -> bool {
+		self.status == RoomStatus::Ongoing as u8
+			|| self.status == RoomStatus::Committed as u8
+			|| self.status == RoomStatus::Revealing as u8
+	}
+
+	// Rake taken from a pot, limited by the room's cap
+	pub fn rake(&self, pot: u64) -> u64 {
+		rake_amount(pot, self.rake_bps, self.rake_cap)
+	}
+}
+
+// Marks an NFT as gating an active room, queue seat or tournament entry:
+// [b"nft_lock", mint]. Closed when that room settles or is cancelled, the player leaves
+// the queue, or the player is knocked out of or finishes the tournament.
+#[account]
+pub struct NftLock {
+	pub mint: Pubkey,
+	pub owner: Pubkey, // Player who gated with the NFT; receives the rent back
+	pub room: Pubkey, // Room, multi-player room, match queue or tournament holding the lock
+	pub bump: u8,
+}
+
+impl NftLock {
+	pub fn space() -> usize {
+		8 + 32 + 32 + 32 + 1
+	}
+
+	pub fn set(&mut self, mint: Pubkey, owner: Pubkey, room: Pubkey, bump: u8) {
+		self.mint = mint;
+		self.owner = owner;
+		self.room = room;
+		self.bump = bump;
+	}
+}
+
+pub const RECENT_MATCHES: usize = 8;
+
+// Season with a prize pool for the top of its points leaderboard:
+// [b"season", season_id]. Holds the prize pool lamports.
+#[account]
+pub struct Season {
+	pub season_id: u64,
+	pub start_ts: i64,
+	pub end_ts: i64,
+	pub rake_share_bps: u16, // Share of lamport room rake paid into the prize pool
+	pub payout_bps: Vec<u16>, // Prize share per leaderboard place; sums to 10000
+	pub leaderboard: Vec<LeaderboardEntry>, // At most one entry per paid place, highest first
+	pub finalized: bool,
+	pub prize_pool: u64, // Set when finalized
+	pub claimed: u16, // Bit per leaderboard place already paid
+	pub bump: u8,
+}
+
+impl Season {
+	pub fn space(places: usize) -> usize {
+		// discriminator
+		8 +
+		// season_id, start_ts, end_ts
+		8 + 8 + 8 +
+		// rake_share_bps
+		2 +
+		// payout_bps (Vec<u16>)
+		4 + places * 2 +
+		// leaderboard (Vec<LeaderboardEntry>)
+		4 + places * LeaderboardEntry::SIZE +
+		// finalized, prize_pool, claimed
+		1 + 8 + 2 +
+		// bump
+		1
+	}
+
+	pub fn is_live(&self, now: i64) -> bool {
+		!self.finalized && now >= self.start_ts && now < self.end_ts
+	}
+
+	// Update a wallet's points on the leaderboard. Points only grow, so a wallet that drops
+	// off can rejoin once it passes the lowest entry; ties keep the earlier entry ahead.
+	pub fn rank(&mut self, wallet: Pubkey, points: u64) {
+		let entry = LeaderboardEntry { wallet, points };
+		if let Some(existing) = self.leaderboard.iter_mut().find(|entry| entry.wallet == wallet) {
+			existing.points = points;
+		} else if self.leaderboard.len() < self.payout_bps.len() {
+			self.leaderboard.push(entry);
+		} else if let Some(last) = self.leaderboard.last_mut().filter(|last| points > last.points) {
+			*last = entry;
+		} else {
+			return;
+		}
+		self.leaderboard.sort_by_key(|entry| std::cmp::Reverse(entry.points));
+	}
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy)]
+pub struct LeaderboardEntry {
+	pub wallet: Pubkey,
+	pub points: u64,
+}
+
+impl LeaderboardEntry {
+	pub const SIZE: usize = 32 + 8;
+}
+
+#[event]
+pub struct SeasonFinalized {
+	pub season: Pubkey,
+	pub prize_pool: u64,
+	pub winners: Vec<Pubkey>, // First place first
+}
+
+// Key a wallet lets act for it in room gameplay: [b"session", wallet]
+#[account]
+pub struct SessionKey {
+	pub wallet: Pubkey,
+	pub session_key: Pubkey,
+	pub expires_at: i64,
+	pub scope: u8, // SESSION_SCOPE_* flags
+	pub bump: u8,
+}
+
+impl SessionKey {
+	pub fn space() -> usize {
+		8 + 32 + 32 + 8 + 1 + 1
+	}
+}
+
+// Rating and stats for a wallet: [b"profile", wallet]
+#[account]
+pub struct PlayerProfile {
+	pub wallet: Pubkey,
+	pub rating: u32, // Elo
+	pub wins: u32,
+	pub losses: u32,
+	pub draws: u32,
+	pub total_wagered: u64, // Lamports staked in settled rooms
+	pub total_won: u64, // Lamports paid out from settled rooms
+	pub recent: [MatchRecord; RECENT_MATCHES], // Ring buffer of the latest results
+	pub recent_next: u8, // Slot the next result is written to
+	pub season: Pubkey, // Season the points below belong to
+	pub season_points: u64,
+	pub bump: u8,
+}
+
+impl PlayerProfile {
+	pub fn space() -> usize {
+		8 + 32 + 4 + 4 + 4 + 4 + 8 + 8 + RECENT_MATCHES * MatchRecord::SIZE + 1 + 32 + 8 + 1
+	}
+
+	// Set up a profile created by `init_if_needed`; no-op for existing profiles
+	pub fn init_if_new(&mut self, wallet: Pubkey, bump: u8) {
+		if self.wallet == Pubkey::default() {
+			self.wallet = wallet;
+			self.rating = INITIAL_RATING;
+			self.bump = bump;
+		}
+	}
+
+	pub fn record(&mut self, record: MatchRecord) {
+		self.rating = (self.rating as i64 + record.rating_delta as i64).max(0) as u32;
+		match record.result {
+			r if r == MatchResult::Win as u8 => self.wins += 1,
+			r if r == MatchResult::Loss as u8 => self.losses += 1,
+			_ => self.draws += 1,
+		}
+		self.total_wagered += record.stake;
+		self.total_won += record.payout;
+
+		self.recent[self.recent_next as usize] = record;
+		self.recent_next = ((self.recent_next as usize + 1) % RECENT_MATCHES) as u8;
+	}
+
+	// Add season points, starting over when a new season begins; returns the season total
+	pub fn add_season_points(&mut self, season: Pubkey, points: u64) -> u64 {
+		if self.season != season {
+			self.season = season;
+			self.season_points = 0;
+		}
+		self.season_points += points;
+		self.season_points
+	}
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default)]
+pub struct MatchRecord {
+	pub room: Pubkey,
+	pub opponent: Pubkey,
+	pub result: u8, // MatchResult
+	pub rating_delta: i32,
+	pub stake: u64, // Lamports; 0 for token-staked rooms
+	pub payout: u64,
+	pub timestamp: i64,
+}
+
+impl MatchRecord {
+	pub const SIZE: usize = 32 + 32 + 1 + 4 + 8 + 8 + 8;
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq)]
+pub enum MatchResult {
+	Loss = 0,
+	Win = 1,
+	Draw = 2,
+}
+
+// Matchmaking queue for one collection and stake tier:
+// [b"match_queue", collection, stake_lamports]. Holds the waiting player's stake.
+#[account]
+pub struct MatchQueue {
+	pub collection: Pubkey,
+	pub stake_lamports: u64,
+	pub timeout_secs: i64, // Timeout for rooms created by this queue
+	pub waiting: Option<Pubkey>,
+	pub matches: u64, // Rooms created so far
+	pub waiting_nft: Pubkey, // NFT the waiting player gated with
+	pub bump: u8,
+}
+
+impl MatchQueue {
+	pub fn space() -> usize {
+		8 + 32 + 8 + 8 + (1 + 32) + 8 + 32 + 1
+	}
+
+	// Room id for the next pairing, derived from the queue so it does not collide
+	// with ids the creator picks for their own rooms
+	pub fn next_room_id(&self) -> u64 {
+		let hash = anchor_lang::solana_program::hash::hashv(&[
+			self.collection.as_ref(),
+			&self.stake_lamports.to_le_bytes(),
+			&self.matches.to_le_bytes(),
+		]);
+		u64::from_le_bytes(hash.to_bytes()[..8].try_into().unwrap())
+	}
+}
+
+// Spectator parimutuel pool on one room: [b"bet_pool", room]. Holds all bets.
+#[account]
+pub struct BetPool {
+	pub room: Pubkey,
+	pub cutoff: i64, // No bets at or after this time
+	pub fee_bps: u16, // House fee on a settled pool
+	pub treasury: Pubkey, // Receives the house fee
+	pub creator_total: u64,
+	pub challenger_total: u64,
+	pub status: u8, // BetPoolStatus
+	pub winning_side: Option<u8>, // BetSide
+	pub payout_total: u64, // Shared by the winning side after the fee
+	pub bump: u8,
+	pub payer: Pubkey, // Opened the pool; gets the rent back from close_bet_pool
+	pub open_bets: u32, // Bets not yet claimed
+}
+
+impl BetPool {
+	pub fn space() -> usize {
+		8 + 32 + 8 + 2 + 32 + 8 + 8 + 1 + (1 + 1) + 8 + 1 + 32 + 4
+	}
+}
+
+// One wallet's bet on a pool: [b"bet", bet_pool, bettor]
+#[account]
+pub struct Bet {
+	pub pool: Pubkey,
+	pub bettor: Pubkey,
+	pub side: u8, // BetSide
+	pub amount: u64,
+	pub bump: u8,
+}
+
+impl Bet {
+	pub fn space() -> usize {
+		8 + 32 + 32 + 1 + 8 + 1
+	}
+}
+
+#[event]
+pub struct BetPoolSettled {
+	pub room: Pubkey,
+	pub pool: Pubkey,
+	pub winning_side: Option<u8>, // None when every bet is refunded
+	pub payout_total: u64,
+}
+
+// Bond posted by a player disputing a room's proposed result: [b"room_dispute", room]
+#[account]
+pub struct RoomDispute {
+	pub room: Pubkey,
+	pub disputer: Pubkey,
+	pub bond: u64,
+	pub bump: u8,
+}
+
+impl RoomDispute {
+	pub fn space() -> usize {
+		8 + 32 + 32 + 8 + 1
+	}
+}
+
+#[event]
+pub struct ResultProposed {
+	pub room: Pubkey,
+	pub winner: Option<Pubkey>,
+	pub dispute_deadline: i64,
+}
+
+#[event]
+pub struct RoomArbitrated {
+	pub room: Pubkey,
+	pub proposed_winner: Option<Pubkey>,
+	pub winner: Option<Pubkey>,
+	pub bond_slashed: bool,
+}
+
+#[event]
+pub struct SeriesGameRecorded {
+	pub room: Pubkey,
+	pub game: u8,
+	pub winner: Option<Pubkey>, // None for a drawn game
+	pub creator_wins: u8,
+	pub challenger_wins: u8,
+}
+
+#[event]
+pub struct QueueMatched {
+	pub queue: Pubkey,
+	pub room: Pubkey,
+	pub creator: Pubkey,
+	pub challenger: Pubkey,
 }
 
+// Room with a fixed number of seats, each escrowing the same lamport stake
 #[account]
-pub struct Room {
+pub struct MultiRoom {
 	pub creator: Pubkey,
-	pub challenger: Option<Pubkey>,
 	pub room_id: u64,
 	pub stake_lamports: u64,
-	pub status: u8,
+	pub seat_count: u8,
+	pub seats: Vec<Pubkey>, // Seated wallets in join order; seat 0 is the creator
+	pub seat_nfts: Vec<Pubkey>, // NFT each seat gated with
+	pub payout_bps: Vec<u16>, // Share of the prize per place; sums to 10000
+	pub status: u8, // RoomStatus
+	pub referee: Pubkey,
+	pub collection: Pubkey,
+	pub timeout_secs: i64,
+	pub deadline: i64, // Set when the last seat fills
+	pub rake_bps: u16,
+	pub rake_cap: u64,
+	pub treasury: Pubkey,
 	pub bump: u8,
 }
 
-impl Room {
-	pub fn space(_name: Option<&str>) -> usize {
+impl MultiRoom {
+	pub fn space(seat_count: u8, payout_places: usize) -> usize {
 		// discriminator
 		8 +
 		// creator
 		32 +
-		// challenger (Option<Pubkey>) -> 1 + 32
-		1 + 32 +
-		// room_id
-		8 +
-		// stake_lamports
-		8 +
+		// room_id, stake_lamports
+		8 + 8 +
+		// seat_count
+		1 +
+		// seats, seat_nfts (Vec<Pubkey>)
+		4 + seat_count as usize * 32 +
+		4 + seat_count as usize * 32 +
+		// payout_bps (Vec<u16>)
+		4 + payout_places * 2 +
 		// status
 		1 +
+		// referee, collection
+		32 + 32 +
+		// timeout_secs, deadline
+		8 + 8 +
+		// rake_bps, rake_cap, treasury
+		2 + 8 + 32 +
+		// bump
+		1
+	}
+}
+
+#[event]
+pub struct MultiRoomResolved {
+	pub room: Pubkey,
+	pub placements: Vec<Pubkey>, // Paid players, first place first
+	pub payouts: Vec<u64>,
+	pub rake: u64,
+}
+
+// Single-elimination tournament: [b"tournament", organizer, tournament_id]. Holds the entry
+// fees as its prize pool. `bracket` is a binary tree of slots stored as a heap: the seeded
+// entrants fill the leaves, and match `k` is played between the holders of slots 2k+1 and
+// 2k+2 and its winner fills slot `k`, so slot 0 ends up with the champion.
+#[account]
+pub struct Tournament {
+	pub organizer: Pubkey,
+	pub tournament_id: u64,
+	pub collection: Pubkey,
+	pub entry_fee: u64,
+	pub size: u8, // Entrants; a power of two
+	pub mode: u8, // RoomMode of the bracket rooms
+	pub referee: Pubkey, // Referee of the bracket rooms in Referee mode
+	pub timeout_secs: i64,
+	pub registration_deadline: i64, // After it anyone can cancel a tournament that has not filled
+	pub status: u8, // TournamentStatus
+	pub payout_bps: Vec<u16>, // Prize share per finishing place; sums to 10000
+	pub entrants: Vec<TournamentEntry>, // In entry order
+	pub bracket: Vec<Pubkey>, // 2 * size - 1 slots; default until decided
+	pub rooms: Vec<Pubkey>, // Room of each match; default until opened
+	pub room_payers: Vec<Pubkey>, // Paid each open room's rent, which is refunded on report
+	pub prize_pool: u64, // Set when the final is reported
+	pub claimed: u32, // Bit per finishing place already paid
+	pub bump: u8,
+}
+
+impl Tournament {
+	pub fn space(size: u8, payout_places: usize) -> usize {
+		let size = size as usize;
+		// discriminator
+		8 +
+		// organizer, tournament_id, collection, entry_fee
+		32 + 8 + 32 + 8 +
+		// size, mode, referee, timeout_secs, registration_deadline, status
+		1 + 1 + 32 + 8 + 8 + 1 +
+		// payout_bps (Vec<u16>)
+		4 + payout_places * 2 +
+		// entrants (Vec<TournamentEntry>)
+		4 + size * TournamentEntry::SIZE +
+		// bracket, rooms, room_payers (Vec<Pubkey>)
+		4 + (2 * size).saturating_sub(1) * 32 +
+		4 + size.saturating_sub(1) * 32 +
+		4 + size.saturating_sub(1) * 32 +
+		// prize_pool, claimed
+		8 + 4 +
 		// bump
 		1
 	}
+
+	pub fn entry(&self, wallet: &Pubkey) -> Option<&TournamentEntry> {
+		self.entrants.iter().find(|entry| entry.wallet == *wallet)
+	}
+
+	// Place the entrants in the leaves by rating, highest first (ties by entry order), so the
+	// top seed meets the bottom seed and the top two seeds can only meet in the final
+	pub fn seed(&mut self) {
+		let mut ranked = self.entrants.clone();
+		ranked.sort_by_key(|entry| std::cmp::Reverse(entry.rating));
+		let mut order = vec![0usize];
+		while order.len() < ranked.len() {
+			let slots = order.len() * 2;
+			order = order.iter().flat_map(|&seed| [seed, slots - 1 - seed]).collect();
+		}
+		let first_leaf = ranked.len() - 1;
+		for (i, seed) in order.into_iter().enumerate() {
+			self.bracket[first_leaf + i] = ranked[seed].wallet;
+		}
+	}
+
+	// Players of match `node`, upper slot first
+	pub fn match_players(&self, node: usize) -> (Pubkey, Pubkey) {
+		(self.bracket[2 * node + 1], self.bracket[2 * node + 2])
+	}
+
+	// Wallet finishing in `place`: the champion, then the loser of each match in bracket order
+	pub fn finisher(&self, place: usize) -> Pubkey {
+		if place == 0 {
+			return self.bracket[0];
+		}
+		let (upper, lower) = self.match_players(place - 1);
+		if self.bracket[place - 1] == upper { lower } else { upper }
+	}
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy)]
+pub struct TournamentEntry {
+	pub wallet: Pubkey,
+	pub nft: Pubkey, // NFT locked for the entry
+	pub rating: u32, // Rating when entered, used for seeding
+}
+
+impl TournamentEntry {
+	pub const SIZE: usize = 32 + 32 + 4;
+}
+
+#[event]
+pub struct TournamentFinished {
+	pub tournament: Pubkey,
+	pub prize_pool: u64,
+	pub winners: Vec<Pubkey>, // Paid places, champion first
+}
+
+#[event]
+pub struct RoomResolved {
+	pub room: Pubkey,
+	pub creator: Pubkey,
+	pub challenger: Pubkey,
+	pub winner: Option<Pubkey>, // None for a draw
+	pub stake_mint: Option<Pubkey>, // Payouts are in this token's base units, or lamports if None
+	pub creator_payout: u64,
+	pub challenger_payout: u64,
+	pub rake: u64, // Paid to the treasury
 }
 
 #[account]
@@ -1146,11 +7223,85 @@ impl StakeAccount {
     }
 }
 
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Eq)]
+pub enum CreationPolicy {
+    AdminOnly = 0,
+    Allowlisted = 1,
+    Permissionless = 2,
+}
+
 #[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Eq)]
 pub enum RoomStatus {
 	Waiting = 0,
 	Ongoing = 1,
 	Closed = 2,
+	Committed = 3, // Both commitments in, nobody revealed yet
+	Revealing = 4, // One player revealed
+	Proposed = 5, // Referee result inside its dispute window
+	Disputed = 6, // Awaiting admin arbitration
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq)]
+pub enum TournamentStatus {
+	Registering = 0,
+	Running = 1,
+	Finished = 2,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq)]
+pub enum BetSide {
+	Creator = 0,
+	Challenger = 1,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq)]
+pub enum BetPoolStatus {
+	Open = 0,
+	Settled = 1, // Winning side shares the pool
+	Refunding = 2, // Every bet gets its amount back
+}
+
+// How a room is decided
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq)]
+pub enum RoomMode {
+	Referee = 0,
+	CoinFlip = 1, // Each player picks a bit; the creator wins if they match
+	RockPaperScissors = 2, // 0 = rock, 1 = paper, 2 = scissors
+	Battle = 3, // NFT type stats fight; the revealed salts seed the battle and the choice is 0
+}
+
+pub enum GameOutcome {
+	CreatorWins,
+	ChallengerWins,
+	Draw,
+}
+
+impl RoomMode {
+	// Number of valid choices in a commit-reveal mode
+	pub fn choices(mode: u8) -> u8 {
+		match mode {
+			m if m == RoomMode::CoinFlip as u8 => 2,
+			m if m == RoomMode::RockPaperScissors as u8 => 3,
+			m if m == RoomMode::Battle as u8 => 1,
+			_ => 0,
+		}
+	}
+
+	pub fn outcome(mode: u8, creator_choice: u8, challenger_choice: u8) -> GameOutcome {
+		if mode == RoomMode::CoinFlip as u8 {
+			if creator_choice == challenger_choice {
+				GameOutcome::CreatorWins
+			} else {
+				GameOutcome::ChallengerWins
+			}
+		} else {
+			match (3 + creator_choice - challenger_choice) % 3 {
+				0 => GameOutcome::Draw,
+				1 => GameOutcome::CreatorWins,
+				_ => GameOutcome::ChallengerWins,
+			}
+		}
+	}
 }
 
 #[error_code]
@@ -1183,6 +7334,176 @@ pub enum ErrorCode {
     NFTAlreadyStaked,
     #[msg("Invalid NFT mint")]
     InvalidNFTMint,
+    #[msg("NFT type is not programmable")]
+    NotProgrammable,
+    #[msg("NFT type must be minted as a programmable NFT")]
+    ProgrammableNftType,
+    #[msg("Authorization rule set does not match the NFT type")]
+    InvalidRuleSet,
+    #[msg("Collection is already a sized collection")]
+    CollectionAlreadySized,
+    #[msg("Too many creators (max 5)")]
+    TooManyCreators,
+    #[msg("Creator shares must be unique wallets summing to 100")]
+    InvalidCreatorShares,
+    #[msg("Creator accounts do not match the collection creators")]
+    InvalidCreatorAccounts,
+    #[msg("Registry index is out of range")]
+    InvalidRegistryIndex,
+    #[msg("Name must not be empty")]
+    EmptyName,
+    #[msg("Name is too long")]
+    NameTooLong,
+    #[msg("Symbol is too long (max 10 bytes)")]
+    SymbolTooLong,
+    #[msg("URI is too long (max 200 bytes)")]
+    UriTooLong,
+    #[msg("Invalid collection creation policy")]
+    InvalidCreationPolicy,
+    #[msg("Creator is not allowlisted")]
+    CreatorNotAllowlisted,
+    #[msg("Treasury account is required to pay the listing fee")]
+    MissingTreasury,
+    #[msg("No referee configured for this room")]
+    MissingReferee,
+    #[msg("Winner must be a participant of the room")]
+    InvalidWinner,
+    #[msg("Invalid room mode")]
+    InvalidRoomMode,
+    #[msg("Commit-reveal rooms require a commitment")]
+    MissingCommitment,
+    #[msg("Room is not accepting reveals")]
+    RoomNotRevealing,
+    #[msg("Room deadline has passed")]
+    RoomDeadlinePassed,
+    #[msg("Room deadline has not been reached")]
+    RoomDeadlineNotReached,
+    #[msg("Invalid choice for this game")]
+    InvalidChoice,
+    #[msg("Choice already revealed")]
+    AlreadyRevealed,
+    #[msg("Revealed choice does not match the commitment")]
+    CommitmentMismatch,
+    #[msg("Room timeout must be between one minute and seven days")]
+    InvalidRoomTimeout,
+    #[msg("Collection has not been verified by the marketplace")]
+    CollectionNotVerified,
+    #[msg("Collection does not match the room")]
+    CollectionMismatch,
+    #[msg("Invalid Token Metadata account")]
+    InvalidMetadata,
+    #[msg("NFT is not a verified item of the collection")]
+    NftNotInCollection,
+    #[msg("Token-staked rooms require the vault, stake token accounts and token program")]
+    MissingStakeAccounts,
+    #[msg("Stake token account does not belong to the player or is for another mint")]
+    InvalidStakeAccount,
+    #[msg("Rake must be at most 10000 bps")]
+    InvalidRakeBps,
+    #[msg("Seat count must be between 2 and 16")]
+    InvalidSeatCount,
+    #[msg("Payout table must have one entry per paid place, at most one per seat, summing to 10000 bps")]
+    InvalidPayoutTable,
+    #[msg("Player already has a seat in this room")]
+    AlreadySeated,
+    #[msg("Placements or player accounts do not match the room")]
+    InvalidPlacements,
+    #[msg("Nobody is waiting in the queue; do not pass a room")]
+    QueueEmpty,
+    #[msg("Player is already waiting in the queue")]
+    AlreadyQueued,
+    #[msg("A room account is required to pair with the waiting player")]
+    MissingRoomAccount,
+    #[msg("NFT-wager rooms require the NFT escrows, destinations and token program")]
+    MissingNftEscrow,
+    #[msg("NFT destination does not belong to the recipient")]
+    InvalidNftDestination,
+    #[msg("NFT lock does not belong to this room")]
+    NftLockMismatch,
+    #[msg("This private room is reserved for another challenger")]
+    NotInvited,
+    #[msg("Invite secret is missing or does not match")]
+    InvalidInviteSecret,
+    #[msg("Series length must be an odd number of games up to the maximum")]
+    InvalidSeriesLength,
+    #[msg("Both players must commit before revealing")]
+    ChoicesNotCommitted,
+    #[msg("Choice already committed for this game")]
+    AlreadyCommitted,
+    #[msg("Room has not been settled")]
+    RoomNotClosed,
+    #[msg("Rematches are only supported for lamport rooms without NFT wagers")]
+    RematchUnsupported,
+    #[msg("Bet cutoff must be in the future and before the room deadline")]
+    InvalidBetCutoff,
+    #[msg("Bet pool is not taking bets")]
+    BetPoolClosed,
+    #[msg("Invalid bet side")]
+    InvalidBetSide,
+    #[msg("Bet pool has not been settled")]
+    BetPoolUnsettled,
+    #[msg("Battle stats are out of range")]
+    InvalidBattleStats,
+    #[msg("NFT type is missing or does not match the NFT")]
+    NftTypeMismatch,
+    #[msg("Room has no proposed result")]
+    NoProposedResult,
+    #[msg("Dispute window has closed")]
+    DisputeWindowClosed,
+    #[msg("Dispute window is still open")]
+    DisputeWindowOpen,
+    #[msg("Room is not under dispute")]
+    RoomNotDisputed,
+    #[msg("Season must end after it starts and in the future")]
+    InvalidSeasonWindow,
+    #[msg("Season is missing or is not the room's season")]
+    SeasonMismatch,
+    #[msg("Season is already finalized")]
+    SeasonFinalized,
+    #[msg("Season has not ended")]
+    SeasonNotEnded,
+    #[msg("Prize already claimed")]
+    PrizeAlreadyClaimed,
+    #[msg("Session must expire in the future and within a day")]
+    InvalidSessionExpiry,
+    #[msg("Session scope must grant at least one known permission")]
+    InvalidSessionScope,
+    #[msg("Signer is not the session key or the session does not allow this action")]
+    InvalidSession,
+    #[msg("Session key has expired")]
+    SessionExpired,
+    #[msg("Tournament size must be a power of two within the allowed range")]
+    InvalidBracketSize,
+    #[msg("Tournament is not taking entries")]
+    TournamentNotOpen,
+    #[msg("Player has already entered this tournament")]
+    AlreadyEntered,
+    #[msg("Tournament is not running")]
+    TournamentNotRunning,
+    #[msg("Match players are not decided yet or the room does not belong to the match")]
+    MatchNotReady,
+    #[msg("Match room is already open")]
+    MatchAlreadyOpened,
+    #[msg("Tournament has not finished")]
+    TournamentNotFinished,
+    #[msg("Tournament rooms are closed when their result is reported")]
+    TournamentRoom,
+    #[msg("Account is not a room in the original layout")]
+    NotLegacyRoom,
+    #[msg("Bets are only taken on rooms decided on-chain or by the marketplace referee")]
+    BetPoolUnsupported,
+    #[msg("Bet pool still has unclaimed bets")]
+    BetsOutstanding,
+    #[msg("Disputed result is still awaiting arbitration")]
+    ArbitrationWindowOpen,
+    #[msg("Disputed rooms need their dispute account and disputer")]
+    MissingDispute,
+    #[msg("Season has leaderboard places to pay")]
+    SeasonHasWinners,
+    #[msg("Registration deadline must be in the future")]
+    InvalidRegistrationDeadline,
+    #[msg("Account does not match the expected layout or address")]
+    AccountLayoutMismatch,
 }
 
 // Accounts for presale
@@ -1292,13 +7613,13 @@ pub struct StakeNFT<'info> {
     pub stake_account: Account<'info, StakeAccount>,
 
     #[account(
-        seeds = [b"collection", collection.name.as_bytes()],
+        seeds = [b"collection", collection.name_seed().as_ref()],
         bump = collection.bump,
     )]
     pub collection: Account<'info, NFTCollection>,
 
     #[account(
-        seeds = [b"type", collection.key().as_ref(), nft_type.name.as_bytes()],
+        seeds = [b"type", collection.key().as_ref(), nft_type.name_seed().as_ref()],
         bump = nft_type.bump,
     )]
     pub nft_type: Account<'info, NftType>,
@@ -1443,4 +7764,336 @@ pub struct ClaimRewards<'info> {
     pub token_program: Program<'info, Token>,
     pub associated_token_program: Program<'info, AssociatedToken>,
     pub rent: Sysvar<'info, Rent>,
-}
\ No newline at end of file
+}
+
+#[derive(Accounts)]
+pub struct StakeProgrammableNFT<'info> {
+    #[account(
+        mut,
+        seeds = [b"stake_pool"],
+        bump = stake_pool.bump
+    )]
+    pub stake_pool: Box<Account<'info, StakePool>>,
+
+    #[account(
+        init,
+        payer = staker,
+        space = StakeAccount::space(),
+        seeds = [b"stake_account", staker.key().as_ref(), nft_mint.key().as_ref()],
+        bump
+    )]
+    pub stake_account: Box<Account<'info, StakeAccount>>,
+
+    #[account(
+        seeds = [b"collection", collection.name_seed().as_ref()],
+        bump = collection.bump,
+    )]
+    pub collection: Box<Account<'info, NFTCollection>>,
+
+    #[account(
+        seeds = [b"type", collection.key().as_ref(), nft_type.name_seed().as_ref()],
+        bump = nft_type.bump,
+        constraint = nft_type.programmable @ ErrorCode::NotProgrammable,
+    )]
+    pub nft_type: Box<Account<'info, NftType>>,
+
+    /// CHECK: NFT mint
+    pub nft_mint: Account<'info, Mint>,
+
+    /// CHECK: NFT Metadata account
+    #[account(
+        mut,
+        seeds = [
+            b"metadata",
+            token_metadata_program.key().as_ref(),
+            nft_mint.key().as_ref(),
+        ],
+        bump,
+        seeds::program = token_metadata_program.key(),
+    )]
+    pub nft_metadata: UncheckedAccount<'info>,
+
+    /// CHECK: NFT master edition account
+    #[account(
+        seeds = [
+            b"metadata",
+            token_metadata_program.key().as_ref(),
+            nft_mint.key().as_ref(),
+            b"edition",
+        ],
+        bump,
+        seeds::program = token_metadata_program.key(),
+    )]
+    pub nft_edition: UncheckedAccount<'info>,
+
+    /// CHECK: Token record of the staker's token account
+    #[account(
+        mut,
+        seeds = [
+            b"metadata",
+            token_metadata_program.key().as_ref(),
+            nft_mint.key().as_ref(),
+            b"token_record",
+            staker_nft_token_account.key().as_ref(),
+        ],
+        bump,
+        seeds::program = token_metadata_program.key(),
+    )]
+    pub token_record: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        constraint = staker_nft_token_account.owner == staker.key(),
+        constraint = staker_nft_token_account.mint == nft_mint.key(),
+        constraint = staker_nft_token_account.amount >= 1,
+    )]
+    pub staker_nft_token_account: Account<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub staker: Signer<'info>,
+    pub system_program: Program<'info, System>,
+    pub token_program: Program<'info, Token>,
+    /// CHECK: Token Metadata Program
+    #[account(constraint = token_metadata_program.key() == mpl_token_metadata::ID)]
+    pub token_metadata_program: UncheckedAccount<'info>,
+    /// CHECK: Instructions sysvar
+    #[account(address = sysvar_instructions::ID)]
+    pub sysvar_instructions: UncheckedAccount<'info>,
+    /// CHECK: Token Auth Rules program, required when the type has a rule set
+    #[account(address = TOKEN_AUTH_RULES_PROGRAM_ID)]
+    pub authorization_rules_program: Option<UncheckedAccount<'info>>,
+    /// CHECK: Rule set account, must match nft_type.rule_set
+    #[account(constraint = Some(authorization_rules.key()) == nft_type.rule_set @ ErrorCode::InvalidRuleSet)]
+    pub authorization_rules: Option<UncheckedAccount<'info>>,
+}
+
+#[derive(Accounts)]
+pub struct UnstakeProgrammableNFT<'info> {
+    #[account(
+        mut,
+        seeds = [b"stake_pool"],
+        bump = stake_pool.bump
+    )]
+    pub stake_pool: Box<Account<'info, StakePool>>,
+
+    #[account(
+        mut,
+        close = staker,
+        seeds = [b"stake_account", staker.key().as_ref(), stake_account.nft_mint.as_ref()],
+        bump = stake_account.bump,
+    )]
+    pub stake_account: Box<Account<'info, StakeAccount>>,
+
+    /// CHECK: Reward token mint from stake pool
+    pub reward_token_mint: Box<Account<'info, Mint>>,
+
+    #[account(
+        mut,
+        seeds = [b"reward_vault"],
+        bump,
+        constraint = reward_token_vault.mint == reward_token_mint.key(),
+    )]
+    pub reward_token_vault: Box<Account<'info, TokenAccount>>,
+
+    #[account(
+        init_if_needed,
+        payer = staker,
+        associated_token::mint = reward_token_mint,
+        associated_token::authority = staker,
+    )]
+    pub staker_reward_token_account: Box<Account<'info, TokenAccount>>,
+
+    /// CHECK: NFT mint
+    #[account(address = stake_account.nft_mint)]
+    pub nft_mint: Account<'info, Mint>,
+
+    /// CHECK: NFT Metadata account
+    #[account(
+        mut,
+        seeds = [
+            b"metadata",
+            token_metadata_program.key().as_ref(),
+            nft_mint.key().as_ref(),
+        ],
+        bump,
+        seeds::program = token_metadata_program.key(),
+    )]
+    pub nft_metadata: UncheckedAccount<'info>,
+
+    /// CHECK: NFT master edition account
+    #[account(
+        seeds = [
+            b"metadata",
+            token_metadata_program.key().as_ref(),
+            nft_mint.key().as_ref(),
+            b"edition",
+        ],
+        bump,
+        seeds::program = token_metadata_program.key(),
+    )]
+    pub nft_edition: UncheckedAccount<'info>,
+
+    /// CHECK: Token record of the staker's token account
+    #[account(
+        mut,
+        seeds = [
+            b"metadata",
+            token_metadata_program.key().as_ref(),
+            nft_mint.key().as_ref(),
+            b"token_record",
+            staker_nft_token_account.key().as_ref(),
+        ],
+        bump,
+        seeds::program = token_metadata_program.key(),
+    )]
+    pub token_record: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        constraint = staker_nft_token_account.owner == staker.key(),
+        constraint = staker_nft_token_account.mint == stake_account.nft_mint,
+    )]
+    pub staker_nft_token_account: Box<Account<'info, TokenAccount>>,
+
+    #[account(mut, constraint = staker.key() == stake_account.owner)]
+    pub staker: Signer<'info>,
+    pub system_program: Program<'info, System>,
+    pub token_program: Program<'info, Token>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    /// CHECK: Token Metadata Program
+    #[account(constraint = token_metadata_program.key() == mpl_token_metadata::ID)]
+    pub token_metadata_program: UncheckedAccount<'info>,
+    /// CHECK: Instructions sysvar
+    #[account(address = sysvar_instructions::ID)]
+    pub sysvar_instructions: UncheckedAccount<'info>,
+    /// CHECK: Token Auth Rules program, required when the type has a rule set
+    #[account(address = TOKEN_AUTH_RULES_PROGRAM_ID)]
+    pub authorization_rules_program: Option<UncheckedAccount<'info>>,
+    /// CHECK: Rule set account recorded on the pNFT's metadata (validated by Token Metadata)
+    pub authorization_rules: Option<UncheckedAccount<'info>>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn elo_delta_is_symmetric_between_equal_ratings() {
+        assert_eq!(elo_delta(1200, 1200, MatchResult::Win), 16);
+        assert_eq!(elo_delta(1200, 1200, MatchResult::Loss), -16);
+        assert_eq!(elo_delta(1200, 1200, MatchResult::Draw), 0);
+    }
+
+    #[test]
+    fn elo_delta_rewards_upsets_over_expected_wins() {
+        // 400 points apart: the favourite is expected to score 909 per mille
+        assert_eq!(elo_delta(1000, 1400, MatchResult::Win), 29);
+        assert_eq!(elo_delta(1400, 1000, MatchResult::Loss), -29);
+        assert_eq!(elo_delta(1400, 1000, MatchResult::Win), 2);
+        assert_eq!(elo_delta(1000, 1400, MatchResult::Loss), -2);
+    }
+
+    #[test]
+    fn elo_delta_moves_draws_towards_the_lower_rating() {
+        assert_eq!(elo_delta(1000, 1400, MatchResult::Draw), 13);
+        assert_eq!(elo_delta(1400, 1000, MatchResult::Draw), -13);
+    }
+
+    #[test]
+    fn elo_delta_caps_the_rating_gap_at_800() {
+        assert_eq!(elo_delta(1000, 1800, MatchResult::Win), elo_delta(1000, 3000, MatchResult::Win));
+        assert_eq!(elo_delta(0, u32::MAX, MatchResult::Win), 31);
+        assert_eq!(elo_delta(u32::MAX, 0, MatchResult::Win), 0);
+    }
+
+    fn serialized<T: AccountSerialize>(account: &T, space: usize) -> Vec<u8> {
+        let mut data = Vec::new();
+        account.try_serialize(&mut data).unwrap();
+        data.resize(space, 0);
+        data
+    }
+
+    fn heroes_collection() -> NFTCollection {
+        NFTCollection {
+            admin: Pubkey::new_unique(),
+            name: "Heroes".to_string(),
+            symbol: "HRO".to_string(),
+            uri: "https://example.com/heroes.json".to_string(),
+            royalty: 500,
+            mint: Pubkey::new_unique(),
+            is_active: true,
+            bump: 254,
+            is_sized: true,
+            creators: vec![],
+            total_types: 3,
+            hashed_seed: false,
+            verified: true,
+        }
+    }
+
+    #[test]
+    fn collection_fields_end_at_is_sized() {
+        let collection = heroes_collection();
+        let data = serialized(&collection, NFTCollection::space(collection.name.len()));
+        let (fields_end, name_len) = collection_fields_end(&data).unwrap();
+
+        assert_eq!(name_len, collection.name.len());
+        assert_eq!(data[fields_end - 1], collection.bump);
+        assert_eq!(data[fields_end], 1);
+    }
+
+    #[test]
+    fn short_collection_reads_as_defaults_once_its_tail_is_zeroed() {
+        let collection = heroes_collection();
+        let mut data = serialized(&collection, NFTCollection::space(collection.name.len()));
+        let (fields_end, _) = collection_fields_end(&data).unwrap();
+        // An account sized before the appended fields, with stale bytes after `bump`
+        data.truncate(fields_end + NFTCollection::APPENDED_FIELDS_MIN_LEN - 1);
+        data[fields_end..].fill(0xab);
+        data.resize(NFTCollection::space(collection.name.len()), 0);
+        data[fields_end..].fill(0);
+
+        let migrated = NFTCollection::try_deserialize(&mut &data[..]).unwrap();
+        assert_eq!(migrated.name, collection.name);
+        assert_eq!(migrated.mint, collection.mint);
+        assert!(!migrated.is_sized && !migrated.hashed_seed && !migrated.verified);
+        assert!(migrated.creators.is_empty());
+        assert_eq!(migrated.total_types, 0);
+    }
+
+    #[test]
+    fn nft_type_fields_end_at_programmable() {
+        let nft_type = NftType {
+            collection: Pubkey::new_unique(),
+            name: "Knight".to_string(),
+            uri: "https://example.com/knight.json".to_string(),
+            price: 1_000_000,
+            max_supply: 100,
+            current_supply: 7,
+            stake_multiplier: 2,
+            bump: 253,
+            programmable: true,
+            rule_set: None,
+            hashed_seed: false,
+            battle_stats: BattleStats::default(),
+        };
+        let data = serialized(&nft_type, NftType::space(nft_type.name.len()));
+        let (fields_end, name_len) = nft_type_fields_end(&data).unwrap();
+
+        assert_eq!(name_len, nft_type.name.len());
+        assert_eq!(data[fields_end - 1], nft_type.bump);
+        assert_eq!(data[fields_end], 1);
+    }
+
+    #[test]
+    fn item_number_reads_names_minted_for_the_type() {
+        assert_eq!(item_number("Knight #12", "Knight"), Some(12));
+        assert_eq!(item_number("Knight #3\0\0\0\0", "Knight"), Some(3));
+        assert_eq!(item_number(&item_name("An exceptionally long type name", 7), "An exceptionally long type name"), Some(7));
+        assert_eq!(item_number("Knight #12", "Knigh"), None);
+        assert_eq!(item_number("Dark Knight #12", "Knight"), None);
+        assert_eq!(item_number("Knight #x", "Knight"), None);
+        assert_eq!(item_number("Knight", "Knight"), None);
+    }
+}
@@ -0,0 +1,366 @@
+import * as anchor from "@coral-xyz/anchor";
+import { Program } from "@coral-xyz/anchor";
+import {
+  Keypair,
+  PublicKey,
+  SystemProgram,
+  SYSVAR_INSTRUCTIONS_PUBKEY,
+  Transaction,
+  TransactionInstruction,
+} from "@solana/web3.js";
+import { assert } from "chai";
+import { createHash } from "crypto";
+import { NftMarketplace } from "../target/types/nft_marketplace";
+
+// Loaded from tests/fixtures via [[test.genesis]] in Anchor.toml (see `yarn fixtures`)
+const TOKEN_METADATA_PROGRAM_ID = new PublicKey(
+  "metaqbxxUerdq28cj1RbAWkYQm3ybzjb6a8bt518x1s"
+);
+const TOKEN_PROGRAM_ID = new PublicKey(
+  "TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA"
+);
+const ASSOCIATED_TOKEN_PROGRAM_ID = new PublicKey(
+  "ATokenGPvbdGVxr1b2hvZbsiqW5xWH25efTNsLJA8knL"
+);
+
+const sha256 = (name: string) => createHash("sha256").update(name).digest();
+
+const ata = (owner: PublicKey, mint: PublicKey) =>
+  PublicKey.findProgramAddressSync(
+    [owner.toBuffer(), TOKEN_PROGRAM_ID.toBuffer(), mint.toBuffer()],
+    ASSOCIATED_TOKEN_PROGRAM_ID
+  )[0];
+
+const metadataPda = (mint: PublicKey) =>
+  PublicKey.findProgramAddressSync(
+    [Buffer.from("metadata"), TOKEN_METADATA_PROGRAM_ID.toBuffer(), mint.toBuffer()],
+    TOKEN_METADATA_PROGRAM_ID
+  )[0];
+
+const editionPda = (mint: PublicKey) =>
+  PublicKey.findProgramAddressSync(
+    [
+      Buffer.from("metadata"),
+      TOKEN_METADATA_PROGRAM_ID.toBuffer(),
+      mint.toBuffer(),
+      Buffer.from("edition"),
+    ],
+    TOKEN_METADATA_PROGRAM_ID
+  )[0];
+
+const tokenRecordPda = (mint: PublicKey, token: PublicKey) =>
+  PublicKey.findProgramAddressSync(
+    [
+      Buffer.from("metadata"),
+      TOKEN_METADATA_PROGRAM_ID.toBuffer(),
+      mint.toBuffer(),
+      Buffer.from("token_record"),
+      token.toBuffer(),
+    ],
+    TOKEN_METADATA_PROGRAM_ID
+  )[0];
+
+describe("programmable nfts", () => {
+  anchor.setProvider(anchor.AnchorProvider.env());
+
+  const program = anchor.workspace.NftMarketplace as Program<NftMarketplace>;
+  const provider = anchor.getProvider() as anchor.AnchorProvider;
+  const admin = provider.wallet.publicKey;
+
+  // Longer than a 32-byte seed; PDAs use the name hash
+  const collectionName = `programmable collection ${Date.now()} with a long name`;
+  const collectionMint = Keypair.generate();
+  const [marketplace] = PublicKey.findProgramAddressSync(
+    [Buffer.from("marketplace")],
+    program.programId
+  );
+  const [collection] = PublicKey.findProgramAddressSync(
+    [Buffer.from("collection"), sha256(collectionName)],
+    program.programId
+  );
+  const typePda = (name: string) =>
+    PublicKey.findProgramAddressSync(
+      [Buffer.from("type"), collection.toBuffer(), sha256(name)],
+      program.programId
+    )[0];
+  const indexPda = (prefix: string, parent: PublicKey, index: anchor.BN) =>
+    PublicKey.findProgramAddressSync(
+      [Buffer.from(prefix), parent.toBuffer(), index.toArrayLike(Buffer, "le", 8)],
+      program.programId
+    )[0];
+
+  before(async () => {
+    if (!(await program.account.marketplace.fetchNullable(marketplace))) {
+      await program.methods.initializeMarketplace(500).rpc();
+    }
+    const { totalCollections } = await program.account.marketplace.fetch(marketplace);
+
+    await program.methods
+      .createNftCollection(collectionName, "PNFT", "https://example.com/c.json", 500, [])
+      .accountsPartial({
+        marketplace,
+        collection,
+        collectionIndex: indexPda("collection_index", marketplace, totalCollections),
+        allowedCreator: null,
+        treasury: null,
+        collectionMint: collectionMint.publicKey,
+        adminTokenAccount: ata(admin, collectionMint.publicKey),
+        collectionMetadata: metadataPda(collectionMint.publicKey),
+        collectionMasterEdition: editionPda(collectionMint.publicKey),
+        admin,
+        tokenMetadataProgram: TOKEN_METADATA_PROGRAM_ID,
+      })
+      .signers([collectionMint])
+      .rpc();
+
+    await program.methods
+      .createNftType(
+        "hero",
+        "https://example.com/hero.json",
+        new anchor.BN(0),
+        new anchor.BN(10),
+        new anchor.BN(10_000),
+        true,
+        null
+      )
+      .accountsPartial({
+        collection,
+        nftType: typePda("hero"),
+        typeIndex: indexPda("type_index", collection, new anchor.BN(0)),
+        admin,
+      })
+      .rpc();
+  });
+
+  it("lists the collection and its types through the registry", async () => {
+    const { totalCollections } = await program.account.marketplace.fetch(marketplace);
+    const last = totalCollections.subn(1);
+    const entry = await program.account.collectionIndex.fetch(
+      indexPda("collection_index", marketplace, last)
+    );
+    assert.ok(entry.collection.equals(collection));
+
+    const typeEntry = await program.account.nftTypeIndex.fetch(
+      indexPda("type_index", collection, new anchor.BN(0))
+    );
+    assert.ok(typeEntry.nftType.equals(typePda("hero")));
+  });
+
+  it("does not index a type or collection twice", async () => {
+    try {
+      await program.methods
+        .indexExistingNftType()
+        .accountsPartial({
+          collection,
+          nftType: typePda("hero"),
+          typeIndex: indexPda("type_index", collection, new anchor.BN(1)),
+          admin,
+        })
+        .rpc();
+      assert.fail("expected the type_indexed entry to exist");
+    } catch (err) {
+      assert.include(String(err), "already in use");
+    }
+
+    const { totalCollections } = await program.account.marketplace.fetch(marketplace);
+    try {
+      await program.methods
+        .indexExistingCollection(totalCollections.subn(1))
+        .accountsPartial({ marketplace, collection, admin })
+        .rpc();
+      assert.fail("expected the collection_indexed entry to exist");
+    } catch (err) {
+      assert.include(String(err), "already in use");
+    }
+  });
+
+  it("rejects a rule set on a non-programmable type", async () => {
+    try {
+      await program.methods
+        .createNftType(
+          "plain",
+          "https://example.com/plain.json",
+          new anchor.BN(0),
+          new anchor.BN(10),
+          new anchor.BN(10_000),
+          false,
+          Keypair.generate().publicKey
+        )
+        .accountsPartial({
+          collection,
+          nftType: typePda("plain"),
+          typeIndex: indexPda("type_index", collection, new anchor.BN(1)),
+          admin,
+        })
+        .rpc();
+      assert.fail("expected NotProgrammable");
+    } catch (err) {
+      assert.include(String(err), "NotProgrammable");
+    }
+  });
+
+  // Minted by the test below and staked by the one after it
+  const nftMint = Keypair.generate();
+
+  it("mints a programmable NFT and verifies the collection", async () => {
+    const buyerToken = ata(admin, nftMint.publicKey);
+
+    await program.methods
+      .mintProgrammableNftFromCollection("hero")
+      .accountsPartial({
+        collection,
+        nftType: typePda("hero"),
+        nftMint: nftMint.publicKey,
+        buyerTokenAccount: buyerToken,
+        nftMetadata: metadataPda(nftMint.publicKey),
+        nftMasterEdition: editionPda(nftMint.publicKey),
+        tokenRecord: tokenRecordPda(nftMint.publicKey, buyerToken),
+        collectionMetadata: metadataPda(collectionMint.publicKey),
+        collectionMasterEdition: editionPda(collectionMint.publicKey),
+        collectionMintAccount: collectionMint.publicKey,
+        collectionAdmin: admin,
+        buyer: admin,
+        tokenMetadataProgram: TOKEN_METADATA_PROGRAM_ID,
+        sysvarInstructions: SYSVAR_INSTRUCTIONS_PUBKEY,
+        authorizationRulesProgram: null,
+        authorizationRules: null,
+      })
+      .signers([nftMint])
+      .preInstructions([
+        anchor.web3.ComputeBudgetProgram.setComputeUnitLimit({ units: 400_000 }),
+      ])
+      .rpc();
+
+    const nftType = await program.account.nftType.fetch(typePda("hero"));
+    assert.equal(nftType.currentSupply.toNumber(), 1);
+
+    const balance = await provider.connection.getTokenAccountBalance(buyerToken);
+    assert.equal(balance.value.amount, "1");
+
+    // pNFT token accounts stay frozen by the master edition
+    const tokenInfo = await provider.connection.getParsedAccountInfo(buyerToken);
+    assert.equal((tokenInfo.value.data as any).parsed.info.state, "frozen");
+  });
+
+  it("locks a staked pNFT to the stake account and releases it on unstake", async () => {
+    const mint = nftMint.publicKey;
+    const stakerToken = ata(admin, mint);
+    const tokenRecord = tokenRecordPda(mint, stakerToken);
+    const [stakePool] = PublicKey.findProgramAddressSync(
+      [Buffer.from("stake_pool")],
+      program.programId
+    );
+    const [rewardVault] = PublicKey.findProgramAddressSync(
+      [Buffer.from("reward_vault")],
+      program.programId
+    );
+    const [stakeAccount] = PublicKey.findProgramAddressSync(
+      [Buffer.from("stake_account"), admin.toBuffer(), mint.toBuffer()],
+      program.programId
+    );
+
+    // A zero reward rate keeps unstaking independent of the reward vault balance
+    if (!(await program.account.stakePool.fetchNullable(stakePool))) {
+      const rewardMint = Keypair.generate();
+      await provider.sendAndConfirm(
+        new Transaction().add(
+          SystemProgram.createAccount({
+            fromPubkey: admin,
+            newAccountPubkey: rewardMint.publicKey,
+            lamports: await provider.connection.getMinimumBalanceForRentExemption(82),
+            space: 82,
+            programId: TOKEN_PROGRAM_ID,
+          }),
+          // InitializeMint2: decimals 0, admin as mint authority, no freeze authority
+          new TransactionInstruction({
+            programId: TOKEN_PROGRAM_ID,
+            keys: [{ pubkey: rewardMint.publicKey, isSigner: false, isWritable: true }],
+            data: Buffer.concat([Buffer.from([20, 0]), admin.toBuffer(), Buffer.from([0])]),
+          })
+        ),
+        [rewardMint]
+      );
+      await program.methods
+        .initializeStakePool(new anchor.BN(0))
+        .accountsPartial({
+          stakePool,
+          rewardTokenMint: rewardMint.publicKey,
+          rewardTokenVault: rewardVault,
+          admin,
+        })
+        .rpc();
+    }
+    const { rewardTokenMint, rewardRatePerSecond } = await program.account.stakePool.fetch(
+      stakePool
+    );
+    assert.equal(rewardRatePerSecond.toNumber(), 0);
+
+    // Token record: key, bump, state (0 unlocked, 1 locked), rule_set_revision, delegate
+    const readTokenRecord = async () => {
+      const data = (await provider.connection.getAccountInfo(tokenRecord)).data;
+      const delegateAt = data[3] === 1 ? 12 : 4;
+      const delegate =
+        data[delegateAt] === 1 ? new PublicKey(data.subarray(delegateAt + 1, delegateAt + 33)) : null;
+      return { locked: data[2] === 1, delegate };
+    };
+    const parsedToken = async () =>
+      ((await provider.connection.getParsedAccountInfo(stakerToken)).value.data as any).parsed.info;
+    const metadataAccounts = {
+      nftMint: mint,
+      nftMetadata: metadataPda(mint),
+      nftEdition: editionPda(mint),
+      tokenRecord,
+      stakerNftTokenAccount: stakerToken,
+      staker: admin,
+      tokenMetadataProgram: TOKEN_METADATA_PROGRAM_ID,
+      sysvarInstructions: SYSVAR_INSTRUCTIONS_PUBKEY,
+      authorizationRulesProgram: null,
+      authorizationRules: null,
+    };
+    const computeBudget = anchor.web3.ComputeBudgetProgram.setComputeUnitLimit({ units: 400_000 });
+
+    await program.methods
+      .stakeProgrammableNft()
+      .accountsPartial({
+        ...metadataAccounts,
+        stakePool,
+        stakeAccount,
+        collection,
+        nftType: typePda("hero"),
+      })
+      .preInstructions([computeBudget])
+      .rpc();
+
+    // The pNFT stays in the wallet, delegated to and locked by the stake account
+    const staked = await readTokenRecord();
+    assert.isTrue(staked.locked);
+    assert.ok(staked.delegate.equals(stakeAccount));
+    const stakedToken = await parsedToken();
+    assert.equal(stakedToken.tokenAmount.amount, "1");
+    assert.equal(stakedToken.delegate, stakeAccount.toBase58());
+    assert.equal(stakedToken.delegatedAmount.amount, "1");
+    assert.ok((await program.account.stakeAccount.fetch(stakeAccount)).owner.equals(admin));
+
+    await program.methods
+      .unstakeProgrammableNft()
+      .accountsPartial({
+        ...metadataAccounts,
+        stakePool,
+        stakeAccount,
+        rewardTokenMint,
+        rewardTokenVault: rewardVault,
+        stakerRewardTokenAccount: ata(admin, rewardTokenMint),
+      })
+      .preInstructions([computeBudget])
+      .rpc();
+
+    // Unlocked, undelegated and still held by the staker
+    const unstaked = await readTokenRecord();
+    assert.isFalse(unstaked.locked);
+    assert.isNull(unstaked.delegate);
+    const unstakedToken = await parsedToken();
+    assert.equal(unstakedToken.tokenAmount.amount, "1");
+    assert.isUndefined(unstakedToken.delegate);
+    assert.isNull(await program.account.stakeAccount.fetchNullable(stakeAccount));
+  });
+});
@@ -0,0 +1,1932 @@
+import * as anchor from "@coral-xyz/anchor";
+import { Program } from "@coral-xyz/anchor";
+import {
+  Keypair,
+  LAMPORTS_PER_SOL,
+  PublicKey,
+  SystemProgram,
+  Transaction,
+  TransactionInstruction,
+} from "@solana/web3.js";
+import { assert } from "chai";
+import { createHash } from "crypto";
+import { NftMarketplace } from "../target/types/nft_marketplace";
+
+// Loaded from tests/fixtures via [[test.genesis]] in Anchor.toml (see `yarn fixtures`)
+const TOKEN_METADATA_PROGRAM_ID = new PublicKey(
+  "metaqbxxUerdq28cj1RbAWkYQm3ybzjb6a8bt518x1s"
+);
+const TOKEN_PROGRAM_ID = new PublicKey(
+  "TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA"
+);
+const ASSOCIATED_TOKEN_PROGRAM_ID = new PublicKey(
+  "ATokenGPvbdGVxr1b2hvZbsiqW5xWH25efTNsLJA8knL"
+);
+
+const sha256 = (name: string) => createHash("sha256").update(name).digest();
+
+const ata = (owner: PublicKey, mint: PublicKey) =>
+  PublicKey.findProgramAddressSync(
+    [owner.toBuffer(), TOKEN_PROGRAM_ID.toBuffer(), mint.toBuffer()],
+    ASSOCIATED_TOKEN_PROGRAM_ID
+  )[0];
+
+const metadataPda = (mint: PublicKey, program = TOKEN_METADATA_PROGRAM_ID) =>
+  PublicKey.findProgramAddressSync(
+    [Buffer.from("metadata"), program.toBuffer(), mint.toBuffer()],
+    program
+  )[0];
+
+const editionPda = (mint: PublicKey) =>
+  PublicKey.findProgramAddressSync(
+    [
+      Buffer.from("metadata"),
+      TOKEN_METADATA_PROGRAM_ID.toBuffer(),
+      mint.toBuffer(),
+      Buffer.from("edition"),
+    ],
+    TOKEN_METADATA_PROGRAM_ID
+  )[0];
+
+const expectError = async (promise: Promise<unknown>, pattern: RegExp) => {
+  try {
+    await promise;
+  } catch (err) {
+    assert.match(String(err), pattern);
+    return;
+  }
+  assert.fail(`expected ${pattern}`);
+};
+
+describe("rooms", () => {
+  anchor.setProvider(anchor.AnchorProvider.env());
+
+  const program = anchor.workspace.NftMarketplace as Program<NftMarketplace>;
+  const provider = anchor.getProvider() as anchor.AnchorProvider;
+  const admin = provider.wallet.publicKey;
+  const challenger = Keypair.generate();
+  // Third player for multi-seat rooms
+  const rival = Keypair.generate();
+
+  const [marketplace] = PublicKey.findProgramAddressSync(
+    [Buffer.from("marketplace")],
+    program.programId
+  );
+  const collectionPda = (name: string) =>
+    PublicKey.findProgramAddressSync(
+      [Buffer.from("collection"), sha256(name)],
+      program.programId
+    )[0];
+  const indexPda = (prefix: string, parent: PublicKey, index: anchor.BN) =>
+    PublicKey.findProgramAddressSync(
+      [Buffer.from(prefix), parent.toBuffer(), index.toArrayLike(Buffer, "le", 8)],
+      program.programId
+    )[0];
+  const roomPda = (creator: PublicKey, roomId: anchor.BN) =>
+    PublicKey.findProgramAddressSync(
+      [Buffer.from("room"), creator.toBuffer(), roomId.toArrayLike(Buffer, "le", 8)],
+      program.programId
+    )[0];
+  const profilePda = (wallet: PublicKey) =>
+    PublicKey.findProgramAddressSync(
+      [Buffer.from("profile"), wallet.toBuffer()],
+      program.programId
+    )[0];
+  const nftLockPda = (nftMint: PublicKey) =>
+    PublicKey.findProgramAddressSync(
+      [Buffer.from("nft_lock"), nftMint.toBuffer()],
+      program.programId
+    )[0];
+
+  // A registered collection with one free "fighter" type
+  const setupCollection = async (name: string) => {
+    const collection = collectionPda(name);
+    const collectionMint = Keypair.generate();
+    const { totalCollections } = await program.account.marketplace.fetch(marketplace);
+
+    await program.methods
+      .createNftCollection(name, "ROOM", "https://example.com/c.json", 500, [])
+      .accountsPartial({
+        marketplace,
+        collection,
+        collectionIndex: indexPda("collection_index", marketplace, totalCollections),
+        allowedCreator: null,
+        treasury: null,
+        collectionMint: collectionMint.publicKey,
+        adminTokenAccount: ata(admin, collectionMint.publicKey),
+        collectionMetadata: metadataPda(collectionMint.publicKey),
+        collectionMasterEdition: editionPda(collectionMint.publicKey),
+        admin,
+        tokenMetadataProgram: TOKEN_METADATA_PROGRAM_ID,
+      })
+      .signers([collectionMint])
+      .rpc();
+
+    await program.methods
+      .createNftType(
+        "fighter",
+        "https://example.com/fighter.json",
+        new anchor.BN(0),
+        new anchor.BN(100),
+        new anchor.BN(10_000),
+        false,
+        null
+      )
+      .accountsPartial({
+        collection,
+        nftType: PublicKey.findProgramAddressSync(
+          [Buffer.from("type"), collection.toBuffer(), sha256("fighter")],
+          program.programId
+        )[0],
+        typeIndex: indexPda("type_index", collection, new anchor.BN(0)),
+        admin,
+      })
+      .rpc();
+
+    return { collection, collectionMint: collectionMint.publicKey };
+  };
+
+  // Mint a collection item to `buyer`; returns the NFT mint
+  const mintFighter = async (
+    { collection, collectionMint }: { collection: PublicKey; collectionMint: PublicKey },
+    buyer: Keypair | null
+  ) => {
+    const nftMint = Keypair.generate();
+    const buyerKey = buyer ? buyer.publicKey : admin;
+    await program.methods
+      .mintNftFromCollection("fighter")
+      .accountsPartial({
+        collection,
+        nftType: PublicKey.findProgramAddressSync(
+          [Buffer.from("type"), collection.toBuffer(), sha256("fighter")],
+          program.programId
+        )[0],
+        nftMint: nftMint.publicKey,
+        buyerTokenAccount: ata(buyerKey, nftMint.publicKey),
+        nftMetadata: metadataPda(nftMint.publicKey),
+        collectionMetadata: metadataPda(collectionMint),
+        collectionMasterEdition: editionPda(collectionMint),
+        collectionMintAccount: collectionMint,
+        collectionAdmin: admin,
+        buyer: buyerKey,
+        tokenMetadataProgram: TOKEN_METADATA_PROGRAM_ID,
+      })
+      .signers(buyer ? [nftMint, buyer] : [nftMint])
+      .rpc();
+    return nftMint.publicKey;
+  };
+
+  const createRoom = (
+    id: anchor.BN,
+    collection: PublicKey,
+    nftMint: PublicKey,
+    overrides: Record<string, PublicKey> = {},
+    inviteHash: number[] | null = null,
+    {
+      mode = 0,
+      commitment = null as number[] | null,
+      timeoutSecs = 3600,
+      referee = null as PublicKey | null,
+      bestOf = 1,
+    } = {}
+  ) =>
+    program.methods
+      .createRoom(
+        id,
+        new anchor.BN(LAMPORTS_PER_SOL / 100),
+        mode,
+        referee,
+        commitment,
+        new anchor.BN(timeoutSecs),
+        null,
+        inviteHash,
+        bestOf
+      )
+      .accountsPartial({
+        marketplace,
+        room: roomPda(admin, id),
+        creator: admin,
+        collection,
+        nftMint,
+        nftMetadata: metadataPda(nftMint),
+        nftType: null,
+        itemType: null,
+        creatorNftToken: ata(admin, nftMint),
+        creatorNftLock: nftLockPda(nftMint),
+        creatorNftEscrow: null,
+        rakeExemption: null,
+        stakeMint: null,
+        roomVault: null,
+        creatorStakeToken: null,
+        tokenProgram: null,
+        tokenMetadataProgram: TOKEN_METADATA_PROGRAM_ID,
+        ...overrides,
+      })
+      .rpc();
+
+  const joinRoom = (
+    id: anchor.BN,
+    collection: PublicKey,
+    nftMint: PublicKey,
+    inviteSecret: number[] | null = null,
+    commitment: number[] | null = null,
+    overrides: Record<string, PublicKey> = {}
+  ) =>
+    program.methods
+      .joinRoom(commitment, inviteSecret)
+      .accountsPartial({
+        room: roomPda(admin, id),
+        creator: admin,
+        collection,
+        challenger: challenger.publicKey,
+        nftMint,
+        nftMetadata: metadataPda(nftMint),
+        nftType: null,
+        itemType: null,
+        challengerNftToken: ata(challenger.publicKey, nftMint),
+        challengerNftLock: nftLockPda(nftMint),
+        challengerNftEscrow: null,
+        roomVault: null,
+        challengerStakeToken: null,
+        tokenProgram: null,
+        tokenMetadataProgram: TOKEN_METADATA_PROGRAM_ID,
+        ...overrides,
+      })
+      .signers([challenger])
+      .rpc();
+
+  // CreateIdempotent for `owner`'s associated token account of `mint`, paid by admin
+  const createAtaIx = (owner: PublicKey, mint: PublicKey) =>
+    new TransactionInstruction({
+      programId: ASSOCIATED_TOKEN_PROGRAM_ID,
+      keys: [
+        { pubkey: admin, isSigner: true, isWritable: true },
+        { pubkey: ata(owner, mint), isSigner: false, isWritable: true },
+        { pubkey: owner, isSigner: false, isWritable: false },
+        { pubkey: mint, isSigner: false, isWritable: false },
+        { pubkey: SystemProgram.programId, isSigner: false, isWritable: false },
+        { pubkey: TOKEN_PROGRAM_ID, isSigner: false, isWritable: false },
+      ],
+      data: Buffer.from([1]),
+    });
+
+  // New 0-decimal SPL mint with `amount` minted to both players' associated accounts
+  const createStakeMint = async (amount: number) => {
+    const mint = Keypair.generate();
+    const tx = new Transaction().add(
+      SystemProgram.createAccount({
+        fromPubkey: admin,
+        newAccountPubkey: mint.publicKey,
+        lamports: await provider.connection.getMinimumBalanceForRentExemption(82),
+        space: 82,
+        programId: TOKEN_PROGRAM_ID,
+      }),
+      // InitializeMint2: decimals 0, admin as mint authority, no freeze authority
+      new TransactionInstruction({
+        programId: TOKEN_PROGRAM_ID,
+        keys: [{ pubkey: mint.publicKey, isSigner: false, isWritable: true }],
+        data: Buffer.concat([Buffer.from([20, 0]), admin.toBuffer(), Buffer.from([0])]),
+      })
+    );
+    const amountData = Buffer.alloc(8);
+    amountData.writeBigUInt64LE(BigInt(amount));
+    for (const owner of [admin, challenger.publicKey]) {
+      const account = ata(owner, mint.publicKey);
+      tx.add(
+        createAtaIx(owner, mint.publicKey),
+        // MintTo
+        new TransactionInstruction({
+          programId: TOKEN_PROGRAM_ID,
+          keys: [
+            { pubkey: mint.publicKey, isSigner: false, isWritable: true },
+            { pubkey: account, isSigner: false, isWritable: true },
+            { pubkey: admin, isSigner: true, isWritable: false },
+          ],
+          data: Buffer.concat([Buffer.from([7]), amountData]),
+        })
+      );
+    }
+    await provider.sendAndConfirm(tx, [mint]);
+    return mint.publicKey;
+  };
+
+  const tokenBalance = async (owner: PublicKey, mint: PublicKey) =>
+    Number((await provider.connection.getTokenAccountBalance(ata(owner, mint))).value.amount);
+
+  const stamp = Date.now();
+  const roomId = (n: number) => new anchor.BN(stamp).muln(10).addn(n);
+  let verified: { collection: PublicKey; collectionMint: PublicKey };
+  let unverified: { collection: PublicKey; collectionMint: PublicKey };
+  let creatorNft: PublicKey;
+  let challengerNft: PublicKey;
+  let outsiderNft: PublicKey;
+
+  before(async () => {
+    if (!(await program.account.marketplace.fetchNullable(marketplace))) {
+      await program.methods.initializeMarketplace(500).rpc();
+    }
+    for (const player of [challenger, rival]) {
+      await provider.connection.confirmTransaction(
+        await provider.connection.requestAirdrop(player.publicKey, 2 * LAMPORTS_PER_SOL)
+      );
+    }
+
+    verified = await setupCollection(`room collection ${stamp}`);
+    unverified = await setupCollection(`unverified room collection ${stamp}`);
+    await program.methods
+      .setCollectionVerified(true)
+      .accountsPartial({ marketplace, collection: verified.collection, admin })
+      .rpc();
+
+    creatorNft = await mintFighter(verified, null);
+    challengerNft = await mintFighter(verified, challenger);
+    outsiderNft = await mintFighter(unverified, null);
+  });
+
+  it("rejects a fake token metadata program", async () => {
+    const fakeProgram = SystemProgram.programId;
+    await expectError(
+      createRoom(roomId(1), verified.collection, creatorNft, {
+        nftMetadata: metadataPda(creatorNft, fakeProgram),
+        tokenMetadataProgram: fakeProgram,
+      }),
+      /InvalidMetadata|ConstraintAddress/
+    );
+  });
+
+  it("rejects a collection the marketplace has not verified", async () => {
+    await expectError(
+      createRoom(roomId(2), unverified.collection, outsiderNft),
+      /CollectionNotVerified/
+    );
+  });
+
+  it("rejects an NFT from another collection", async () => {
+    await expectError(
+      createRoom(roomId(3), verified.collection, outsiderNft),
+      /NftNotInCollection/
+    );
+  });
+
+  it("records the gating collection and requires it on join", async () => {
+    const id = roomId(4);
+    await createRoom(id, verified.collection, creatorNft);
+    const room = await program.account.room.fetch(roomPda(admin, id));
+    assert.ok(room.collection.equals(verified.collection));
+
+    await expectError(
+      joinRoom(id, unverified.collection, challengerNft),
+      /CollectionMismatch/
+    );
+
+    await joinRoom(id, verified.collection, challengerNft);
+    const joined = await program.account.room.fetch(roomPda(admin, id));
+    assert.ok(joined.challenger.equals(challenger.publicKey));
+  });
+
+  it("locks the gating NFT while its room is active", async () => {
+    const lock = await program.account.nftLock.fetch(nftLockPda(creatorNft));
+    assert.ok(lock.room.equals(roomPda(admin, roomId(4))));
+    assert.ok(lock.owner.equals(admin));
+
+    await expectError(
+      createRoom(roomId(5), verified.collection, creatorNft),
+      /already in use/
+    );
+  });
+
+  it("only lets the holder of the invite secret join a private room", async () => {
+    const id = roomId(6);
+    const secret = Keypair.generate().publicKey.toBuffer();
+    const hash = [...createHash("sha256").update(secret).digest()];
+    const hostNft = await mintFighter(verified, null);
+    const guestNft = await mintFighter(verified, challenger);
+    await createRoom(id, verified.collection, hostNft, {}, hash);
+
+    await expectError(joinRoom(id, verified.collection, guestNft), /InvalidInviteSecret/);
+    await expectError(
+      joinRoom(id, verified.collection, guestNft, [...Buffer.alloc(32)]),
+      /InvalidInviteSecret/
+    );
+
+    await joinRoom(id, verified.collection, guestNft, [...secret]);
+    const joined = await program.account.room.fetch(roomPda(admin, id));
+    assert.ok(joined.challenger.equals(challenger.publicKey));
+  });
+
+  // Accounts for settling a lamport room between `admin` and `challenger`
+  const settleAccounts = (room: PublicKey, creatorNftMint: PublicKey, challengerNftMint: PublicKey) => ({
+    room,
+    creator: admin,
+    challenger: challenger.publicKey,
+    creatorProfile: profilePda(admin),
+    challengerProfile: profilePda(challenger.publicKey),
+    creatorNftLock: nftLockPda(creatorNftMint),
+    challengerNftLock: nftLockPda(challengerNftMint),
+    roomVault: null,
+    creatorStakeToken: null,
+    challengerStakeToken: null,
+    tokenProgram: null,
+    treasury: null,
+    treasuryStakeToken: null,
+    season: null as PublicKey | null,
+    creatorNftEscrow: null,
+    challengerNftEscrow: null,
+    creatorNftDestination: null,
+    challengerNftDestination: null,
+  });
+
+  // sha256(choice || salt || player), as committed in commit-reveal rooms
+  const commit = (choice: number, salt: Buffer, player: PublicKey) => [
+    ...createHash("sha256")
+      .update(Buffer.from([choice]))
+      .update(salt)
+      .update(player.toBuffer())
+      .digest(),
+  ];
+
+  // Enter a tournament with a freshly minted fighter; `player` null is admin
+  const enterTournament = async (tournament: PublicKey, player: Keypair | null) => {
+    const wallet = player ? player.publicKey : admin;
+    const nftMint = await mintFighter(verified, player);
+    await program.methods
+      .enterTournament()
+      .accountsPartial({
+        tournament,
+        player: wallet,
+        collection: verified.collection,
+        nftMint,
+        nftMetadata: metadataPda(nftMint),
+        playerNftToken: ata(wallet, nftMint),
+        playerNftLock: nftLockPda(nftMint),
+        tokenMetadataProgram: TOKEN_METADATA_PROGRAM_ID,
+      })
+      .signers(player ? [player] : [])
+      .rpc();
+    return nftMint;
+  };
+
+  // Reveal `choice` in a room between `admin` and `challenger`; `player` null is admin
+  const reveal = (
+    room: PublicKey,
+    hostNft: PublicKey,
+    guestNft: PublicKey,
+    player: Keypair | null,
+    choice: number,
+    salt: Buffer
+  ) =>
+    program.methods
+      .revealChoice(choice, [...salt])
+      .accountsPartial({
+        ...settleAccounts(room, hostNft, guestNft),
+        player: player ? player.publicKey : admin,
+        session: null,
+      })
+      .signers(player ? [player] : [])
+      .rpc();
+
+  // Host and guest NFTs plus a joined commit-reveal room with the given choices
+  const playGame = async (
+    n: number,
+    mode: number,
+    hostChoice: number,
+    guestChoice: number,
+    timeoutSecs = 3600
+  ) => {
+    const id = roomId(n);
+    const hostNft = await mintFighter(verified, null);
+    const guestNft = await mintFighter(verified, challenger);
+    const hostSalt = Keypair.generate().publicKey.toBuffer();
+    const guestSalt = Keypair.generate().publicKey.toBuffer();
+    await createRoom(id, verified.collection, hostNft, {}, null, {
+      mode,
+      commitment: commit(hostChoice, hostSalt, admin),
+      timeoutSecs,
+    });
+    await joinRoom(
+      id,
+      verified.collection,
+      guestNft,
+      null,
+      commit(guestChoice, guestSalt, challenger.publicKey)
+    );
+    return { room: roomPda(admin, id), hostNft, guestNft, hostSalt, guestSalt };
+  };
+
+  it("settles a coin flip once both players reveal and rejects a wrong salt", async () => {
+    const { room, hostNft, guestNft, hostSalt, guestSalt } = await playGame(11, 1, 1, 1);
+    await expectError(
+      reveal(room, hostNft, guestNft, challenger, 1, hostSalt),
+      /CommitmentMismatch/
+    );
+    await expectError(
+      reveal(room, hostNft, guestNft, challenger, 0, guestSalt),
+      /CommitmentMismatch/
+    );
+
+    await reveal(room, hostNft, guestNft, null, 1, hostSalt);
+    assert.equal((await program.account.room.fetch(room)).status, 4);
+    await reveal(room, hostNft, guestNft, challenger, 1, guestSalt);
+    const settled = await program.account.room.fetch(room);
+    assert.equal(settled.status, 2);
+    // Matching bits go to the creator
+    assert.ok(settled.winner.equals(admin));
+  });
+
+  it("decides rock-paper-scissors wins and draws", async () => {
+    // Rock (0) loses to paper (1)
+    const won = await playGame(12, 2, 0, 1);
+    await reveal(won.room, won.hostNft, won.guestNft, null, 0, won.hostSalt);
+    await reveal(won.room, won.hostNft, won.guestNft, challenger, 1, won.guestSalt);
+    assert.ok((await program.account.room.fetch(won.room)).winner.equals(challenger.publicKey));
+
+    const drawn = await playGame(13, 2, 2, 2);
+    await reveal(drawn.room, drawn.hostNft, drawn.guestNft, null, 2, drawn.hostSalt);
+    await reveal(drawn.room, drawn.hostNft, drawn.guestNft, challenger, 2, drawn.guestSalt);
+    const settled = await program.account.room.fetch(drawn.room);
+    assert.equal(settled.status, 2);
+    assert.isNull(settled.winner);
+
+    const invalid = await playGame(14, 2, 3, 0);
+    await expectError(
+      reveal(invalid.room, invalid.hostNft, invalid.guestNft, null, 3, invalid.hostSalt),
+      /InvalidChoice/
+    );
+  });
+
+  it("awards a commit-reveal room to the only player who revealed by the deadline", async () => {
+    const { room, hostNft, guestNft, hostSalt, guestSalt } = await playGame(15, 1, 0, 1, 60);
+    await reveal(room, hostNft, guestNft, null, 0, hostSalt);
+
+    await expectError(
+      program.methods.timeoutRoom().accountsPartial(settleAccounts(room, hostNft, guestNft)).rpc(),
+      /RoomDeadlineNotReached/
+    );
+    await new Promise((resolve) => setTimeout(resolve, 61_000));
+    await expectError(
+      reveal(room, hostNft, guestNft, challenger, 1, guestSalt),
+      /RoomDeadlinePassed/
+    );
+    await program.methods.timeoutRoom().accountsPartial(settleAccounts(room, hostNft, guestNft)).rpc();
+    assert.ok((await program.account.room.fetch(room)).winner.equals(admin));
+  });
+
+  it("lets only the creator cancel a room nobody has joined", async () => {
+    const id = roomId(16);
+    const room = roomPda(admin, id);
+    const hostNft = await mintFighter(verified, null);
+    await createRoom(id, verified.collection, hostNft);
+    const cancel = (creator: PublicKey, signers: Keypair[] = []) =>
+      program.methods
+        .cancelRoom()
+        .accountsPartial({
+          room,
+          creator,
+          creatorNftLock: nftLockPda(hostNft),
+          roomVault: null,
+          creatorStakeToken: null,
+          tokenProgram: null,
+          creatorNftEscrow: null,
+          creatorNftDestination: null,
+        })
+        .signers(signers)
+        .rpc();
+
+    await expectError(cancel(challenger.publicKey, [challenger]), /ConstraintHasOne|ConstraintSeeds/);
+    await cancel(admin);
+    assert.isNull(await program.account.room.fetchNullable(room));
+    assert.isNull(await program.account.nftLock.fetchNullable(nftLockPda(hostNft)));
+  });
+
+  it("refunds a referee room left unresolved past its deadline", async () => {
+    const id = roomId(17);
+    const room = roomPda(admin, id);
+    const hostNft = await mintFighter(verified, null);
+    const guestNft = await mintFighter(verified, challenger);
+    await createRoom(id, verified.collection, hostNft, {}, null, { timeoutSecs: 60 });
+    await joinRoom(id, verified.collection, guestNft);
+
+    await expectError(
+      program.methods
+        .cancelRoom()
+        .accountsPartial({
+          room,
+          creator: admin,
+          creatorNftLock: nftLockPda(hostNft),
+          roomVault: null,
+          creatorStakeToken: null,
+          tokenProgram: null,
+          creatorNftEscrow: null,
+          creatorNftDestination: null,
+        })
+        .rpc(),
+      /RoomNotWaiting/
+    );
+    const timeout = () =>
+      program.methods.timeoutRoom().accountsPartial(settleAccounts(room, hostNft, guestNft)).rpc();
+    await expectError(timeout(), /RoomDeadlineNotReached/);
+
+    await new Promise((resolve) => setTimeout(resolve, 61_000));
+    const before = await provider.connection.getBalance(challenger.publicKey);
+    const profileBefore = await program.account.playerProfile.fetch(profilePda(challenger.publicKey));
+    await timeout();
+    const refunded = await program.account.room.fetch(room);
+    assert.equal(refunded.status, 2);
+    assert.isNull(refunded.winner);
+    assert.equal(
+      (await provider.connection.getBalance(challenger.publicKey)) - before,
+      LAMPORTS_PER_SOL / 100
+    );
+    // A refund is not a match
+    const profileAfter = await program.account.playerProfile.fetch(profilePda(challenger.publicKey));
+    assert.equal(profileAfter.draws, profileBefore.draws);
+    assert.equal(profileAfter.recentNext, profileBefore.recentNext);
+  });
+
+  it("escrows token stakes in the room vault and pays them out", async () => {
+    const stake = LAMPORTS_PER_SOL / 100;
+    const stakeMint = await createStakeMint(10 * stake);
+    const otherMint = await createStakeMint(10 * stake);
+    const id = roomId(18);
+    const room = roomPda(admin, id);
+    const [roomVault] = PublicKey.findProgramAddressSync(
+      [Buffer.from("room_vault"), room.toBuffer()],
+      program.programId
+    );
+    const hostNft = await mintFighter(verified, null);
+    const guestNft = await mintFighter(verified, challenger);
+    await createRoom(id, verified.collection, hostNft, {
+      stakeMint,
+      roomVault,
+      creatorStakeToken: ata(admin, stakeMint),
+      tokenProgram: TOKEN_PROGRAM_ID,
+    });
+    assert.equal(await tokenBalance(admin, stakeMint), 9 * stake);
+
+    const joinWith = (challengerStakeToken: PublicKey) =>
+      joinRoom(id, verified.collection, guestNft, null, null, {
+        roomVault,
+        challengerStakeToken,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      });
+    await expectError(joinWith(ata(challenger.publicKey, otherMint)), /InvalidStakeAccount/);
+    await joinWith(ata(challenger.publicKey, stakeMint));
+    assert.equal(
+      Number((await provider.connection.getTokenAccountBalance(roomVault)).value.amount),
+      2 * stake
+    );
+
+    await program.methods
+      .resolveRoom(admin)
+      .accountsPartial({
+        ...settleAccounts(room, hostNft, guestNft),
+        roomVault,
+        creatorStakeToken: ata(admin, stakeMint),
+        challengerStakeToken: ata(challenger.publicKey, stakeMint),
+        tokenProgram: TOKEN_PROGRAM_ID,
+        referee: admin,
+      })
+      .rpc();
+    assert.equal(await tokenBalance(admin, stakeMint), 11 * stake);
+    assert.equal(await tokenBalance(challenger.publicKey, stakeMint), 9 * stake);
+    assert.isNull(await provider.connection.getAccountInfo(roomVault));
+  });
+
+  it("refunds a token stake when a waiting room is cancelled", async () => {
+    const stake = LAMPORTS_PER_SOL / 100;
+    const stakeMint = await createStakeMint(stake);
+    const id = roomId(19);
+    const room = roomPda(admin, id);
+    const [roomVault] = PublicKey.findProgramAddressSync(
+      [Buffer.from("room_vault"), room.toBuffer()],
+      program.programId
+    );
+    const hostNft = await mintFighter(verified, null);
+    await createRoom(id, verified.collection, hostNft, {
+      stakeMint,
+      roomVault,
+      creatorStakeToken: ata(admin, stakeMint),
+      tokenProgram: TOKEN_PROGRAM_ID,
+    });
+    assert.equal(await tokenBalance(admin, stakeMint), 0);
+
+    await program.methods
+      .cancelRoom()
+      .accountsPartial({
+        room,
+        creator: admin,
+        creatorNftLock: nftLockPda(hostNft),
+        roomVault,
+        creatorStakeToken: ata(admin, stakeMint),
+        tokenProgram: TOKEN_PROGRAM_ID,
+        creatorNftEscrow: null,
+        creatorNftDestination: null,
+      })
+      .rpc();
+    assert.equal(await tokenBalance(admin, stakeMint), stake);
+    assert.isNull(await provider.connection.getAccountInfo(roomVault));
+  });
+
+  it("fills a multi-seat room and pays placements with the remainder to first place", async () => {
+    // Odd stake so the 70/30 split leaves a lamport of rounding dust
+    const stake = 1_000_001;
+    const id = roomId(20);
+    const [multiRoom] = PublicKey.findProgramAddressSync(
+      [Buffer.from("multi_room"), admin.toBuffer(), id.toArrayLike(Buffer, "le", 8)],
+      program.programId
+    );
+    const hostNft = await mintFighter(verified, null);
+    const seats = [
+      { wallet: admin, nft: hostNft },
+      { wallet: challenger.publicKey, nft: await mintFighter(verified, challenger) },
+      { wallet: rival.publicKey, nft: await mintFighter(verified, rival) },
+    ];
+    await program.methods
+      .createMultiRoom(id, new anchor.BN(stake), 3, [7000, 3000], null, new anchor.BN(3600))
+      .accountsPartial({
+        marketplace,
+        multiRoom,
+        creator: admin,
+        rakeExemption: null,
+        collection: verified.collection,
+        nftMint: hostNft,
+        nftMetadata: metadataPda(hostNft),
+        creatorNftToken: ata(admin, hostNft),
+        creatorNftLock: nftLockPda(hostNft),
+        tokenMetadataProgram: TOKEN_METADATA_PROGRAM_ID,
+      })
+      .rpc();
+
+    const join = (player: Keypair, nft: PublicKey) =>
+      program.methods
+        .joinMultiRoom()
+        .accountsPartial({
+          multiRoom,
+          collection: verified.collection,
+          player: player.publicKey,
+          nftMint: nft,
+          nftMetadata: metadataPda(nft),
+          playerNftToken: ata(player.publicKey, nft),
+          playerNftLock: nftLockPda(nft),
+          tokenMetadataProgram: TOKEN_METADATA_PROGRAM_ID,
+        })
+        .signers([player])
+        .rpc();
+    await join(challenger, seats[1].nft);
+    assert.equal(
+      (await program.account.multiRoom.fetch(multiRoom)).status,
+      0,
+      "room keeps filling until every seat is taken"
+    );
+    await join(rival, seats[2].nft);
+    const started = await program.account.multiRoom.fetch(multiRoom);
+    assert.equal(started.status, 1);
+    assert.isAbove(started.deadline.toNumber(), 0);
+    await expectError(join(challenger, await mintFighter(verified, challenger)), /RoomNotWaiting/);
+
+    const resolve = (placements: number[]) =>
+      program.methods
+        .resolveMultiRoom(Buffer.from(placements))
+        .accountsPartial({ multiRoom, creator: admin, referee: admin, treasury: null })
+        .remainingAccounts(
+          [
+            ...placements.map((seat) => seats[seat % seats.length].wallet),
+            ...seats.map(({ wallet }) => wallet),
+            ...seats.map(({ nft }) => nftLockPda(nft)),
+          ].map((pubkey) => ({ pubkey, isSigner: false, isWritable: true }))
+        )
+        .rpc();
+    await expectError(resolve([2, 2]), /InvalidPlacements/);
+    await expectError(resolve([3, 1]), /InvalidPlacements/);
+    await expectError(resolve([2]), /InvalidPlacements/);
+
+    const before = await Promise.all(
+      [rival.publicKey, challenger.publicKey].map((wallet) =>
+        provider.connection.getBalance(wallet)
+      )
+    );
+    await resolve([2, 1]);
+    // Each seat's NFT lock rent goes back to its wallet
+    const lockRent = await provider.connection.getMinimumBalanceForRentExemption(
+      8 + 32 + 32 + 32 + 1
+    );
+    const pot = 3 * stake;
+    const second = Math.floor((pot * 3000) / 10_000);
+    const first = pot - second;
+    assert.isAbove(first, Math.floor((pot * 7000) / 10_000), "rounding dust goes to first place");
+    assert.equal((await provider.connection.getBalance(rival.publicKey)) - before[0], first + lockRent);
+    assert.equal(
+      (await provider.connection.getBalance(challenger.publicKey)) - before[1],
+      second + lockRent
+    );
+    assert.isNull(await program.account.multiRoom.fetchNullable(multiRoom));
+    for (const { nft } of seats) {
+      assert.isNull(await provider.connection.getAccountInfo(nftLockPda(nft)));
+    }
+  });
+
+  it("pairs queued players of the same tier into a referee room", async () => {
+    const queuePda = (stake: number) =>
+      PublicKey.findProgramAddressSync(
+        [
+          Buffer.from("match_queue"),
+          verified.collection.toBuffer(),
+          new anchor.BN(stake).toArrayLike(Buffer, "le", 8),
+        ],
+        program.programId
+      )[0];
+    const lowStake = LAMPORTS_PER_SOL / 100;
+    const highStake = LAMPORTS_PER_SOL / 50;
+    for (const stake of [lowStake, highStake]) {
+      await program.methods
+        .createMatchQueue(new anchor.BN(stake), new anchor.BN(3600))
+        .accountsPartial({
+          marketplace,
+          collection: verified.collection,
+          matchQueue: queuePda(stake),
+          admin,
+        })
+        .rpc();
+    }
+
+    const enqueue = async (
+      player: Keypair,
+      nft: PublicKey,
+      stake: number,
+      { collection = verified.collection, pair = false } = {}
+    ) => {
+      const matchQueue = queuePda(stake);
+      const queue = await program.account.matchQueue.fetch(matchQueue);
+      // Room id the program derives for the next pairing on this queue
+      const nextRoomId = new anchor.BN(
+        createHash("sha256")
+          .update(
+            Buffer.concat([
+              queue.collection.toBuffer(),
+              new anchor.BN(stake).toArrayLike(Buffer, "le", 8),
+              queue.matches.toArrayLike(Buffer, "le", 8),
+            ])
+          )
+          .digest()
+          .subarray(0, 8),
+        "le"
+      );
+      const room = roomPda(player.publicKey, nextRoomId);
+      await program.methods
+        .enqueue()
+        .accountsPartial({
+          marketplace,
+          matchQueue,
+          room: pair ? room : null,
+          player: player.publicKey,
+          playerProfile: profilePda(player.publicKey),
+          rakeExemption: null,
+          collection,
+          nftMint: nft,
+          nftMetadata: metadataPda(nft),
+          playerNftToken: ata(player.publicKey, nft),
+          playerNftLock: nftLockPda(nft),
+          waitingNftLock: pair ? nftLockPda(queue.waitingNft) : null,
+          tokenMetadataProgram: TOKEN_METADATA_PROGRAM_ID,
+        })
+        .signers([player])
+        .rpc();
+      return room;
+    };
+
+    const waitingNft = await mintFighter(verified, challenger);
+    const rivalNft = await mintFighter(verified, rival);
+    await enqueue(challenger, waitingNft, lowStake);
+    await expectError(
+      enqueue(challenger, await mintFighter(verified, challenger), lowStake, { pair: true }),
+      /AlreadyQueued/
+    );
+    await expectError(
+      enqueue(rival, rivalNft, lowStake, { collection: unverified.collection }),
+      /CollectionMismatch/
+    );
+
+    // A different stake tier is a separate queue: the rival waits there instead of pairing
+    await enqueue(rival, rivalNft, highStake);
+    const waiting = async (stake: number) =>
+      (await program.account.matchQueue.fetch(queuePda(stake))).waiting;
+    assert.ok((await waiting(highStake)).equals(rival.publicKey));
+    assert.ok((await waiting(lowStake)).equals(challenger.publicKey));
+
+    const lockRent = await provider.connection.getMinimumBalanceForRentExemption(
+      8 + 32 + 32 + 32 + 1
+    );
+    const beforeLeave = await provider.connection.getBalance(rival.publicKey);
+    await program.methods
+      .leaveQueue()
+      .accountsPartial({
+        matchQueue: queuePda(highStake),
+        player: rival.publicKey,
+        playerNftLock: nftLockPda(rivalNft),
+      })
+      .signers([rival])
+      .rpc();
+    assert.equal(
+      (await provider.connection.getBalance(rival.publicKey)) - beforeLeave,
+      highStake + lockRent
+    );
+    assert.isNull(await waiting(highStake));
+
+    const room = await enqueue(rival, rivalNft, lowStake, { pair: true });
+    const paired = await program.account.room.fetch(room);
+    assert.ok(paired.creator.equals(rival.publicKey));
+    assert.ok(paired.challenger.equals(challenger.publicKey));
+    assert.ok(paired.referee.equals(admin));
+    assert.equal(paired.status, 1);
+    assert.equal(paired.stakeLamports.toNumber(), lowStake);
+    assert.ok((await program.account.nftLock.fetch(nftLockPda(waitingNft))).room.equals(room));
+    const queue = await program.account.matchQueue.fetch(queuePda(lowStake));
+    assert.isNull(queue.waiting);
+    assert.equal(queue.matches.toNumber(), 1);
+  });
+
+  // Room wagering both gating NFTs; returns the room and its escrows
+  const createNftWagerRoom = async (n: number, hostNft: PublicKey) => {
+    const id = roomId(n);
+    const room = roomPda(admin, id);
+    const escrow = (nft: PublicKey) =>
+      PublicKey.findProgramAddressSync(
+        [Buffer.from("room_nft"), room.toBuffer(), nft.toBuffer()],
+        program.programId
+      )[0];
+    await createRoom(id, verified.collection, hostNft, {
+      creatorNftEscrow: escrow(hostNft),
+      tokenProgram: TOKEN_PROGRAM_ID,
+    });
+    return { id, room, escrow };
+  };
+
+  it("gives the winner both wagered NFTs and rejects an escrow for another mint", async () => {
+    const hostNft = await mintFighter(verified, null);
+    const guestNft = await mintFighter(verified, challenger);
+    const { id, room, escrow } = await createNftWagerRoom(22, hostNft);
+    assert.equal(await tokenBalance(admin, hostNft), 0);
+
+    const joinWith = (challengerNftEscrow: PublicKey) =>
+      joinRoom(id, verified.collection, guestNft, null, null, {
+        challengerNftEscrow,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      });
+    await expectError(joinWith(escrow(hostNft)), /ConstraintSeeds/);
+    await joinWith(escrow(guestNft));
+    assert.equal(await tokenBalance(challenger.publicKey, guestNft), 0);
+
+    await provider.sendAndConfirm(new Transaction().add(createAtaIx(admin, guestNft)));
+    const resolve = (challengerNftDestination: PublicKey) =>
+      program.methods
+        .resolveRoom(admin)
+        .accountsPartial({
+          ...settleAccounts(room, hostNft, guestNft),
+          tokenProgram: TOKEN_PROGRAM_ID,
+          creatorNftEscrow: escrow(hostNft),
+          challengerNftEscrow: escrow(guestNft),
+          creatorNftDestination: ata(admin, hostNft),
+          challengerNftDestination,
+          referee: admin,
+        })
+        .rpc();
+    // The loser's NFT goes to the winner, so the loser's own account is refused
+    await expectError(resolve(ata(challenger.publicKey, guestNft)), /InvalidNftDestination/);
+    await resolve(ata(admin, guestNft));
+    assert.equal(await tokenBalance(admin, hostNft), 1);
+    assert.equal(await tokenBalance(admin, guestNft), 1);
+    for (const nft of [hostNft, guestNft]) {
+      assert.isNull(await provider.connection.getAccountInfo(escrow(nft)));
+    }
+  });
+
+  it("returns each wagered NFT to its owner on a draw or a cancel", async () => {
+    const hostNft = await mintFighter(verified, null);
+    const guestNft = await mintFighter(verified, challenger);
+    const drawn = await createNftWagerRoom(23, hostNft);
+    await joinRoom(drawn.id, verified.collection, guestNft, null, null, {
+      challengerNftEscrow: drawn.escrow(guestNft),
+      tokenProgram: TOKEN_PROGRAM_ID,
+    });
+    await program.methods
+      .resolveRoom(null)
+      .accountsPartial({
+        ...settleAccounts(drawn.room, hostNft, guestNft),
+        tokenProgram: TOKEN_PROGRAM_ID,
+        creatorNftEscrow: drawn.escrow(hostNft),
+        challengerNftEscrow: drawn.escrow(guestNft),
+        creatorNftDestination: ata(admin, hostNft),
+        challengerNftDestination: ata(challenger.publicKey, guestNft),
+        referee: admin,
+      })
+      .rpc();
+    assert.equal(await tokenBalance(admin, hostNft), 1);
+    assert.equal(await tokenBalance(challenger.publicKey, guestNft), 1);
+
+    const cancelled = await createNftWagerRoom(24, hostNft);
+    assert.equal(await tokenBalance(admin, hostNft), 0);
+    await program.methods
+      .cancelRoom()
+      .accountsPartial({
+        room: cancelled.room,
+        creator: admin,
+        creatorNftLock: nftLockPda(hostNft),
+        roomVault: null,
+        creatorStakeToken: null,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        creatorNftEscrow: cancelled.escrow(hostNft),
+        creatorNftDestination: ata(admin, hostNft),
+      })
+      .rpc();
+    assert.equal(await tokenBalance(admin, hostNft), 1);
+    assert.isNull(await provider.connection.getAccountInfo(cancelled.escrow(hostNft)));
+  });
+
+  it("reopens a settled room for a rematch with the same challenger", async () => {
+    const id = roomId(4);
+    const room = roomPda(admin, id);
+    await program.methods
+      .resolveRoom(admin)
+      .accountsPartial({ ...settleAccounts(room, creatorNft, challengerNft), referee: admin })
+      .rpc();
+    const settled = await program.account.room.fetch(room);
+    assert.ok(settled.winner.equals(admin));
+
+    await program.methods
+      .rematch(null)
+      .accountsPartial({
+        room,
+        creator: admin,
+        marketplace,
+        rakeExemption: null,
+        collection: verified.collection,
+        creatorNftToken: ata(admin, creatorNft),
+        creatorNftLock: nftLockPda(creatorNft),
+      })
+      .rpc();
+    const reopened = await program.account.room.fetch(room);
+    assert.equal(reopened.status, 0);
+    assert.isNull(reopened.challenger);
+    assert.ok(reopened.invitedChallenger.equals(challenger.publicKey));
+
+    await joinRoom(id, verified.collection, challengerNft);
+  });
+
+  it("plays a rematch under the current terms of a still-verified collection", async () => {
+    const id = roomId(25);
+    const room = roomPda(admin, id);
+    const hostNft = await mintFighter(verified, null);
+    const guestNft = await mintFighter(verified, challenger);
+    await createRoom(id, verified.collection, hostNft);
+    await joinRoom(id, verified.collection, guestNft);
+    await program.methods
+      .resolveRoom(admin)
+      .accountsPartial({ ...settleAccounts(room, hostNft, guestNft), referee: admin })
+      .rpc();
+    assert.equal((await program.account.room.fetch(room)).disputeWindowSecs.toNumber(), 0);
+
+    const setVerified = (verifiedFlag: boolean) =>
+      program.methods
+        .setCollectionVerified(verifiedFlag)
+        .accountsPartial({ marketplace, collection: verified.collection, admin })
+        .rpc();
+    const setDisputeTerms = (windowSecs: number) =>
+      program.methods
+        .setDisputeTerms(new anchor.BN(windowSecs), new anchor.BN(0))
+        .accountsPartial({ marketplace, admin })
+        .rpc();
+    const rematch = () =>
+      program.methods
+        .rematch(null)
+        .accountsPartial({
+          room,
+          creator: admin,
+          marketplace,
+          rakeExemption: null,
+          collection: verified.collection,
+          creatorNftToken: ata(admin, hostNft),
+          creatorNftLock: nftLockPda(hostNft),
+        })
+        .rpc();
+
+    await setVerified(false);
+    try {
+      await expectError(rematch(), /CollectionNotVerified/);
+    } finally {
+      await setVerified(true);
+    }
+
+    await setDisputeTerms(600);
+    try {
+      await rematch();
+    } finally {
+      await setDisputeTerms(0);
+    }
+    const reopened = await program.account.room.fetch(room);
+    assert.equal(reopened.status, 0);
+    assert.equal(reopened.disputeWindowSecs.toNumber(), 600);
+
+    await program.methods
+      .cancelRoom()
+      .accountsPartial({
+        room,
+        creator: admin,
+        creatorNftLock: nftLockPda(hostNft),
+        roomVault: null,
+        creatorStakeToken: null,
+        tokenProgram: null,
+        creatorNftEscrow: null,
+        creatorNftDestination: null,
+      })
+      .rpc();
+  });
+
+  it("takes spectator bets on a room in play but not from its players", async () => {
+    const room = roomPda(admin, roomId(4));
+    const [betPool] = PublicKey.findProgramAddressSync(
+      [Buffer.from("bet_pool"), room.toBuffer()],
+      program.programId
+    );
+    const betPda = (bettor: PublicKey) =>
+      PublicKey.findProgramAddressSync(
+        [Buffer.from("bet"), betPool.toBuffer(), bettor.toBuffer()],
+        program.programId
+      )[0];
+    const cutoff = new anchor.BN(Math.floor(Date.now() / 1000) + 600);
+    await program.methods.createBetPool(cutoff).accountsPartial({ marketplace, room, betPool }).rpc();
+
+    const spectator = Keypair.generate();
+    await provider.connection.confirmTransaction(
+      await provider.connection.requestAirdrop(spectator.publicKey, LAMPORTS_PER_SOL)
+    );
+    const stake = new anchor.BN(LAMPORTS_PER_SOL / 10);
+    await program.methods
+      .placeBet(0, stake)
+      .accountsPartial({ room, betPool, bet: betPda(spectator.publicKey), bettor: spectator.publicKey })
+      .signers([spectator])
+      .rpc();
+    const pool = await program.account.betPool.fetch(betPool);
+    assert.ok(pool.creatorTotal.eq(stake));
+
+    await expectError(
+      program.methods
+        .placeBet(1, stake)
+        .accountsPartial({ room, betPool, bet: betPda(challenger.publicKey), bettor: challenger.publicKey })
+        .signers([challenger])
+        .rpc(),
+      /Unauthorized/
+    );
+  });
+
+  it("closes betting at the first reveal and reopens a pool after a rematch", async () => {
+    const poolPda = (room: PublicKey) =>
+      PublicKey.findProgramAddressSync(
+        [Buffer.from("bet_pool"), room.toBuffer()],
+        program.programId
+      )[0];
+    const betPda = (betPool: PublicKey, bettor: PublicKey) =>
+      PublicKey.findProgramAddressSync(
+        [Buffer.from("bet"), betPool.toBuffer(), bettor.toBuffer()],
+        program.programId
+      )[0];
+    const cutoff = () => new anchor.BN(Math.floor(Date.now() / 1000) + 600);
+    const openPool = (room: PublicKey) =>
+      program.methods
+        .createBetPool(cutoff())
+        .accountsPartial({ marketplace, room, betPool: poolPda(room) })
+        .rpc();
+
+    // A referee picked by the players could settle the bets in their favour
+    const privateId = roomId(27);
+    const privateRoom = roomPda(admin, privateId);
+    await createRoom(privateId, verified.collection, await mintFighter(verified, null), {}, null, {
+      referee: rival.publicKey,
+    });
+    await joinRoom(privateId, verified.collection, await mintFighter(verified, challenger));
+    await expectError(openPool(privateRoom), /BetPoolUnsupported/);
+
+    const { room, hostNft, guestNft, hostSalt, guestSalt } = await playGame(26, 1, 1, 1);
+    const betPool = poolPda(room);
+    await openPool(room);
+    const bet = (side: number) =>
+      program.methods
+        .placeBet(side, new anchor.BN(LAMPORTS_PER_SOL / 100))
+        .accountsPartial({
+          room,
+          betPool,
+          bet: betPda(betPool, rival.publicKey),
+          bettor: rival.publicKey,
+        })
+        .signers([rival])
+        .rpc();
+    await bet(0);
+    await reveal(room, hostNft, guestNft, null, 1, hostSalt);
+    await expectError(bet(0), /BetPoolClosed/);
+    await reveal(room, hostNft, guestNft, challenger, 1, guestSalt);
+
+    await program.methods
+      .settleBetPool()
+      .accountsPartial({ room, betPool, treasury: admin })
+      .rpc();
+    const closePool = () =>
+      program.methods.closeBetPool().accountsPartial({ betPool, payer: admin }).rpc();
+    await expectError(closePool(), /BetsOutstanding/);
+    // Anyone can claim on a bettor's behalf, so an idle bettor cannot hold the pool open
+    await program.methods
+      .claimBet()
+      .accountsPartial({ betPool, bet: betPda(betPool, rival.publicKey), bettor: rival.publicKey })
+      .rpc();
+    await closePool();
+    assert.isNull(await program.account.betPool.fetchNullable(betPool));
+
+    const rematchSalt = Keypair.generate().publicKey.toBuffer();
+    await program.methods
+      .rematch(commit(0, rematchSalt, admin))
+      .accountsPartial({
+        room,
+        creator: admin,
+        marketplace,
+        rakeExemption: null,
+        collection: verified.collection,
+        creatorNftToken: ata(admin, hostNft),
+        creatorNftLock: nftLockPda(hostNft),
+      })
+      .rpc();
+    await joinRoom(
+      roomId(26),
+      verified.collection,
+      guestNft,
+      null,
+      commit(0, rematchSalt, challenger.publicKey)
+    );
+    await openPool(room);
+    assert.equal((await program.account.betPool.fetch(betPool)).openBets, 0);
+  });
+
+  it("sets battle stats on a type within the allowed range", async () => {
+    const nftType = PublicKey.findProgramAddressSync(
+      [Buffer.from("type"), verified.collection.toBuffer(), sha256("fighter")],
+      program.programId
+    )[0];
+    const accounts = { collection: verified.collection, nftType, admin };
+
+    await expectError(
+      program.methods
+        .setBattleStats({ attack: 5000, defense: 10, speed: 10, rarity: 0 })
+        .accountsPartial(accounts)
+        .rpc(),
+      /InvalidBattleStats/
+    );
+
+    await program.methods
+      .setBattleStats({ attack: 40, defense: 20, speed: 30, rarity: 2 })
+      .accountsPartial(accounts)
+      .rpc();
+    const fighter = await program.account.nftType.fetch(nftType);
+    assert.equal(fighter.battleStats.attack, 40);
+    assert.equal(fighter.battleStats.rarity, 2);
+  });
+
+  it("fights with the stats of the type an item was minted as", async () => {
+    const typePda = (name: string) =>
+      PublicKey.findProgramAddressSync(
+        [Buffer.from("type"), verified.collection.toBuffer(), sha256(name)],
+        program.programId
+      )[0];
+    const itemTypePda = (nftMint: PublicKey) =>
+      PublicKey.findProgramAddressSync(
+        [Buffer.from("item_type"), nftMint.toBuffer()],
+        program.programId
+      )[0];
+    // Another type whose name shares the fighter's prefix
+    await program.methods
+      .createNftType(
+        "fighter #1",
+        "https://example.com/brawler.json",
+        new anchor.BN(0),
+        new anchor.BN(100),
+        new anchor.BN(10_000),
+        false,
+        null
+      )
+      .accountsPartial({
+        collection: verified.collection,
+        nftType: typePda("fighter #1"),
+        typeIndex: indexPda("type_index", verified.collection, new anchor.BN(1)),
+        admin,
+      })
+      .rpc();
+
+    const hostNft = await mintFighter(verified, null);
+    const itemType = itemTypePda(hostNft);
+    assert.ok((await program.account.itemType.fetch(itemType)).nftType.equals(typePda("fighter")));
+
+    const id = roomId(28);
+    const salt = Keypair.generate().publicKey.toBuffer();
+    const createBattle = (nftType: PublicKey) =>
+      createRoom(id, verified.collection, hostNft, { nftType, itemType }, null, {
+        mode: 3,
+        commitment: commit(0, salt, admin),
+      });
+    await expectError(createBattle(typePda("fighter #1")), /NftTypeMismatch/);
+    await createBattle(typePda("fighter"));
+    const room = await program.account.room.fetch(roomPda(admin, id));
+    assert.equal(room.creatorStats.attack, 40);
+    assert.equal(room.creatorStats.rarity, 2);
+
+    await program.methods
+      .cancelRoom()
+      .accountsPartial({
+        room: roomPda(admin, id),
+        creator: admin,
+        creatorNftLock: nftLockPda(hostNft),
+        roomVault: null,
+        creatorStakeToken: null,
+        tokenProgram: null,
+        creatorNftEscrow: null,
+        creatorNftDestination: null,
+      })
+      .rpc();
+  });
+
+  it("backfills the type of an item minted before item types were recorded", async () => {
+    const typePda = (name: string) =>
+      PublicKey.findProgramAddressSync(
+        [Buffer.from("type"), verified.collection.toBuffer(), sha256(name)],
+        program.programId
+      )[0];
+    const itemTypePda = (nftMint: PublicKey) =>
+      PublicKey.findProgramAddressSync(
+        [Buffer.from("item_type"), nftMint.toBuffer()],
+        program.programId
+      )[0];
+    const borshString = (value: string) => {
+      const len = Buffer.alloc(4);
+      len.writeUInt32LE(Buffer.byteLength(value));
+      return Buffer.concat([len, Buffer.from(value)]);
+    };
+
+    // An item as the marketplace minted it before, without an item type account
+    const legacyItem = async (name: string) => {
+      const mint = Keypair.generate();
+      const metadata = metadataPda(mint.publicKey);
+      const tx = new Transaction().add(
+        SystemProgram.createAccount({
+          fromPubkey: admin,
+          newAccountPubkey: mint.publicKey,
+          lamports: await provider.connection.getMinimumBalanceForRentExemption(82),
+          space: 82,
+          programId: TOKEN_PROGRAM_ID,
+        }),
+        // InitializeMint2: decimals 0, admin as mint authority, no freeze authority
+        new TransactionInstruction({
+          programId: TOKEN_PROGRAM_ID,
+          keys: [{ pubkey: mint.publicKey, isSigner: false, isWritable: true }],
+          data: Buffer.concat([Buffer.from([20, 0]), admin.toBuffer(), Buffer.from([0])]),
+        }),
+        // CreateMetadataAccountV3 naming the collection, not yet verified
+        new TransactionInstruction({
+          programId: TOKEN_METADATA_PROGRAM_ID,
+          keys: [
+            { pubkey: metadata, isSigner: false, isWritable: true },
+            { pubkey: mint.publicKey, isSigner: false, isWritable: false },
+            { pubkey: admin, isSigner: true, isWritable: false },
+            { pubkey: admin, isSigner: true, isWritable: true },
+            { pubkey: admin, isSigner: true, isWritable: false },
+            { pubkey: SystemProgram.programId, isSigner: false, isWritable: false },
+          ],
+          data: Buffer.concat([
+            Buffer.from([33]),
+            borshString(name),
+            borshString("ROOM"),
+            borshString("https://example.com/fighter.json"),
+            Buffer.from([0xf4, 0x01, 0, 1, 0]),
+            verified.collectionMint.toBuffer(),
+            Buffer.from([0, 0, 0]),
+          ]),
+        }),
+        // VerifySizedCollectionItem
+        new TransactionInstruction({
+          programId: TOKEN_METADATA_PROGRAM_ID,
+          keys: [
+            { pubkey: metadata, isSigner: false, isWritable: true },
+            { pubkey: admin, isSigner: true, isWritable: false },
+            { pubkey: admin, isSigner: true, isWritable: true },
+            { pubkey: verified.collectionMint, isSigner: false, isWritable: false },
+            { pubkey: metadataPda(verified.collectionMint), isSigner: false, isWritable: true },
+            { pubkey: editionPda(verified.collectionMint), isSigner: false, isWritable: false },
+          ],
+          data: Buffer.from([30]),
+        })
+      );
+      await provider.sendAndConfirm(tx, [mint]);
+      return mint.publicKey;
+    };
+    const backfill = (nftMint: PublicKey, nftType: PublicKey) =>
+      program.methods
+        .backfillItemType()
+        .accountsPartial({
+          collection: verified.collection,
+          nftType,
+          nftMint,
+          nftMetadata: metadataPda(nftMint),
+          itemType: itemTypePda(nftMint),
+          admin,
+          tokenMetadataProgram: TOKEN_METADATA_PROGRAM_ID,
+        })
+        .rpc();
+
+    const legacy = await legacyItem("fighter #1");
+    await expectError(backfill(legacy, typePda("fighter #1")), /NftTypeMismatch/);
+    await expectError(backfill(await legacyItem("fighter #100"), typePda("fighter")), /NftTypeMismatch/);
+    await expectError(backfill(outsiderNft, typePda("fighter")), /NftNotInCollection/);
+    await expectError(backfill(creatorNft, typePda("fighter")), /already in use/);
+
+    await backfill(legacy, typePda("fighter"));
+    assert.ok((await program.account.itemType.fetch(itemTypePda(legacy))).nftType.equals(typePda("fighter")));
+  });
+
+  it("only lets the referee resolve and splits the pot on a draw", async () => {
+    const id = roomId(10);
+    const room = roomPda(admin, id);
+    const hostNft = await mintFighter(verified, null);
+    const guestNft = await mintFighter(verified, challenger);
+    await createRoom(id, verified.collection, hostNft);
+    await joinRoom(id, verified.collection, guestNft);
+
+    await expectError(
+      program.methods
+        .resolveRoom(challenger.publicKey)
+        .accountsPartial({ ...settleAccounts(room, hostNft, guestNft), referee: challenger.publicKey })
+        .signers([challenger])
+        .rpc(),
+      /Unauthorized|ConstraintHasOne|ConstraintRaw/
+    );
+
+    const before = await provider.connection.getBalance(challenger.publicKey);
+    await program.methods
+      .resolveRoom(null)
+      .accountsPartial({ ...settleAccounts(room, hostNft, guestNft), referee: admin })
+      .rpc();
+    const drawn = await program.account.room.fetch(room);
+    assert.equal(drawn.status, 2);
+    assert.isNull(drawn.winner);
+    assert.equal(
+      (await provider.connection.getBalance(challenger.publicKey)) - before,
+      LAMPORTS_PER_SOL / 100
+    );
+  });
+
+  it("updates both profiles and their match history when a room settles", async () => {
+    const id = roomId(21);
+    const room = roomPda(admin, id);
+    const hostNft = await mintFighter(verified, null);
+    const guestNft = await mintFighter(verified, challenger);
+    await createRoom(id, verified.collection, hostNft);
+    await joinRoom(id, verified.collection, guestNft);
+
+    const profiles = () =>
+      Promise.all(
+        [admin, challenger.publicKey].map((wallet) =>
+          program.account.playerProfile.fetch(profilePda(wallet))
+        )
+      );
+    const [hostBefore, guestBefore] = await profiles();
+    await program.methods
+      .resolveRoom(challenger.publicKey)
+      .accountsPartial({ ...settleAccounts(room, hostNft, guestNft), referee: admin })
+      .rpc();
+    const [host, guest] = await profiles();
+
+    const stake = LAMPORTS_PER_SOL / 100;
+    for (const [before, after, opponent, result, payout] of [
+      [hostBefore, host, challenger.publicKey, 0, 0],
+      [guestBefore, guest, admin, 1, 2 * stake],
+    ] as const) {
+      // The result lands in the ring slot the profile pointed at, which then advances
+      const record = after.recent[before.recentNext];
+      assert.equal(after.recentNext, (before.recentNext + 1) % 8);
+      assert.ok(record.room.equals(room));
+      assert.ok(record.opponent.equals(opponent));
+      assert.equal(record.result, result);
+      assert.equal(record.stake.toNumber(), stake);
+      assert.equal(record.payout.toNumber(), payout);
+      assert.equal(after.rating - before.rating, record.ratingDelta);
+      assert.equal(after.totalWagered.sub(before.totalWagered).toNumber(), stake);
+      assert.equal(after.totalWon.sub(before.totalWon).toNumber(), payout);
+    }
+    assert.isBelow(host.recent[hostBefore.recentNext].ratingDelta, 0);
+    assert.isAbove(guest.recent[guestBefore.recentNext].ratingDelta, 0);
+    assert.equal(host.losses - hostBefore.losses, 1);
+    assert.equal(guest.wins - guestBefore.wins, 1);
+  });
+
+  it("holds a referee result for disputes and lets the admin overturn it", async () => {
+    await program.methods
+      .setDisputeTerms(new anchor.BN(3600), new anchor.BN(LAMPORTS_PER_SOL / 100))
+      .accountsPartial({ marketplace, admin })
+      .rpc();
+    try {
+      const id = roomId(7);
+      const room = roomPda(admin, id);
+      const hostNft = await mintFighter(verified, null);
+      const guestNft = await mintFighter(verified, challenger);
+      await createRoom(id, verified.collection, hostNft);
+      await joinRoom(id, verified.collection, guestNft);
+
+      await program.methods
+        .resolveRoom(admin)
+        .accountsPartial({ ...settleAccounts(room, hostNft, guestNft), referee: admin })
+        .rpc();
+      assert.equal((await program.account.room.fetch(room)).status, 5);
+
+      const [dispute] = PublicKey.findProgramAddressSync(
+        [Buffer.from("room_dispute"), room.toBuffer()],
+        program.programId
+      );
+      await expectError(
+        program.methods
+          .finalizeResult()
+          .accountsPartial({ ...settleAccounts(room, hostNft, guestNft), dispute: null, disputer: null })
+          .rpc(),
+        /DisputeWindowOpen/
+      );
+      await program.methods
+        .disputeResult()
+        .accountsPartial({ room, dispute, disputer: challenger.publicKey })
+        .signers([challenger])
+        .rpc();
+      assert.equal((await program.account.room.fetch(room)).status, 6);
+
+      await program.methods
+        .arbitrateRoom(challenger.publicKey, false)
+        .accountsPartial({
+          ...settleAccounts(room, hostNft, guestNft),
+          marketplace,
+          admin,
+          dispute,
+          disputer: challenger.publicKey,
+        })
+        .rpc();
+      const settled = await program.account.room.fetch(room);
+      assert.ok(settled.winner.equals(challenger.publicKey));
+      assert.isNull(await program.account.roomDispute.fetchNullable(dispute));
+    } finally {
+      await program.methods
+        .setDisputeTerms(new anchor.BN(0), new anchor.BN(0))
+        .accountsPartial({ marketplace, admin })
+        .rpc();
+    }
+  });
+
+  it("replays a series game the admin overturns instead of settling it", async () => {
+    await program.methods
+      .setDisputeTerms(new anchor.BN(3600), new anchor.BN(0))
+      .accountsPartial({ marketplace, admin })
+      .rpc();
+    try {
+      const id = roomId(29);
+      const room = roomPda(admin, id);
+      const hostNft = await mintFighter(verified, null);
+      const guestNft = await mintFighter(verified, challenger);
+      await createRoom(id, verified.collection, hostNft, {}, null, { bestOf: 3 });
+      await joinRoom(id, verified.collection, guestNft);
+      const resolve = () =>
+        program.methods
+          .resolveRoom(admin)
+          .accountsPartial({ ...settleAccounts(room, hostNft, guestNft), referee: admin })
+          .rpc();
+      await resolve();
+      // The clinching game is only proposed
+      await resolve();
+      assert.equal((await program.account.room.fetch(room)).status, 5);
+
+      const [dispute] = PublicKey.findProgramAddressSync(
+        [Buffer.from("room_dispute"), room.toBuffer()],
+        program.programId
+      );
+      await program.methods
+        .disputeResult()
+        .accountsPartial({ room, dispute, disputer: challenger.publicKey })
+        .signers([challenger])
+        .rpc();
+      await program.methods
+        .arbitrateRoom(challenger.publicKey, false)
+        .accountsPartial({
+          ...settleAccounts(room, hostNft, guestNft),
+          marketplace,
+          admin,
+          dispute,
+          disputer: challenger.publicKey,
+        })
+        .rpc();
+
+      // 1-1 after the overturned game, so the series plays on
+      const resumed = await program.account.room.fetch(room);
+      assert.equal(resumed.status, 1);
+      assert.equal(resumed.creatorGameWins, 1);
+      assert.equal(resumed.challengerGameWins, 1);
+      assert.equal(resumed.gamesPlayed, 2);
+      assert.isNull(resumed.proposedWinner);
+      assert.isNull(await program.account.roomDispute.fetchNullable(dispute));
+    } finally {
+      await program.methods
+        .setDisputeTerms(new anchor.BN(0), new anchor.BN(0))
+        .accountsPartial({ marketplace, admin })
+        .rpc();
+    }
+  });
+
+  it("lets a scoped session key forfeit for its wallet", async () => {
+    const id = roomId(9);
+    const room = roomPda(admin, id);
+    const hostNft = await mintFighter(verified, null);
+    const guestNft = await mintFighter(verified, challenger);
+    await createRoom(id, verified.collection, hostNft);
+    await joinRoom(id, verified.collection, guestNft);
+
+    const sessionKey = Keypair.generate();
+    const [session] = PublicKey.findProgramAddressSync(
+      [Buffer.from("session"), challenger.publicKey.toBuffer()],
+      program.programId
+    );
+    const expiresAt = new anchor.BN(Math.floor(Date.now() / 1000) + 3600);
+    const forfeit = () =>
+      program.methods
+        .forfeitRoom()
+        .accountsPartial({ ...settleAccounts(room, hostNft, guestNft), player: sessionKey.publicKey, session })
+        .signers([sessionKey])
+        .rpc();
+
+    // Commit-only sessions cannot concede
+    await program.methods
+      .createSession(sessionKey.publicKey, expiresAt, 1)
+      .accountsPartial({ wallet: challenger.publicKey })
+      .signers([challenger])
+      .rpc();
+    await expectError(forfeit(), /InvalidSession/);
+
+    await program.methods
+      .createSession(sessionKey.publicKey, expiresAt, 4)
+      .accountsPartial({ wallet: challenger.publicKey })
+      .signers([challenger])
+      .rpc();
+    await forfeit();
+    const settled = await program.account.room.fetch(room);
+    assert.ok(settled.winner.equals(admin));
+
+    await program.methods
+      .revokeSession()
+      .accountsPartial({ wallet: challenger.publicKey })
+      .signers([challenger])
+      .rpc();
+    assert.isNull(await program.account.sessionKey.fetchNullable(session));
+  });
+
+  it("awards season points on settlement and pays the leaderboard", async () => {
+    const seasonId = new anchor.BN(stamp);
+    const [season] = PublicKey.findProgramAddressSync(
+      [Buffer.from("season"), seasonId.toArrayLike(Buffer, "le", 8)],
+      program.programId
+    );
+    const [nextSeason] = PublicKey.findProgramAddressSync(
+      [Buffer.from("season"), seasonId.addn(1).toArrayLike(Buffer, "le", 8)],
+      program.programId
+    );
+    const now = Math.floor(Date.now() / 1000);
+    const createSeason = (
+      id: anchor.BN,
+      account: PublicKey,
+      endTs: number,
+      payoutBps: number[],
+      previous: PublicKey | null
+    ) =>
+      program.methods
+        .createSeason(id, new anchor.BN(now - 10), new anchor.BN(endTs), payoutBps, 0)
+        .accountsPartial({ marketplace, season: account, previousSeason: previous, admin })
+        .rpc();
+    await createSeason(seasonId, season, now + 8, [7000, 3000], null);
+    // Profiles hold one season's points, so the next season waits for this one to end
+    await expectError(
+      createSeason(seasonId.addn(1), nextSeason, now + 60, [10000], null),
+      /SeasonMismatch/
+    );
+    await expectError(
+      createSeason(seasonId.addn(1), nextSeason, now + 60, [10000], season),
+      /SeasonNotEnded/
+    );
+    await program.methods
+      .fundSeason(new anchor.BN(LAMPORTS_PER_SOL / 20))
+      .accountsPartial({ season, funder: admin })
+      .rpc();
+
+    const id = roomId(8);
+    const room = roomPda(admin, id);
+    const hostNft = await mintFighter(verified, null);
+    const guestNft = await mintFighter(verified, challenger);
+    await createRoom(id, verified.collection, hostNft);
+    await joinRoom(id, verified.collection, guestNft);
+
+    await expectError(
+      program.methods
+        .resolveRoom(admin)
+        .accountsPartial({ ...settleAccounts(room, hostNft, guestNft), referee: admin })
+        .rpc(),
+      /SeasonMismatch/
+    );
+    await program.methods
+      .resolveRoom(admin)
+      .accountsPartial({ ...settleAccounts(room, hostNft, guestNft), season, referee: admin })
+      .rpc();
+    const live = await program.account.season.fetch(season);
+    assert.ok(live.leaderboard[0].wallet.equals(admin));
+    assert.equal(live.leaderboard[0].points.toNumber(), 3);
+
+    await new Promise((resolve) => setTimeout(resolve, 9000));
+    await program.methods.finalizeSeason().accountsPartial({ season }).rpc();
+    await program.methods.claimSeasonPrize(0).accountsPartial({ season, winner: admin }).rpc();
+    await expectError(
+      program.methods.claimSeasonPrize(0).accountsPartial({ season, winner: admin }).rpc(),
+      /PrizeAlreadyClaimed/
+    );
+
+    // A season nobody placed in can be swept to the treasury
+    await expectError(
+      createSeason(seasonId.addn(1), nextSeason, now + 20, [0, 10000], season),
+      /InvalidPayoutTable/
+    );
+    await createSeason(seasonId.addn(1), nextSeason, now + 20, [10000], season);
+    await program.methods
+      .fundSeason(new anchor.BN(LAMPORTS_PER_SOL / 100))
+      .accountsPartial({ season: nextSeason, funder: admin })
+      .rpc();
+    const sweep = (target: PublicKey) =>
+      program.methods
+        .sweepSeason()
+        .accountsPartial({ marketplace, season: target, treasury: admin, admin })
+        .rpc();
+    await expectError(sweep(season), /SeasonHasWinners/);
+    await expectError(sweep(nextSeason), /SeasonNotEnded/);
+    await new Promise((resolve) => setTimeout(resolve, (now + 21) * 1000 - Date.now()));
+    await program.methods.finalizeSeason().accountsPartial({ season: nextSeason }).rpc();
+    await sweep(nextSeason);
+    assert.equal((await program.account.season.fetch(nextSeason)).prizePool.toNumber(), 0);
+  });
+
+  it("runs a two-player tournament from entry to prize claims", async () => {
+    const id = new anchor.BN(stamp);
+    const tournament = indexPda("tournament", admin, id);
+    const deadline = new anchor.BN(Math.floor(Date.now() / 1000) + 3600);
+    await program.methods
+      .createTournament(
+        id,
+        new anchor.BN(LAMPORTS_PER_SOL / 100),
+        2,
+        2,
+        new anchor.BN(3600),
+        [7000, 3000],
+        deadline
+      )
+      .accountsPartial({ marketplace, tournament, organizer: admin, collection: verified.collection })
+      .rpc();
+
+    const nfts = new Map<string, PublicKey>();
+    const enter = async (player: Keypair | null) => {
+      const nftMint = await enterTournament(tournament, player);
+      nfts.set((player ? player.publicKey : admin).toBase58(), nftMint);
+    };
+    await enter(null);
+    await expectError(enter(null), /AlreadyEntered/);
+    await enter(challenger);
+
+    const seeded = await program.account.tournament.fetch(tournament);
+    assert.equal(seeded.status, 1);
+    const [upper, lower] = [seeded.bracket[1], seeded.bracket[2]];
+    const lock = (wallet: PublicKey) => nftLockPda(nfts.get(wallet.toBase58()));
+    const signers = (wallet: PublicKey) => (wallet.equals(admin) ? [] : [challenger]);
+    // Match rooms live under the tournament, so a player cannot take the address first
+    const [room] = PublicKey.findProgramAddressSync(
+      [Buffer.from("bracket_room"), tournament.toBuffer(), Buffer.alloc(8)],
+      program.programId
+    );
+    const openRoom = () =>
+      program.methods
+        .openBracketRoom(0)
+        .accountsPartial({
+          marketplace,
+          tournament,
+          room,
+          creatorNftLock: lock(upper),
+          challengerNftLock: lock(lower),
+          payer: rival.publicKey,
+        })
+        .signers([rival])
+        .rpc();
+    const matchAccounts = {
+      ...settleAccounts(room, nfts.get(upper.toBase58()), nfts.get(lower.toBase58())),
+      creator: upper,
+      challenger: lower,
+      creatorProfile: profilePda(upper),
+      challengerProfile: profilePda(lower),
+      session: null,
+    };
+    const report = (winner: PublicKey, loser: PublicKey) =>
+      program.methods
+        .reportBracketRoom(0)
+        .accountsPartial({
+          tournament,
+          room,
+          payer: rival.publicKey,
+          winner,
+          loser,
+          winnerNftLock: lock(winner),
+          loserNftLock: lock(loser),
+        })
+        .rpc();
+    await openRoom();
+
+    // Both play rock: the draw is replayed rather than handed to the upper slot
+    const salts = [upper, lower].map(() => Keypair.generate().publicKey.toBuffer());
+    for (const [i, player] of [upper, lower].entries()) {
+      await program.methods
+        .commitChoice(commit(0, salts[i], player))
+        .accountsPartial({ room, player, session: null })
+        .signers(signers(player))
+        .rpc();
+    }
+    for (const [i, player] of [upper, lower].entries()) {
+      await program.methods
+        .revealChoice(0, [...salts[i]])
+        .accountsPartial({ ...matchAccounts, player })
+        .signers(signers(player))
+        .rpc();
+    }
+    assert.isNull((await program.account.room.fetch(room)).winner);
+    const rentBefore = await provider.connection.getBalance(rival.publicKey);
+    await report(upper, lower);
+    assert.isAbove(await provider.connection.getBalance(rival.publicKey), rentBefore);
+    const replay = await program.account.tournament.fetch(tournament);
+    assert.ok(replay.rooms[0].equals(PublicKey.default));
+    assert.ok(replay.bracket[0].equals(PublicKey.default));
+    await openRoom();
+
+    await program.methods
+      .forfeitRoom()
+      .accountsPartial({ ...matchAccounts, player: challenger.publicKey })
+      .signers([challenger])
+      .rpc();
+    await report(admin, challenger.publicKey);
+    const finished = await program.account.tournament.fetch(tournament);
+    assert.equal(finished.status, 2);
+    assert.ok(finished.bracket[0].equals(admin));
+    assert.equal(finished.prizePool.toNumber(), LAMPORTS_PER_SOL / 50);
+
+    await program.methods.claimTournamentPrize(0).accountsPartial({ tournament, winner: admin }).rpc();
+    await program.methods
+      .claimTournamentPrize(1)
+      .accountsPartial({ tournament, winner: challenger.publicKey })
+      .signers([challenger])
+      .rpc();
+    await expectError(
+      program.methods.claimTournamentPrize(0).accountsPartial({ tournament, winner: admin }).rpc(),
+      /PrizeAlreadyClaimed/
+    );
+  });
+
+  it("lets anyone call off a tournament that has not filled by its deadline", async () => {
+    const id = new anchor.BN(stamp + 1);
+    const tournament = indexPda("tournament", admin, id);
+    const deadline = Math.floor(Date.now() / 1000) + 3;
+    await program.methods
+      .createTournament(
+        id,
+        new anchor.BN(LAMPORTS_PER_SOL / 100),
+        2,
+        1,
+        new anchor.BN(3600),
+        [10000],
+        new anchor.BN(deadline)
+      )
+      .accountsPartial({ marketplace, tournament, organizer: admin, collection: verified.collection })
+      .rpc();
+
+    const enter = (player: Keypair | null) => enterTournament(tournament, player);
+    const entryNft = await enter(null);
+    const cancel = () =>
+      program.methods
+        .cancelTournament()
+        .accountsPartial({ tournament, organizer: admin, caller: challenger.publicKey })
+        .remainingAccounts(
+          [admin, nftLockPda(entryNft)].map((pubkey) => ({ pubkey, isSigner: false, isWritable: true }))
+        )
+        .signers([challenger])
+        .rpc();
+    await expectError(cancel(), /Unauthorized/);
+
+    await new Promise((resolve) => setTimeout(resolve, (deadline + 1) * 1000 - Date.now()));
+    await expectError(enter(challenger), /TournamentNotOpen/);
+    await cancel();
+    assert.isNull(await program.account.tournament.fetchNullable(tournament));
+    assert.isNull(await program.account.nftLock.fetchNullable(nftLockPda(entryNft)));
+  });
+});
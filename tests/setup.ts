@@ -0,0 +1,23 @@
+import * as anchor from "@coral-xyz/anchor";
+import { PublicKey } from "@solana/web3.js";
+
+// Programs Anchor.toml loads from tests/fixtures; the binaries are not checked in
+const FIXTURES = [
+  ["Token Metadata", "metaqbxxUerdq28cj1RbAWkYQm3ybzjb6a8bt518x1s"],
+  ["Token Auth Rules", "auth9SigNpDKz4sJJ1DfCTuZrZNSAgh9sFD3rboVmgg"],
+];
+
+// Root hook: fail once, up front, when the local validator is missing a fixture program
+before(async () => {
+  const connection = anchor.AnchorProvider.env().connection;
+  for (const [name, address] of FIXTURES) {
+    const account = await connection.getAccountInfo(new PublicKey(address));
+    if (!account?.executable) {
+      throw new Error(
+        `${name} program ${address} is not deployed on the test validator. ` +
+          "Run `yarn fixtures` to dump it into tests/fixtures/ (needs the Solana CLI and " +
+          "mainnet access), then rerun `anchor test`."
+      );
+    }
+  }
+});